@@ -0,0 +1,76 @@
+#[cfg(not(feature = "binary"))]
+compile_error!("Requires feature: 'binary'");
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use log::info;
+use rustymon_world::diff::diff;
+use rustymon_world::features::{self, FeatureParser};
+use rustymon_world::formats::envelope::Envelope;
+use rustymon_world::formats::Format;
+use rustymon_world::projection::WebMercator;
+use rustymon_world::world::World;
+use rustymon_world::Error;
+
+type Feature = <features::prototyping::Parser as FeatureParser>::Feature;
+
+/// Compare two outputs of rustymon_world and print the changes as JSON
+///
+/// Tiles are matched by their position and items by their kind and oid.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// The older output
+    old: PathBuf,
+
+    /// The newer output
+    new: PathBuf,
+
+    /// Data format of both outputs
+    #[clap(value_enum, short, long, default_value_t = Default::default())]
+    format: Format,
+
+    /// Only print the counts per feature instead of the changes per tile
+    #[clap(long)]
+    summary: bool,
+}
+
+fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    let Args {
+        old,
+        new,
+        format,
+        summary,
+    } = Args::parse();
+
+    let read = |path: PathBuf| -> Result<Envelope<World<Feature, WebMercator>>, Error> {
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let envelope: Envelope<World<Feature, WebMercator>> = format.read(reader)?;
+        envelope.check()?;
+        envelope.check_payload()?;
+        Ok(envelope)
+    };
+    let old = read(old)?;
+    let new = read(new)?;
+    if old.parameters.zoom != new.parameters.zoom
+        || old.parameters.projection != new.parameters.projection
+    {
+        return Err(Error::InvalidConfig(
+            "The outputs use different zoom levels or projections".to_string(),
+        ));
+    }
+
+    let diff = diff(&old.tiles.tiles, &new.tiles.tiles, new.parameters.zoom)?;
+    info!("{} tiles changed", diff.tiles.len());
+
+    let stdout = std::io::stdout();
+    if summary {
+        serde_json::to_writer_pretty(stdout, &diff.summary)
+    } else {
+        serde_json::to_writer_pretty(stdout, &diff)
+    }
+    .map_err(Error::serialization)
+}
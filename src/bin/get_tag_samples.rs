@@ -7,6 +7,7 @@ use std::io::stdout;
 use libosmium::handler::{AreaAssemblerConfig, Handler};
 use libosmium::tag_list::OwnedTagList;
 use libosmium::{Area, Node, Way};
+use rustymon_world::Error;
 use serde::Serialize;
 
 #[derive(Serialize, Default)]
@@ -37,13 +38,14 @@ impl Handler for Samples {
     }
 }
 
-fn main() -> Result<(), String> {
+fn main() -> Result<(), Error> {
     let mut args = env::args().skip(1);
     let file = args
         .next()
-        .ok_or_else(|| "expected a file as argument".to_string())?;
+        .ok_or_else(|| Error::InvalidConfig("expected a file as argument".to_string()))?;
     let size = if let Some(size) = args.next() {
-        size.parse::<usize>().map_err(|err| err.to_string())?
+        size.parse::<usize>()
+            .map_err(|err| Error::InvalidConfig(format!("invalid sample size: {err}")))?
     } else {
         100
     };
@@ -59,9 +61,9 @@ fn main() -> Result<(), String> {
                 ..Default::default()
             },
         )
-        .map_err(|err| err.to_string_lossy().to_string())?;
+        .map_err(|err| Error::Pbf(err.to_string_lossy().into_owned()))?;
 
-    rmp_serde::encode::write(&mut stdout(), &samples).map_err(|err| err.to_string())?;
+    rmp_serde::encode::write(&mut stdout(), &samples).map_err(Error::serialization)?;
 
     Ok(())
 }
@@ -1,33 +1,64 @@
 #[cfg(not(feature = "binary"))]
 compile_error!("Requires feature: 'binary'");
 
-use clap::{Parser, ValueEnum};
-use rustymon_world::{features, parse, Config};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-#[derive(ValueEnum, Debug, Copy, Clone, Default)]
-pub enum Format {
-    #[default]
-    Json,
+use clap::Parser;
+use log::warn;
+use rustymon_world::buffered::{CAPACITY, DEPTH};
+use rustymon_world::features::prototyping::ExclusionConfig;
+use rustymon_world::features::FeatureParser;
+use rustymon_world::formats::archive::{ArchiveWriter, Compression, Metadata, TileKey};
+use rustymon_world::formats::binary;
+use rustymon_world::formats::envelope::Envelope;
+use rustymon_world::formats::mvt::{self, Value};
+use rustymon_world::formats::quantized::{QuantizedWorld, EXTENT, MAX_EXTENT};
+use rustymon_world::formats::Format;
+use rustymon_world::metadata::{self, Excluded, MetadataFilter};
+use rustymon_world::progress::{CancellationToken, Monitor, Observer, Progress};
+use rustymon_world::projection::{Projection, WebMercator};
+use rustymon_world::source::Source;
+use rustymon_world::{features, parse, AreaOptions, Config, Error};
 
-    #[cfg(feature = "message-pack")]
-    MessagePack,
+/// Prints the progress to stderr and collects the objects excluded by their metadata
+#[derive(Default)]
+struct Report {
+    print_progress: bool,
+    collect_excluded: bool,
+    excluded: Mutex<Vec<Excluded>>,
 }
-impl Format {
-    pub fn write(
-        &self,
-        mut writer: impl std::io::Write,
-        data: &impl serde::Serialize,
-    ) -> Result<(), String> {
-        match self {
-            Format::Json => serde_json::to_writer(writer, data).map_err(|error| error.to_string()),
-            #[cfg(feature = "message-pack")]
-            Format::MessagePack => {
-                rmp_serde::encode::write(&mut writer, data).map_err(|error| error.to_string())
-            }
+impl Observer for Report {
+    fn progress(&self, progress: &Progress) {
+        if !self.print_progress {
+            return;
+        }
+        eprintln!(
+            "[{:?}] {} nodes, {} ways, {} areas, {} skipped, {} excluded, {} MiB read, {} items generated",
+            progress.elapsed,
+            progress.nodes,
+            progress.ways,
+            progress.areas,
+            progress.skipped,
+            progress.excluded,
+            progress.bytes >> 20,
+            progress.items,
+        );
+    }
+
+    fn excluded(&self, excluded: &Excluded) {
+        if self.collect_excluded {
+            self.excluded.lock().unwrap().push(*excluded);
         }
     }
 }
 
+fn parse_cutoff(date: &str) -> Result<u32, String> {
+    metadata::parse_date_end(date)
+        .ok_or_else(|| format!("Expected a date as YYYY-MM-DD, got {date}"))
+}
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
@@ -56,12 +87,94 @@ struct Args {
     #[clap(value_enum, short, long, default_value_t = Default::default())]
     format: Format,
 
+    /// Encode points as tile-local integers using this many cells per axis (e.g. 4096)
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=MAX_EXTENT as i64))]
+    quantize: Option<u32>,
+
+    /// Add each tile's bounds as extra polygon to the GeoJSON output (`--format geojson`)
+    #[clap(long, conflicts_with_all = ["mvt", "archive"])]
+    debug_bounds: bool,
+
+    /// Skip assembling areas, e.g. when only nodes are of interest
+    #[clap(long)]
+    no_areas: bool,
+
+    /// Let the area assembler check the multipolygon members' roles
+    #[clap(long)]
+    check_roles: bool,
+
+    /// Let the area assembler create areas from relations whose rings couldn't be built
+    #[clap(long)]
+    empty_areas: bool,
+
+    /// Let the area assembler ignore invalid node locations instead of dropping the area
+    #[clap(long)]
+    ignore_invalid_locations: bool,
+
+    /// Sort each tile's items by their oid to get reproducible output
+    #[clap(long)]
+    canonical_order: bool,
+
+    /// Include each tile's spatial index for point queries
+    #[clap(long)]
+    spatial_index: bool,
+
+    /// Write the tiles as Mapbox Vector Tiles into `<DIR>/<zoom>/<x>/<y>.mvt` instead of stdout
+    #[clap(long, value_name = "DIR")]
+    mvt: Option<PathBuf>,
+
+    /// Write the tiles into a single archive file instead of stdout
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["format", "quantize", "mvt"])]
+    archive: Option<PathBuf>,
+
+    /// Compress each tile in the archive
+    #[clap(long, requires = "archive")]
+    compress: bool,
+
+    /// Write the config's names for the feature ids as JSON into this file
+    #[clap(long, value_name = "FILE")]
+    dictionary: Option<PathBuf>,
+
     /// Config for assigning visual types
     #[clap(long)]
     visual: String,
+
+    /// Exclusion zones and the rules for suppressing items inside them as JSON,
+    /// see `prototyping::ExclusionConfig`
+    #[clap(long, value_name = "FILE")]
+    exclusions: Option<PathBuf>,
+
+    /// Number of worker threads [default: number of cores]
+    #[clap(short, long)]
+    workers: Option<usize>,
+
+    /// Bytes size of the buffers sent to the workers
+    #[clap(long, default_value_t = CAPACITY)]
+    buffer_size: usize,
+
+    /// Number of buffers which can be queued for a single worker
+    #[clap(long, default_value_t = DEPTH)]
+    channel_depth: usize,
+
+    /// Print the progress to stderr every given number of seconds
+    #[clap(long)]
+    progress: Option<u64>,
+
+    /// Exclude objects created or modified after the end of this date (YYYY-MM-DD) in UTC
+    #[clap(long, value_parser = parse_cutoff)]
+    cutoff: Option<u32>,
+
+    /// Exclude objects whose latest edit is younger than this number of days,
+    /// the output can't be updated by update_world
+    #[clap(long, value_name = "DAYS")]
+    quarantine_days: Option<u32>,
+
+    /// Write the objects excluded by their metadata as JSON into this file
+    #[clap(long, value_name = "FILE")]
+    excluded: Option<PathBuf>,
 }
 
-fn main() -> Result<(), String> {
+fn main() -> Result<(), Error> {
     env_logger::init();
 
     let Args {
@@ -72,29 +185,94 @@ fn main() -> Result<(), String> {
         center_x,
         center_y,
         visual,
+        exclusions,
         format,
+        quantize,
+        debug_bounds,
+        no_areas,
+        check_roles,
+        empty_areas,
+        ignore_invalid_locations,
+        canonical_order,
+        spatial_index,
+        mvt,
+        archive,
+        compress,
+        dictionary: dictionary_file,
+        workers,
+        buffer_size,
+        channel_depth,
+        progress,
+        cutoff,
+        quarantine_days,
+        excluded: excluded_file,
     } = Args::parse();
+    if debug_bounds && format != Format::GeoJson {
+        return Err(Error::InvalidConfig(
+            "--debug-bounds requires --format geojson".to_string(),
+        ));
+    }
 
     /* "Production prototype"
     let visual_config = if let Some(visual) = visual {
-        std::fs::read_to_string(visual).map_err(|err| err.to_string())?
+        std::fs::read_to_string(visual)?
     } else {
         include_str!("sample.config").to_string()
     };
 
     #[cfg(feature = "yada")]
-    let visual: features::yada::YadaParser = features::yada::YadaParser::from_file(&visual_config)
-        .ok_or_else(|| "Couldn't read config")?;
+    let visual: features::yada::YadaParser = features::yada::YadaParser::from_file(&visual_config)?;
 
     #[cfg(not(feature = "yada"))]
     let visual = features::config::ConfigParser::borrowing()
-        .parse_file(&visual_config)
-        .map_err(|err| format!("{err:?}"))?;
+        .parse_file(&visual_config)?;
     */
 
-    let visual = std::fs::read_to_string(visual).map_err(|err| err.to_string())?;
-    let visual = features::prototyping::Parser::from_file(&visual)
-        .ok_or_else(|| "Couldn't create visual parser from config".to_string())?;
+    let visual_config = std::fs::read_to_string(visual)?;
+    let visual = features::prototyping::Parser::from_file(&visual_config)?;
+    let (visual, exclusions) = match exclusions {
+        Some(path) => {
+            let config = ExclusionConfig::from_file(&std::fs::read_to_string(path)?)?;
+            let (zones, exclusions) = config.into_parts();
+            (visual.with_exclusions(zones)?, exclusions)
+        }
+        None => (visual, Default::default()),
+    };
+
+    let areas = (!no_areas).then_some(AreaOptions {
+        check_roles,
+        create_empty_areas: empty_areas,
+        ignore_invalid_locations,
+        ..Default::default()
+    });
+    let metadata_filter =
+        (cutoff.is_some() || quarantine_days.is_some()).then_some(MetadataFilter {
+            cutoff,
+            quarantine_days,
+            now: None,
+        });
+
+    // Stop processing on the first Ctrl-C, libosmium can only be interrupted by a second one
+    let token = CancellationToken::new();
+    {
+        let token = token.clone();
+        ctrlc::set_handler(move || {
+            if token.is_cancelled() {
+                std::process::exit(130);
+            }
+            eprintln!("Cancelling, press Ctrl-C again to exit immediately");
+            token.cancel();
+        })
+        .map_err(|error| {
+            Error::InvalidConfig(format!("Couldn't set the Ctrl-C handler: {error}"))
+        })?;
+    }
+
+    let report = Arc::new(Report {
+        print_progress: progress.is_some(),
+        collect_excluded: excluded_file.is_some(),
+        ..Default::default()
+    });
 
     let config = Config {
         file,
@@ -104,11 +282,88 @@ fn main() -> Result<(), String> {
         center_y,
         zoom,
         visual,
+        projection: WebMercator,
+        workers,
+        buffer_size,
+        channel_depth,
+        area_rule: Default::default(),
+        simplification: Default::default(),
+        exclusions,
+        areas,
+        only_tiles: None,
+        canonical_order,
+        spatial_index,
+        metadata_filter,
+        monitor: Monitor::new(
+            (progress.is_some() || excluded_file.is_some()).then(|| report.clone() as _),
+            token,
+            Duration::from_secs(progress.unwrap_or(1)),
+        ),
     };
 
-    let tiles = parse(config).map_err(|err| err.to_string())?;
+    let config_hash = config.config_hash()?;
+    let mut parameters = config.parameters();
+    parameters.config_hash = Some(config_hash);
+    let source = Source::from_pbf(&config.file)
+        .inspect_err(|error| warn!("Couldn't read the input's header: {error}"))
+        .ok();
+    let dictionary = config.visual.dictionary();
+    if let Some(path) = dictionary_file {
+        let Some(dictionary) = dictionary.as_ref() else {
+            return Err(Error::InvalidConfig(
+                "The visual config doesn't define any names".to_string(),
+            ));
+        };
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer_pretty(file, dictionary).map_err(Error::serialization)?;
+    }
+    let world = parse(config)?;
+
+    if let Some(path) = excluded_file {
+        let excluded = report.excluded.lock().unwrap();
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer_pretty(file, &*excluded).map_err(Error::serialization)?;
+    }
+
+    if let Some(path) = archive {
+        let compression = if compress {
+            Compression::Deflate
+        } else {
+            Compression::None
+        };
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut writer = ArchiveWriter::new(file, compression)?;
+        for tile in world.tiles.iter() {
+            let encoded =
+                binary::try_encode(tile, serde_json::to_vec).map_err(Error::serialization)?;
+            writer.add(TileKey::of(tile, zoom)?, &encoded)?;
+        }
+        writer.finish(&Metadata {
+            config_hash,
+            zoom,
+            projection: world.projection.name().to_string(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        })?;
+    } else if let Some(directory) = mvt {
+        for tile in world.tiles.iter() {
+            let TileKey { x, y } = TileKey::of(tile, zoom)?;
+            let directory = directory.join(zoom.to_string()).join(x.to_string());
+            std::fs::create_dir_all(&directory)?;
 
-    format.write(std::io::stdout(), &tiles)?;
+            let encoded = mvt::try_encode(tile, quantize.unwrap_or(EXTENT), |feature| {
+                serde_json::to_string(feature).map(Value::from)
+            })
+            .map_err(Error::serialization)?;
+            std::fs::write(directory.join(format!("{y}.mvt")), encoded)?;
+        }
+    } else if let Some(extent) = quantize {
+        let world = QuantizedWorld::new(world, extent);
+        let envelope = Envelope::new(parameters, source, dictionary, world);
+        format.write_geojson(std::io::stdout(), &envelope, debug_bounds)?;
+    } else {
+        let envelope = Envelope::new(parameters, source, dictionary, world);
+        format.write_geojson(std::io::stdout(), &envelope, debug_bounds)?;
+    }
 
     Ok(())
 }
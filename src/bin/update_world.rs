@@ -0,0 +1,215 @@
+#[cfg(not(feature = "binary"))]
+compile_error!("Requires feature: 'binary'");
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use log::{info, warn};
+use rustymon_world::features::prototyping::ExclusionConfig;
+use rustymon_world::features::{self, FeatureParser};
+use rustymon_world::formats::archive::TileKey;
+use rustymon_world::formats::envelope::Envelope;
+use rustymon_world::formats::Format;
+use rustymon_world::metadata::{self, MetadataFilter};
+use rustymon_world::projection::{Projection, WebMercator};
+use rustymon_world::source::Source;
+use rustymon_world::update::{update, Changes, Update};
+use rustymon_world::world::World;
+use rustymon_world::{AreaOptions, Config, Error};
+
+type Feature = <features::prototyping::Parser as FeatureParser>::Feature;
+
+fn parse_cutoff(date: &str) -> Result<u32, String> {
+    metadata::parse_date_end(date)
+        .ok_or_else(|| format!("Expected a date as YYYY-MM-DD, got {date}"))
+}
+
+/// Regenerate the tiles of a previous output which are affected by an osm change file
+///
+/// The grid is taken from the previous output, the updated tiles are written to stdout.
+/// All other settings have to be the same as for the previous run, which is checked using its config hash.
+/// Outputs generated with `--quarantine-days` can't be updated,
+/// because the objects the quarantine excluded from the unchanged tiles would never be released.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Output of a previous run of rustymon_world
+    previous: PathBuf,
+
+    /// Osm change file (.osc or .osc.gz) describing the changes since the previous run
+    changes: String,
+
+    /// PBF file with the changes already applied
+    file: String,
+
+    /// Config for assigning visual types, has to be the one used for the previous run
+    #[clap(long)]
+    visual: String,
+
+    /// Exclusion zones and the rules for suppressing items inside them as JSON,
+    /// has to be the one used for the previous run
+    #[clap(long, value_name = "FILE")]
+    exclusions: Option<PathBuf>,
+
+    /// Skip assembling areas, has to match the previous run
+    #[clap(long)]
+    no_areas: bool,
+
+    /// Let the area assembler check the multipolygon members' roles, has to match the previous run
+    #[clap(long)]
+    check_roles: bool,
+
+    /// Let the area assembler create areas from relations whose rings couldn't be built,
+    /// has to match the previous run
+    #[clap(long)]
+    empty_areas: bool,
+
+    /// Let the area assembler ignore invalid node locations, has to match the previous run
+    #[clap(long)]
+    ignore_invalid_locations: bool,
+
+    /// Data format of the previous output and of stdout
+    #[clap(value_enum, short, long, default_value_t = Default::default())]
+    format: Format,
+
+    /// Write the keys of the regenerated tiles as JSON into this file
+    #[clap(long, value_name = "FILE")]
+    changed: Option<PathBuf>,
+
+    /// Sort each tile's items by their oid, has to match the previous run
+    #[clap(long)]
+    canonical_order: bool,
+
+    /// Include each tile's spatial index, has to match the previous run
+    #[clap(long)]
+    spatial_index: bool,
+
+    /// Exclude objects created or modified after the end of this date (YYYY-MM-DD) in UTC, has to match the previous run
+    #[clap(long, value_parser = parse_cutoff)]
+    cutoff: Option<u32>,
+
+    /// Number of worker threads [default: number of cores]
+    #[clap(short, long)]
+    workers: Option<usize>,
+}
+
+fn main() -> Result<(), Error> {
+    env_logger::init();
+
+    let Args {
+        previous,
+        changes,
+        file,
+        visual,
+        exclusions,
+        no_areas,
+        check_roles,
+        empty_areas,
+        ignore_invalid_locations,
+        format,
+        changed: changed_file,
+        canonical_order,
+        spatial_index,
+        cutoff,
+        workers,
+    } = Args::parse();
+
+    let reader = std::io::BufReader::new(std::fs::File::open(previous)?);
+    let previous: Envelope<World<Feature, WebMercator>> = format.read(reader)?;
+    previous.check()?;
+    previous.check_payload()?;
+    let Envelope {
+        mut parameters,
+        tiles: previous,
+        ..
+    } = previous;
+    if parameters.projection != WebMercator.name() {
+        return Err(Error::InvalidConfig(format!(
+            "The previous output uses the projection {}",
+            parameters.projection
+        )));
+    }
+
+    let changes = Changes::from_osc(&changes)?;
+    info!(
+        "Read {} nodes, {} ways and {} relations from the change file",
+        changes.nodes.len(),
+        changes.ways.len(),
+        changes.relations.len()
+    );
+
+    let visual_config = std::fs::read_to_string(visual)?;
+    let visual = features::prototyping::Parser::from_file(&visual_config)?;
+    let (visual, exclusions) = match exclusions {
+        Some(path) => {
+            let config = ExclusionConfig::from_file(&std::fs::read_to_string(path)?)?;
+            let (zones, exclusions) = config.into_parts();
+            (visual.with_exclusions(zones)?, exclusions)
+        }
+        None => (visual, Default::default()),
+    };
+
+    parameters.file = file;
+    let config = Config {
+        file: parameters.file.clone(),
+        cols: parameters.cols,
+        rows: parameters.rows,
+        center_x: parameters.center_x,
+        center_y: parameters.center_y,
+        zoom: parameters.zoom,
+        visual,
+        projection: WebMercator,
+        workers,
+        buffer_size: rustymon_world::buffered::CAPACITY,
+        channel_depth: rustymon_world::buffered::DEPTH,
+        area_rule: Default::default(),
+        simplification: Default::default(),
+        exclusions,
+        areas: (!no_areas).then_some(AreaOptions {
+            check_roles,
+            create_empty_areas: empty_areas,
+            ignore_invalid_locations,
+            ..Default::default()
+        }),
+        only_tiles: None,
+        canonical_order,
+        spatial_index,
+        metadata_filter: cutoff.map(|cutoff| MetadataFilter {
+            cutoff: Some(cutoff),
+            ..Default::default()
+        }),
+        monitor: Default::default(),
+    };
+
+    // The unchanged tiles are reused, so they have to be generated the same way
+    let config_hash = config.config_hash()?;
+    match parameters.config_hash {
+        Some(previous) if previous != config_hash => {
+            return Err(Error::InvalidConfig(
+                "The settings differ from the previous run's".to_string(),
+            ));
+        }
+        Some(_) => {}
+        None => warn!("The previous output doesn't record its settings, they can't be verified"),
+    }
+    parameters.config_hash = Some(config_hash);
+    let source = Source::from_pbf(&config.file)
+        .inspect_err(|error| warn!("Couldn't read the input's header: {error}"))
+        .ok();
+    let dictionary = config.visual.dictionary();
+
+    let Update { world, changed } = update(config, previous, &changes)?;
+    info!("Regenerated {} tiles", changed.len());
+
+    if let Some(path) = changed_file {
+        let keys = changed
+            .iter()
+            .map(|&index| TileKey::of(&world.tiles[index], parameters.zoom))
+            .collect::<Result<Vec<_>, _>>()?;
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer(file, &keys).map_err(Error::serialization)?;
+    }
+
+    let envelope = Envelope::new(parameters, source, dictionary, world);
+    format.write(std::io::stdout(), &envelope)
+}
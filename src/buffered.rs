@@ -1,63 +1,139 @@
-use std::{panic, thread};
+use std::any::Any;
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crossbeam_channel::{unbounded, Receiver, SendError, Sender};
+use crossbeam_channel::{bounded, SendError, Sender, TryRecvError, TrySendError};
 use libosmium::{Area, Handler, Item, ItemBuffer, ItemRef, Node, Way};
-use log::{debug, error};
+use log::{debug, error, info};
 
+use crate::error::Error;
 use crate::features::FeatureParser;
-use crate::formats::Tile;
+use crate::formats::{Kind, Tile};
 use crate::generator::WorldGenerator;
+use crate::geometry::Point;
+use crate::progress::Monitor;
 use crate::projection::Projection;
 
-/// Bytes size of buffer
+/// Default bytes size of buffer
 /// - bigger consumes more memory
 /// - lower produces more synchronization overhead
 pub const CAPACITY: usize = 2 << 20;
 
+/// Default number of buffers which can be queued for a single worker
+/// - bigger consumes more memory
+/// - lower blocks the reader more often
+pub const DEPTH: usize = 4;
+
+/// Splits the grid into horizontal bands of rows and generates each band in its own thread.
+///
+/// Each item is routed only to the workers whose band its bounding box touches.
+/// Since every tile is owned by exactly one worker, the workers' tiles don't have to be merged.
 pub struct MultithreadedGenerator<P: Projection, V: FeatureParser> {
+    /// "Empty" world generator to split into bands for the threads
+    generator: WorldGenerator<P, V>,
+
+    /// The workers ordered by their bands
+    workers: Vec<Worker<V::Feature>>,
+
+    /// Bytes size of the buffers sent to the workers
+    capacity: usize,
+
+    /// Number of buffers which can be queued for a single worker before the producer blocks
+    depth: usize,
+
+    /// The first error which occurred while handling items
+    ///
+    /// Once set, all further items are ignored.
+    error: Option<Error>,
+}
+
+/// A worker thread and the producer's state used to feed it
+struct Worker<Feature> {
+    /// The band of rows this worker owns
+    rows: Range<usize>,
+
+    /// Buffer collecting the items for this worker
     buffer: ItemBuffer,
     sender: Sender<ItemBuffer>,
 
-    /// "Empty" world generator to clone for the threads
-    generator: WorldGenerator<P, V>,
-    /// Receiver part of channel to clone for the threads
-    receiver: Receiver<ItemBuffer>,
+    /// Time the producer was blocked because this worker's channel was full
+    blocked: Starvation,
+
+    /// Join handle for the worker thread
+    handle: thread::JoinHandle<(Vec<Tile<Feature>>, Starvation)>,
+}
+
+/// Measures how often and how long a thread had to wait on a channel
+#[derive(Default, Copy, Clone, Debug)]
+pub struct Starvation {
+    /// Number of times the thread had to wait
+    pub times: u32,
 
-    /// Join handles for the worker threads
-    handles: Vec<thread::JoinHandle<Vec<Tile<V::Feature>>>>,
+    /// Total time spent waiting
+    pub duration: Duration,
+}
+impl Starvation {
+    /// Add a single wait which started at `start`
+    fn add(&mut self, start: Instant) {
+        self.times += 1;
+        self.duration += start.elapsed();
+    }
 }
 
 impl<P: Projection, V: FeatureParser> MultithreadedGenerator<P, V>
 where
     V: Clone + Send + 'static,
-    V::Feature: Clone + Send + 'static,
+    V::Feature: Clone + PartialEq + Send + 'static,
 {
     /// Wrap a [WorldGenerator] to be multithreaded
-    pub fn new(generator: WorldGenerator<P, V>) -> Self {
-        let (sender, receiver) = unbounded();
+    ///
+    /// - `capacity` is the bytes size of the buffers sent to the workers (see [CAPACITY])
+    /// - `depth` is the number of buffers which can be queued for a single worker (see [DEPTH])
+    pub fn new(generator: WorldGenerator<P, V>, capacity: usize, depth: usize) -> Self {
         Self {
-            buffer: ItemBuffer::with_capacity(CAPACITY),
-            sender,
-
             generator,
-            receiver,
-
-            handles: Vec::new(),
+            workers: Vec::new(),
+            capacity,
+            depth: depth.max(1),
+            error: None,
         }
     }
 
     /// Spawn worker threads
+    ///
+    /// There won't be more workers than the grid has rows.
     pub fn spawn_workers(&mut self, worker: usize) {
+        let num_rows = self.generator.grid.size().y;
+        let worker = worker.min(num_rows);
         for i in 0..worker {
-            let mut generator = self.generator.clone();
-            let receiver = self.receiver.clone();
+            let rows = i * num_rows / worker..(i + 1) * num_rows / worker;
+            let mut generator = self.generator.band(rows.clone());
+            let (sender, receiver) = bounded::<ItemBuffer>(self.depth);
             let handle = thread::spawn(move || {
-                while let Ok(buffer) = receiver.recv() {
+                let mut starved = Starvation::default();
+                loop {
+                    let buffer = match receiver.try_recv() {
+                        Ok(buffer) => buffer,
+                        Err(TryRecvError::Disconnected) => break,
+                        Err(TryRecvError::Empty) => {
+                            let start = Instant::now();
+                            let buffer = receiver.recv();
+                            starved.add(start);
+                            match buffer {
+                                Ok(buffer) => buffer,
+                                Err(_) => break,
+                            }
+                        }
+                    };
                     debug!(
                         "Worker {} received ItemBuffer: {} remaining",
                         i,
                         receiver.len()
                     );
+                    if generator.monitor.is_cancelled() {
+                        continue;
+                    }
                     for item in buffer.iter() {
                         match item.cast() {
                             Some(ItemRef::Area(area)) => generator.area(area),
@@ -72,82 +148,213 @@ where
                         }
                     }
                 }
-                generator.into_tiles()
+                (generator.into_tiles(), starved)
+            });
+            debug!("Spawned a worker {} for the rows {:?}", i, rows);
+            self.workers.push(Worker {
+                rows,
+                buffer: ItemBuffer::with_capacity(self.capacity),
+                sender,
+                blocked: Starvation::default(),
+                handle,
             });
-            self.handles.push(handle);
-            debug!("Spawned a worker {}", i);
         }
     }
 
-    /// Handle any osm item by populating the buffer.
-    pub fn handle(&mut self, item: &impl AsRef<Item>) -> Result<(), SendError<ItemBuffer>> {
-        if self.buffer.fits(item) || self.buffer.is_empty() {
-            self.buffer.push(item);
-        } else {
-            self.sender.send(std::mem::replace(
-                &mut self.buffer,
-                ItemBuffer::with_capacity(CAPACITY),
-            ))?;
-            debug!(
-                "Send ItemBuffer to workers: {} in channel",
-                self.sender.len()
-            );
+    /// Get the range of rows an item's points touch
+    fn row_range(&self, points: impl IntoIterator<Item = Point>) -> Option<Range<usize>> {
+        self.generator.grid.row_range(points)
+    }
+
+    /// Handle any osm item by populating the buffers of all workers whose band intersects `rows`.
+    pub fn handle(&mut self, item: &impl AsRef<Item>, rows: Range<usize>) -> Result<(), Error> {
+        for (i, worker) in self.workers.iter_mut().enumerate() {
+            if worker.rows.start < rows.end && rows.start < worker.rows.end {
+                worker
+                    .handle(item, self.capacity)
+                    .map_err(|_| Error::WorkerDisconnected { worker: i })?;
+            }
         }
         Ok(())
     }
 
+    /// Count an item read from the input and check whether it should be handled
+    ///
+    /// Handled items are counted here using `count`, since the bands don't count them.
+    fn read(&self, item: &impl AsRef<Item>, count: fn(&Monitor)) -> bool {
+        let monitor = &self.generator.monitor;
+        monitor.add_bytes(item.as_ref().byte_size() as u64);
+        monitor.report();
+        if self.error.is_some() || monitor.is_cancelled() {
+            return false;
+        }
+        count(monitor);
+        true
+    }
+
+    /// Handle an item unless an error occurred previously, in which case it is stored.
+    fn try_handle(&mut self, item: &impl AsRef<Item>, rows: Range<usize>) {
+        if self.error.is_none() {
+            if let Err(error) = self.handle(item, rows) {
+                error!("Stopped handling items: {error}");
+                self.error = Some(error);
+            }
+        }
+    }
+
     /// Join all workers and collect their tiles
-    pub fn into_tiles(mut self) -> Vec<Tile<V::Feature>> {
-        drop(self.sender);
-        for handle in self.handles {
-            let tiles = match handle.join() {
-                Ok(tiles) => tiles,
-                Err(error) => panic::resume_unwind(error),
+    ///
+    /// All workers are joined even if some of them failed.
+    /// The tiles' items are in the order they were read,
+    /// use [Tile::canonicalize] to make them independent of the reader.
+    /// A panicked worker is reported in favour of the error it caused while handling items.
+    pub fn into_tiles(self) -> Result<Vec<Tile<V::Feature>>, Error> {
+        if self.workers.is_empty() {
+            let monitor = self.generator.monitor.clone();
+            let tiles = self.generator.into_tiles();
+            monitor.add_items(&tiles);
+            return Ok(tiles);
+        }
+
+        let mut panicked = None;
+        let mut tiles = Vec::with_capacity(self.generator.tiles.len());
+        for (i, worker) in self.workers.into_iter().enumerate() {
+            let Worker {
+                buffer,
+                sender,
+                blocked,
+                handle,
+                ..
+            } = worker;
+
+            // Send the last partially filled buffer and close the channel
+            if !buffer.is_empty() && sender.send(buffer).is_err() {
+                error!("Couldn't send the last ItemBuffer to a worker");
+            }
+            drop(sender);
+
+            let (band, starved) = match handle.join() {
+                Ok(result) => result,
+                Err(payload) => {
+                    let error = Error::WorkerPanicked {
+                        worker: i,
+                        message: panic_message(payload.as_ref()),
+                    };
+                    error!("{error}");
+                    panicked.get_or_insert(error);
+                    continue;
+                }
             };
-            for (i, mut from) in tiles.into_iter().enumerate() {
-                if let Some(to) = self.generator.tiles.get_mut(i) {
-                    let offset = to.points.len();
-                    to.points.extend(from.points.into_iter());
-
-                    from.areas.iter_mut().for_each(|area| {
-                        area.points.0 += offset;
-                        area.points.1 += offset;
-                    });
-                    to.areas.extend(from.areas.into_iter());
-
-                    from.nodes.iter_mut().for_each(|node| {
-                        node.points += offset;
-                    });
-                    to.nodes.extend(from.nodes.into_iter());
-
-                    from.ways.iter_mut().for_each(|way| {
-                        way.points.0 += offset;
-                        way.points.1 += offset;
-                    });
-                    to.ways.extend(from.ways.into_iter());
-                } else {
-                    error!("A worker contains tiles the base doesn't!");
+            self.generator.monitor.add_items(&band);
+            tiles.extend(band);
+
+            info!(
+                "Worker {}: waited {} times for {:?} on the producer, which waited {} times for {:?} on the worker",
+                i, starved.times, starved.duration, blocked.times, blocked.duration
+            );
+        }
+
+        match panicked.or(self.error) {
+            Some(error) => Err(error),
+            None => Ok(tiles),
+        }
+    }
+}
+
+/// Extract the message from a panic's payload
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+impl<Feature> Worker<Feature> {
+    /// Push an item into the buffer and send it to the worker once it is full.
+    fn handle(
+        &mut self,
+        item: &impl AsRef<Item>,
+        capacity: usize,
+    ) -> Result<(), SendError<ItemBuffer>> {
+        if !self.buffer.fits(item) && !self.buffer.is_empty() {
+            let buffer = std::mem::replace(&mut self.buffer, ItemBuffer::with_capacity(capacity));
+            match self.sender.try_send(buffer) {
+                Ok(()) => {}
+                Err(TrySendError::Disconnected(buffer)) => return Err(SendError(buffer)),
+                Err(TrySendError::Full(buffer)) => {
+                    let start = Instant::now();
+                    let result = self.sender.send(buffer);
+                    self.blocked.add(start);
+                    result?;
                 }
             }
+            debug!(
+                "Send ItemBuffer to worker: {} in channel",
+                self.sender.len()
+            );
         }
-        self.generator.tiles
+        self.buffer.push(item);
+        Ok(())
     }
 }
 
 impl<P: Projection, V: FeatureParser> Handler for MultithreadedGenerator<P, V>
 where
     V: Clone + Send + 'static,
-    V::Feature: Clone + Send + 'static,
+    V::Feature: Clone + PartialEq + Send + 'static,
 {
     fn area(&mut self, area: &Area) {
-        self.handle(area).unwrap();
+        if !self.read(area, Monitor::add_area)
+            || self
+                .generator
+                .skip(area.outer_rings().flat_map(|ring| ring.iter()))
+            || self.generator.oid(area.id()).is_none()
+            || self.generator.exclude(Kind::Area, area)
+        {
+            return;
+        }
+        let projection = self.generator.projection;
+        let points = area
+            .outer_rings()
+            .flat_map(|ring| ring.iter())
+            .filter_map(|node| projection.project(node));
+        if let Some(rows) = self.row_range(points) {
+            self.try_handle(area, rows);
+        }
     }
 
     fn node(&mut self, node: &Node) {
-        self.handle(node).unwrap();
+        if !self.read(node, Monitor::add_node)
+            || self.generator.skip([node])
+            || self.generator.oid(node.id()).is_none()
+            || self.generator.exclude(Kind::Node, node)
+        {
+            return;
+        }
+        let point = self.generator.projection.project(node);
+        if let Some(rows) = self.row_range(point) {
+            self.try_handle(node, rows);
+        }
     }
 
     fn way(&mut self, way: &Way) {
-        self.handle(way).unwrap();
+        if !self.read(way, Monitor::add_way)
+            || self.generator.skip(way.nodes().iter())
+            || self.generator.oid(way.id()).is_none()
+            || self.generator.exclude(Kind::Way, way)
+        {
+            return;
+        }
+        let projection = self.generator.projection;
+        let points = way
+            .nodes()
+            .iter()
+            .filter_map(|node| projection.project(node));
+        if let Some(rows) = self.row_range(points) {
+            self.try_handle(way, rows);
+        }
     }
 }
@@ -0,0 +1,389 @@
+//! Compare two generations of tiles
+//!
+//! Tiles are matched by their [TileKey] and items by their kind and oid.
+//! Items sharing an oid, like the pieces of a clipped way, are compared as a whole.
+//! Their points are compared with a [TOLERANCE] to ignore floating point noise.
+//! A tile missing in one of the generations is treated as empty.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::formats::archive::TileKey;
+use crate::formats::{Kind, Tile};
+use crate::geometry::Point;
+
+/// Maximum distance of two points to be considered equal relative to their tile's size
+pub const TOLERANCE: f64 = 1e-9;
+
+/// An item which was added, removed or whose geometry changed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ItemChange<Feature> {
+    pub kind: Kind,
+    pub oid: usize,
+
+    /// The item's feature in the new generation or, if it was removed, in the old one
+    pub feature: Feature,
+}
+
+/// An item whose feature changed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Reclassified<Feature> {
+    pub kind: Kind,
+    pub oid: usize,
+    pub old: Feature,
+    pub new: Feature,
+}
+
+/// The changes of a single tile
+///
+/// An item which was reclassified and moved is listed in both `reclassified` and `geometry_changed`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TileDiff<Feature> {
+    pub key: TileKey,
+    pub added: Vec<ItemChange<Feature>>,
+    pub removed: Vec<ItemChange<Feature>>,
+    pub reclassified: Vec<Reclassified<Feature>>,
+    pub geometry_changed: Vec<ItemChange<Feature>>,
+}
+
+impl<Feature> TileDiff<Feature> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.reclassified.is_empty()
+            && self.geometry_changed.is_empty()
+    }
+}
+
+/// Number of changed items with a certain feature summed over all tiles
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FeatureCounts<Feature> {
+    pub feature: Feature,
+    pub added: usize,
+    pub removed: usize,
+
+    /// Number of items which had this feature before being reclassified
+    pub reclassified_from: usize,
+
+    /// Number of items which have this feature after being reclassified
+    pub reclassified_to: usize,
+
+    pub geometry_changed: usize,
+}
+
+/// The changes between two generations
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Diff<Feature> {
+    /// The tiles which changed sorted by their key
+    pub tiles: Vec<TileDiff<Feature>>,
+
+    /// The counts sorted by feature
+    pub summary: Vec<FeatureCounts<Feature>>,
+}
+
+impl<Feature> Diff<Feature> {
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+/// Compare the tiles of an old and a new generation generated at the same zoom level
+///
+/// Fails for tiles outside the zoom level's range, see [TileKey::of].
+pub fn diff<Feature>(
+    old: &[Tile<Feature>],
+    new: &[Tile<Feature>],
+    zoom: u8,
+) -> Result<Diff<Feature>, Error>
+where
+    Feature: Clone + Ord,
+{
+    let mut tiles: BTreeMap<TileKey, (Option<_>, Option<_>)> = BTreeMap::new();
+    for tile in old.iter() {
+        tiles.entry(TileKey::of(tile, zoom)?).or_default().0 = Some(tile);
+    }
+    for tile in new.iter() {
+        tiles.entry(TileKey::of(tile, zoom)?).or_default().1 = Some(tile);
+    }
+
+    let mut summary: BTreeMap<Feature, FeatureCounts<Feature>> = BTreeMap::new();
+    let mut diffs = Vec::new();
+    for (key, (old, new)) in tiles {
+        let diff = diff_tile(key, old, new);
+        if diff.is_empty() {
+            continue;
+        }
+        for item in diff.added.iter() {
+            count(&mut summary, &item.feature).added += 1;
+        }
+        for item in diff.removed.iter() {
+            count(&mut summary, &item.feature).removed += 1;
+        }
+        for item in diff.reclassified.iter() {
+            count(&mut summary, &item.old).reclassified_from += 1;
+            count(&mut summary, &item.new).reclassified_to += 1;
+        }
+        for item in diff.geometry_changed.iter() {
+            count(&mut summary, &item.feature).geometry_changed += 1;
+        }
+        diffs.push(diff);
+    }
+
+    Ok(Diff {
+        tiles: diffs,
+        summary: summary.into_values().collect(),
+    })
+}
+
+fn count<'s, Feature: Clone + Ord>(
+    summary: &'s mut BTreeMap<Feature, FeatureCounts<Feature>>,
+    feature: &Feature,
+) -> &'s mut FeatureCounts<Feature> {
+    summary
+        .entry(feature.clone())
+        .or_insert_with(|| FeatureCounts {
+            feature: feature.clone(),
+            added: 0,
+            removed: 0,
+            reclassified_from: 0,
+            reclassified_to: 0,
+            geometry_changed: 0,
+        })
+}
+
+/// An item's feature and the rings, lines or points of all its pieces
+struct Entry<'t, Feature> {
+    feature: &'t Feature,
+    geometry: Vec<&'t [Point]>,
+}
+
+/// Group a tile's items by their kind and oid
+///
+/// The result is sorted, so the changes are listed in a stable order.
+fn entries<Feature>(tile: Option<&Tile<Feature>>) -> BTreeMap<(Kind, usize), Entry<'_, Feature>> {
+    let mut entries = BTreeMap::new();
+    let Some(tile) = tile else {
+        return entries;
+    };
+    for area in tile.iter_areas() {
+        insert(
+            &mut entries,
+            Kind::Area,
+            area.oid,
+            area.feature,
+            area.points.iter(),
+        );
+    }
+    for node in tile.iter_nodes() {
+        let point = std::slice::from_ref(node.points);
+        insert(
+            &mut entries,
+            Kind::Node,
+            node.oid,
+            node.feature,
+            std::iter::once(point),
+        );
+    }
+    for way in tile.iter_ways() {
+        insert(
+            &mut entries,
+            Kind::Way,
+            way.oid,
+            way.feature,
+            std::iter::once(way.points),
+        );
+    }
+    entries
+}
+
+fn insert<'t, Feature>(
+    entries: &mut BTreeMap<(Kind, usize), Entry<'t, Feature>>,
+    kind: Kind,
+    oid: usize,
+    feature: &'t Feature,
+    geometry: impl Iterator<Item = &'t [Point]>,
+) {
+    entries
+        .entry((kind, oid))
+        .or_insert_with(|| Entry {
+            feature,
+            geometry: Vec::new(),
+        })
+        .geometry
+        .extend(geometry);
+}
+
+fn diff_tile<Feature>(
+    key: TileKey,
+    old: Option<&Tile<Feature>>,
+    new: Option<&Tile<Feature>>,
+) -> TileDiff<Feature>
+where
+    Feature: Clone + Ord,
+{
+    let mut diff = TileDiff {
+        key,
+        added: Vec::new(),
+        removed: Vec::new(),
+        reclassified: Vec::new(),
+        geometry_changed: Vec::new(),
+    };
+
+    let change = |(kind, oid): (Kind, usize), entry: &Entry<Feature>| ItemChange {
+        kind,
+        oid,
+        feature: entry.feature.clone(),
+    };
+
+    let tolerance = old
+        .or(new)
+        .map_or(0.0, |tile| (tile.max - tile.min).amax() * TOLERANCE);
+    let old = entries(old);
+    let mut new = entries(new);
+    for (item, old) in old.iter() {
+        let Some(new) = new.remove(item) else {
+            diff.removed.push(change(*item, old));
+            continue;
+        };
+        if old.feature != new.feature {
+            diff.reclassified.push(Reclassified {
+                kind: item.0,
+                oid: item.1,
+                old: old.feature.clone(),
+                new: new.feature.clone(),
+            });
+        }
+        if !same_geometry(&old.geometry, &new.geometry, tolerance) {
+            diff.geometry_changed.push(change(*item, &new));
+        }
+    }
+    for (item, new) in new.iter() {
+        diff.added.push(change(*item, new));
+    }
+
+    diff
+}
+
+/// Check whether two items' pieces have the same points up to a tolerance
+fn same_geometry(old: &[&[Point]], new: &[&[Point]], tolerance: f64) -> bool {
+    old.len() == new.len()
+        && old.iter().zip(new).all(|(old, new)| {
+            old.len() == new.len()
+                && old
+                    .iter()
+                    .zip(new.iter())
+                    .all(|(old, new)| (old - new).amax() <= tolerance)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diff::{diff, FeatureCounts, ItemChange, Reclassified};
+    use crate::formats::archive::TileKey;
+    use crate::formats::{Kind, Tile};
+    use crate::geometry::{BBox, Point};
+
+    /// A tile at zoom 1
+    fn tile(x: f64, y: f64) -> Tile<usize> {
+        Tile::new(BBox {
+            min: Point::new(x * 0.5, y * 0.5),
+            max: Point::new(x * 0.5 + 0.5, y * 0.5 + 0.5),
+        })
+    }
+
+    #[test]
+    fn changes() {
+        let line = [Point::new(0.1, 0.1), Point::new(0.2, 0.2)];
+        let moved = [Point::new(0.1, 0.1), Point::new(0.3, 0.2)];
+
+        let mut old = vec![tile(0.0, 0.0), tile(1.0, 0.0)];
+        old[0].add_node(Point::new(0.1, 0.1), 1, 10);
+        old[0].add_node(Point::new(0.2, 0.2), 1, 11);
+        old[0].add_way(&line, 2, 20);
+        old[0].add_way(&line, 2, 21);
+        old[0].add_way(&line, 2, 22);
+        old[1].add_node(Point::new(0.6, 0.1), 1, 12);
+
+        let mut new = vec![tile(0.0, 0.0), tile(0.0, 1.0)];
+        new[0].add_node(Point::new(0.1, 0.1), 1, 10);
+        new[0].add_way(&line, 3, 20);
+        new[0].add_way(&moved, 2, 21);
+        new[0].add_way(&line, 2, 22);
+        new[0].add_way(&line, 2, 22); // A second piece
+        new[1].add_node(Point::new(0.1, 0.6), 3, 13);
+
+        let diff = diff(&old, &new, 1).unwrap();
+        let keys: Vec<_> = diff.tiles.iter().map(|tile| tile.key).collect();
+        assert_eq!(
+            keys,
+            vec![
+                TileKey { x: 0, y: 0 },
+                TileKey { x: 0, y: 1 },
+                TileKey { x: 1, y: 0 }
+            ]
+        );
+
+        let first = &diff.tiles[0];
+        assert!(first.added.is_empty());
+        assert_eq!(
+            first.removed,
+            vec![ItemChange {
+                kind: Kind::Node,
+                oid: 11,
+                feature: 1
+            }]
+        );
+        assert_eq!(
+            first.reclassified,
+            vec![Reclassified {
+                kind: Kind::Way,
+                oid: 20,
+                old: 2,
+                new: 3
+            }]
+        );
+        let oids: Vec<_> = first.geometry_changed.iter().map(|item| item.oid).collect();
+        assert_eq!(oids, vec![21, 22]);
+
+        assert_eq!(diff.tiles[1].added[0].oid, 13);
+        assert_eq!(diff.tiles[2].removed[0].oid, 12);
+
+        assert_eq!(
+            diff.summary,
+            vec![
+                FeatureCounts {
+                    feature: 1,
+                    removed: 2,
+                    ..Default::default()
+                },
+                FeatureCounts {
+                    feature: 2,
+                    reclassified_from: 1,
+                    geometry_changed: 2,
+                    ..Default::default()
+                },
+                FeatureCounts {
+                    feature: 3,
+                    added: 1,
+                    reclassified_to: 1,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged() {
+        let mut old = vec![tile(0.0, 0.0)];
+        old[0].add_node(Point::new(0.1, 0.1), 1, 10);
+        let new = old.clone();
+        assert!(diff(&old, &new, 1).unwrap().is_empty());
+
+        // Floating point noise
+        let mut new = vec![tile(0.0, 0.0)];
+        new[0].add_node(Point::new(0.1 + 1e-12, 0.1), 1, 10);
+        assert!(diff(&old, &new, 1).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+//! The library's error type
+
+use std::fmt;
+
+use crate::features::config::ParserError;
+
+/// Errors produced while generating a world
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O operation failed
+    Io(std::io::Error),
+
+    /// libosmium failed to read or decode the PBF file
+    Pbf(String),
+
+    /// A worker thread panicked while generating its tiles
+    WorkerPanicked {
+        /// Index of the worker
+        worker: usize,
+
+        /// The panic's message if it was a string
+        message: String,
+    },
+
+    /// A worker stopped receiving items before the input was finished
+    WorkerDisconnected {
+        /// Index of the worker
+        worker: usize,
+    },
+
+    /// The generation was cancelled using a [CancellationToken]
+    ///
+    /// [CancellationToken]: crate::progress::CancellationToken
+    Cancelled,
+
+    /// The config contains invalid values
+    InvalidConfig(String),
+
+    /// The feature config couldn't be parsed
+    ConfigParse(Box<ParserError>),
+
+    /// A trie used to look up tags couldn't be built
+    Trie(String),
+
+    /// Serializing or deserializing some data failed
+    Serialization(Box<dyn std::error::Error + Send + Sync>),
+
+    /// Data isn't in the expected format
+    Format(String),
+
+    /// An osm change file couldn't be parsed
+    ChangeFile(String),
+}
+
+impl Error {
+    /// Wrap any serde error
+    pub fn serialization(error: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self::Serialization(Box::new(error))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "I/O error: {error}"),
+            Error::Pbf(error) => write!(f, "Couldn't read the PBF file: {error}"),
+            Error::WorkerPanicked { worker, message } => {
+                write!(f, "Worker {worker} panicked: {message}")
+            }
+            Error::WorkerDisconnected { worker } => {
+                write!(f, "Worker {worker} stopped before the input was finished")
+            }
+            Error::Cancelled => write!(f, "The generation was cancelled"),
+            Error::InvalidConfig(error) => write!(f, "Invalid config: {error}"),
+            Error::ConfigParse(error) => write!(f, "Couldn't parse the feature config: {error}"),
+            Error::Trie(error) => write!(f, "Couldn't build trie: {error}"),
+            Error::Serialization(error) => write!(f, "Serialization failed: {error}"),
+            Error::Format(error) => write!(f, "Invalid data: {error}"),
+            Error::ChangeFile(error) => write!(f, "Couldn't parse the change file: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::ConfigParse(error) => Some(error.as_ref()),
+            Error::Serialization(error) => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<ParserError> for Error {
+    fn from(error: ParserError) -> Self {
+        Self::ConfigParse(Box::new(error))
+    }
+}
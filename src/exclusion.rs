@@ -0,0 +1,489 @@
+//! Exclusion zones suppressing items inside sensitive areas like schools or military areas
+//!
+//! The zones are areas matched by a config's `[Exclusions]` block, see [FeatureParser::exclusion].
+//! While generating, they are clipped into every tile's [ExclusionMask].
+//! Afterwards [suppress] uses the [Exclusions] rules to remove the nodes inside the zones,
+//! cut the ways at the zones' borders and cut the zones out of the areas.
+//!
+//! [FeatureParser::exclusion]: crate::features::FeatureParser::exclusion
+
+use serde::{Deserialize, Serialize};
+
+use crate::formats::{Item, Kind, Rings, Tile};
+use crate::geometry::{polygon, polyline, BBox, Point};
+
+/// The exclusion zones clipped to a tile
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ExclusionMask {
+    /// Zones define a range of `rings` like [Tile::areas], their feature is the id of an `[Exclusions]` branch.
+    pub zones: Vec<Item<usize, (usize, usize)>>,
+
+    /// Ranges of `points` forming the zones' rings
+    pub rings: Vec<(usize, usize)>,
+
+    /// Pool of points used by all zones
+    pub points: Vec<Point>,
+}
+
+impl ExclusionMask {
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+
+    pub fn zone(&self, index: usize) -> Item<&usize, Rings<'_>> {
+        let Item {
+            feature,
+            oid,
+            points: (start, end),
+        } = &self.zones[index];
+        Item {
+            feature,
+            oid: *oid,
+            points: Rings {
+                points: &self.points,
+                rings: &self.rings[*start..*end],
+            },
+        }
+    }
+
+    pub fn iter_zones(&self) -> impl Iterator<Item = Item<&usize, Rings<'_>>> {
+        (0..self.zones.len()).map(|index| self.zone(index))
+    }
+
+    /// Check whether a point lies inside any zone
+    pub fn contains(&self, point: Point) -> bool {
+        self.iter_zones().any(|zone| zone.points.contains(point))
+    }
+
+    /// Add a zone consisting only of its outer ring
+    pub fn add_zone(&mut self, outer_ring: &[Point], class: usize, oid: usize) {
+        let ring = self.rings.len();
+        self.push_ring(outer_ring);
+        self.zones.push(Item {
+            feature: class,
+            oid,
+            points: (ring, ring + 1),
+        });
+    }
+
+    /// Add a hole to the last added zone
+    pub fn add_hole(&mut self, inner_ring: &[Point]) {
+        let Some(zone) = self.zones.last_mut() else {
+            return;
+        };
+        zone.points.1 += 1;
+        self.push_ring(inner_ring);
+    }
+
+    fn push_ring(&mut self, ring: &[Point]) {
+        let start = self.points.len();
+        self.points.extend_from_slice(ring);
+        self.rings.push((start, self.points.len()));
+    }
+
+    /// Sort the zones by their oid and rewrite the points in that order, see [Tile::canonicalize]
+    pub fn canonicalize(&mut self) {
+        let mut mask = ExclusionMask::default();
+        let mut order: Vec<usize> = (0..self.zones.len()).collect();
+        order.sort_by_key(|&index| self.zones[index].oid);
+        for index in order {
+            let zone = self.zone(index);
+            mask.add_zone(zone.points.outer(), *zone.feature, zone.oid);
+            for hole in zone.points.inner() {
+                mask.add_hole(hole);
+            }
+        }
+        *self = mask;
+    }
+}
+
+/// Which items are suppressed inside the exclusion zones
+///
+/// An item is suppressed inside a zone if any rule matches the zone's class, the item's kind and feature.
+/// Without any rules the zones are only recorded in the tiles' masks.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Exclusions<Feature> {
+    pub rules: Vec<ExclusionRule<Feature>>,
+}
+
+/// Selects the items a zone suppresses
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExclusionRule<Feature> {
+    /// Class of zones to apply to i.e. the id of an `[Exclusions]` branch, `None` for all zones
+    pub zone: Option<usize>,
+
+    /// Kind of items to apply to, `None` for all kinds
+    pub kind: Option<Kind>,
+
+    /// Feature to apply to, `None` for all features
+    pub feature: Option<Feature>,
+
+    /// Remove areas touching the zone entirely instead of cutting the zone out of them
+    #[serde(default)]
+    pub overlapping: bool,
+}
+
+impl<Feature> Default for Exclusions<Feature> {
+    fn default() -> Self {
+        Self { rules: Vec::new() }
+    }
+}
+
+impl<Feature: PartialEq> Exclusions<Feature> {
+    /// Check whether a zone suppresses items of a kind and feature
+    pub fn applies(&self, zone: usize, kind: Kind, feature: &Feature) -> bool {
+        self.matching(zone, kind, feature).next().is_some()
+    }
+
+    /// Iterate over the rules matching a zone's class, an item's kind and feature
+    pub fn matching<'a>(
+        &'a self,
+        zone: usize,
+        kind: Kind,
+        feature: &'a Feature,
+    ) -> impl Iterator<Item = &'a ExclusionRule<Feature>> + 'a {
+        self.rules.iter().filter(move |rule| {
+            rule.zone.is_none_or(|class| class == zone)
+                && rule.kind.is_none_or(|rule_kind| rule_kind == kind)
+                && rule.feature.as_ref().is_none_or(|f| f == feature)
+        })
+    }
+}
+
+/// Suppress a tile's items inside its [ExclusionMask] according to the rules
+///
+/// Nodes inside a zone are removed and ways are cut at the zones' borders, keeping the pieces outside.
+/// The zones are cut out of the areas, which might split an area into several ones sharing its feature and oid.
+/// Rules with [ExclusionRule::overlapping] set remove areas touching a zone entirely instead.
+/// The tile's spatial index is dropped.
+pub fn suppress<Feature>(tile: &mut Tile<Feature>, exclusions: &Exclusions<Feature>)
+where
+    Feature: Clone + PartialEq,
+{
+    if exclusions.rules.is_empty() || tile.exclusions.is_empty() {
+        return;
+    }
+    let mask = std::mem::take(&mut tile.exclusions);
+    let zones = |kind, feature: &Feature| -> Vec<Rings<'_>> {
+        mask.iter_zones()
+            .filter(|zone| exclusions.applies(*zone.feature, kind, feature))
+            .map(|zone| zone.points)
+            .collect()
+    };
+
+    let mut output = Tile::new(BBox {
+        min: tile.min,
+        max: tile.max,
+    });
+    'areas: for area in tile.iter_areas() {
+        let bbox = BBox::from_iter(area.points.outer().iter().copied());
+        // The area's remaining rings, `None` while no zone has been cut out of it
+        let mut rings: Option<Vec<Vec<Point>>> = None;
+        for zone in mask.iter_zones() {
+            let (mut applies, mut overlapping) = (false, false);
+            for rule in exclusions.matching(*zone.feature, Kind::Area, area.feature) {
+                applies = true;
+                overlapping |= rule.overlapping;
+            }
+            let zone_bbox = BBox::from_iter(zone.points.outer().iter().copied());
+            if !applies || !bbox.overlaps(&zone_bbox) {
+                continue;
+            }
+            if overlapping {
+                if overlaps(&zone.points, &area.points) {
+                    continue 'areas;
+                }
+                continue;
+            }
+            let rings =
+                rings.get_or_insert_with(|| area.points.iter().map(<[Point]>::to_vec).collect());
+            let subject: Vec<&[Point]> = rings.iter().map(Vec::as_slice).collect();
+            let clip: Vec<&[Point]> = zone.points.iter().collect();
+            *rings = polygon::difference(&subject, &clip);
+        }
+        match rings {
+            None => {
+                output.add_area(area.points.outer(), area.feature.clone(), area.oid);
+                for hole in area.points.inner() {
+                    output.add_hole(hole);
+                }
+            }
+            Some(rings) => {
+                for (outer, holes) in polygon::nest(rings) {
+                    output.add_area(&outer, area.feature.clone(), area.oid);
+                    for hole in &holes {
+                        output.add_hole(hole);
+                    }
+                }
+            }
+        }
+    }
+    for node in tile.iter_nodes() {
+        let zones = zones(Kind::Node, node.feature);
+        if zones.iter().any(|zone| zone.contains(*node.points)) {
+            continue;
+        }
+        output.add_node(*node.points, node.feature.clone(), node.oid);
+    }
+    for way in tile.iter_ways() {
+        let zones = zones(Kind::Way, way.feature);
+        for piece in cut(way.points, &zones) {
+            output.add_way(&piece, way.feature.clone(), way.oid);
+        }
+    }
+
+    output.exclusions = mask;
+    *tile = output;
+}
+
+/// Check whether an area touches a zone
+fn overlaps(zone: &Rings, area: &Rings) -> bool {
+    let outer = area.outer();
+    if outer.iter().any(|&point| zone.contains(point)) {
+        return true;
+    }
+    if zone.outer().iter().any(|&point| area.contains(point)) {
+        return true;
+    }
+    zone.iter().any(|ring| {
+        polygon::iter_edges(ring).any(|(a, b)| {
+            polygon::iter_edges(outer).any(|(c, d)| polygon::segments_intersect(*a, *b, *c, *d))
+        })
+    })
+}
+
+/// Cut a path at the zones' borders and keep the pieces outside of all zones
+fn cut(path: &[Point], zones: &[Rings]) -> Vec<Vec<Point>> {
+    if zones.is_empty() || path.len() < 2 {
+        return vec![path.to_vec()];
+    }
+    let inside = |point: Point| zones.iter().any(|zone| zone.contains(point));
+
+    let mut pieces = Vec::new();
+    let mut piece = Vec::new();
+    let mut crossings = Vec::new();
+    for (&from, &to) in polyline::iter_segments(path) {
+        crossings.clear();
+        crossings.extend([0.0, 1.0]);
+        for ring in zones.iter().flat_map(Rings::iter) {
+            polygon::crossings(ring, from, to, &mut crossings);
+        }
+        crossings.sort_by(f64::total_cmp);
+
+        // Use the segment's ends as they are to keep consecutive pieces connected
+        let at = |t: f64| match t {
+            0.0 => from,
+            1.0 => to,
+            t => from + (to - from) * t,
+        };
+        for range in crossings.windows(2) {
+            if range[0] == range[1] {
+                continue;
+            }
+            let (start, end) = (at(range[0]), at(range[1]));
+            if inside((start + end) / 2.0) {
+                if piece.len() > 1 {
+                    pieces.push(std::mem::take(&mut piece));
+                }
+                piece.clear();
+            } else {
+                if piece.is_empty() {
+                    piece.push(start);
+                }
+                piece.push(end);
+            }
+        }
+    }
+    if piece.len() > 1 {
+        pieces.push(piece);
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod test {
+    use crate::exclusion::{cut, suppress, ExclusionMask, ExclusionRule, Exclusions};
+    use crate::formats::{Kind, Rings, Tile};
+    use crate::geometry::{polygon, square, BBox, Point};
+
+    fn mask() -> ExclusionMask {
+        let mut mask = ExclusionMask::default();
+        mask.add_zone(&square(1.0, 3.0), 7, 100);
+        mask.add_hole(&square(1.5, 2.5));
+        mask
+    }
+
+    #[test]
+    fn cut_ways() {
+        let mask = mask();
+        let zones: Vec<_> = mask.iter_zones().map(|zone| zone.points).collect();
+
+        let path = [Point::new(0.0, 2.0), Point::new(4.0, 2.0)];
+        assert_eq!(
+            cut(&path, &zones),
+            vec![
+                vec![Point::new(0.0, 2.0), Point::new(1.0, 2.0)],
+                vec![Point::new(1.5, 2.0), Point::new(2.5, 2.0)],
+                vec![Point::new(3.0, 2.0), Point::new(4.0, 2.0)],
+            ]
+        );
+
+        // Pieces outside continue across the path's vertices
+        let path = [
+            Point::new(0.0, 0.0),
+            Point::new(4.0, 0.0),
+            Point::new(4.0, 2.0),
+            Point::new(2.0, 2.0),
+        ];
+        let pieces = cut(&path, &zones);
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0][..3], path[..3]);
+        assert_eq!(pieces[0][3], Point::new(3.0, 2.0));
+        assert_eq!(pieces[1], vec![Point::new(2.5, 2.0), Point::new(2.0, 2.0)]);
+    }
+
+    #[test]
+    fn suppress_items() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(4.0, 4.0),
+        });
+        tile.add_area(&square(0.0, 0.5), 1, 10);
+        tile.add_area(&square(0.5, 1.5), 1, 11);
+        tile.add_area(&square(1.75, 2.25), 1, 12); // Inside the hole
+        tile.add_node(Point::new(1.25, 1.25), 2, 20);
+        tile.add_node(Point::new(1.25, 1.25), 3, 21);
+        tile.add_node(Point::new(2.0, 2.0), 2, 22);
+        tile.add_way(&[Point::new(0.0, 1.25), Point::new(4.0, 1.25)], 4, 30);
+        tile.exclusions = mask();
+
+        let exclusions = Exclusions {
+            rules: vec![
+                ExclusionRule {
+                    zone: Some(7),
+                    kind: Some(Kind::Area),
+                    feature: None,
+                    overlapping: true,
+                },
+                ExclusionRule {
+                    zone: None,
+                    kind: None,
+                    feature: Some(2),
+                    overlapping: false,
+                },
+                ExclusionRule {
+                    zone: None,
+                    kind: Some(Kind::Way),
+                    feature: Some(4),
+                    overlapping: false,
+                },
+            ],
+        };
+        suppress(&mut tile, &exclusions);
+
+        let areas: Vec<_> = tile.iter_areas().map(|area| area.oid).collect();
+        assert_eq!(areas, vec![10, 12]);
+        let nodes: Vec<_> = tile.iter_nodes().map(|node| node.oid).collect();
+        assert_eq!(nodes, vec![21, 22]);
+        let ways: Vec<_> = tile.iter_ways().map(|way| way.points.to_vec()).collect();
+        assert_eq!(
+            ways,
+            vec![
+                vec![Point::new(0.0, 1.25), Point::new(1.0, 1.25)],
+                vec![Point::new(3.0, 1.25), Point::new(4.0, 1.25)],
+            ]
+        );
+        assert_eq!(tile.exclusions.zones.len(), 1);
+    }
+
+    #[test]
+    fn clipped_areas() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(4.0, 4.0),
+        });
+        tile.add_area(&square(0.5, 1.5), 1, 10); // Overlapping
+        tile.add_area(&square(1.1, 1.4), 1, 11); // Inside
+        tile.add_area(&square(1.75, 2.25), 1, 12); // Inside the hole
+        tile.add_area(&square(1.25, 2.75), 1, 13); // Around the hole
+        tile.exclusions = mask();
+
+        let exclusions = Exclusions {
+            rules: vec![ExclusionRule {
+                zone: Some(7),
+                kind: Some(Kind::Area),
+                feature: None,
+                overlapping: false,
+            }],
+        };
+        suppress(&mut tile, &exclusions);
+
+        let areas: Vec<_> = tile.iter_areas().map(|area| area.oid).collect();
+        assert_eq!(areas, vec![10, 12, 13]);
+        let areas: Vec<_> = tile.iter_areas().map(|area| area.points).collect();
+        // The part inside the zone is cut out of the corner
+        assert_eq!(areas[0].outer().len(), 6);
+        assert!((area(&areas[0]) - 0.75).abs() < 1e-6);
+        assert_eq!(areas[1].outer(), square(1.75, 2.25));
+        // Only the zone's hole is left
+        assert!((area(&areas[2]) - 1.0).abs() < 1e-6);
+        assert!(areas[2]
+            .outer()
+            .iter()
+            .all(|point| { (point - Point::new(2.0, 2.0)).amax() < 0.5 + 1e-6 }));
+    }
+
+    #[test]
+    fn park_in_school() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(4.0, 4.0),
+        });
+        let park = [
+            Point::new(0.0, 1.0),
+            Point::new(2.0, 1.0),
+            Point::new(2.0, 2.0),
+            Point::new(0.0, 2.0),
+        ];
+        tile.add_area(&park, 1, 10);
+        tile.add_hole(&[
+            Point::new(0.25, 1.25),
+            Point::new(0.25, 1.75),
+            Point::new(0.75, 1.5),
+        ]);
+        tile.exclusions.add_zone(&square(1.0, 3.0), 7, 100);
+
+        let exclusions = Exclusions {
+            rules: vec![ExclusionRule {
+                zone: Some(7),
+                kind: None,
+                feature: None,
+                overlapping: false,
+            }],
+        };
+        suppress(&mut tile, &exclusions);
+
+        // The park's half outside the school is kept with its pond
+        let areas: Vec<_> = tile.iter_areas().collect();
+        assert_eq!(areas.len(), 1);
+        assert_eq!(areas[0].oid, 10);
+        assert_eq!(areas[0].points.inner().count(), 1);
+        assert!(areas[0]
+            .points
+            .outer()
+            .iter()
+            .all(|point| point.x < 1.0 + 1e-6));
+        assert!((area(&areas[0].points) - (1.0 - 0.125)).abs() < 1e-6);
+    }
+
+    fn area(rings: &Rings) -> f64 {
+        let signed_area = |ring: &[Point]| {
+            polygon::iter_edges(ring)
+                .map(|(a, b)| a.perp(b))
+                .sum::<f64>()
+                .abs()
+                / 2.0
+        };
+        signed_area(rings.outer()) - rings.inner().map(signed_area).sum::<f64>()
+    }
+}
@@ -0,0 +1,161 @@
+//! Decide whether a closed way describes an area or a closed line
+//!
+//! libosmium's area assembler turns every tagged closed way into an area,
+//! even roundabouts, fences or closed footpaths which are actually lines.
+//! An [AreaRule] implements [OSM's heuristics](https://wiki.openstreetmap.org/wiki/Key:area)
+//! to tell them apart:
+//! - `area=yes` and `area=no` always take precedence
+//! - otherwise the way is an area if any of its tags matches a [KeyRule]
+
+use serde::{Deserialize, Serialize};
+
+use crate::features::Tags;
+
+/// Set of rules to decide whether a closed way is an area
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AreaRule {
+    /// Keys which make a closed way an area
+    pub keys: Vec<KeyRule>,
+}
+
+/// A key which makes a closed way an area depending on its value
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyRule {
+    pub key: String,
+    pub values: Values,
+}
+
+/// Selects the values of a [KeyRule] which make a closed way an area
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Values {
+    /// Any value except the listed ones describes an area
+    AllExcept(Vec<String>),
+
+    /// Only the listed values describe an area
+    Only(Vec<String>),
+}
+
+impl AreaRule {
+    /// Check whether a closed way with these tags is an area
+    pub fn is_area<'t>(&self, tags: impl Tags<'t>) -> bool {
+        let mut is_area = false;
+        for (key, value) in tags {
+            if key == "area" {
+                match value {
+                    "yes" => return true,
+                    "no" => return false,
+                    _ => {}
+                }
+            }
+            if !is_area {
+                is_area = self.keys.iter().any(|rule| rule.matches(key, value));
+            }
+        }
+        is_area
+    }
+}
+
+impl KeyRule {
+    /// Check whether a tag matches this rule
+    pub fn matches(&self, key: &str, value: &str) -> bool {
+        if self.key != key || value == "no" {
+            return false;
+        }
+        match &self.values {
+            Values::AllExcept(values) => !values.iter().any(|v| v == value),
+            Values::Only(values) => values.iter().any(|v| v == value),
+        }
+    }
+}
+
+impl Default for AreaRule {
+    /// The keys and exceptions used by most OSM renderers
+    fn default() -> Self {
+        fn all_except(key: &str, values: &[&str]) -> KeyRule {
+            KeyRule {
+                key: key.to_string(),
+                values: Values::AllExcept(values.iter().map(|v| v.to_string()).collect()),
+            }
+        }
+        fn only(key: &str, values: &[&str]) -> KeyRule {
+            KeyRule {
+                key: key.to_string(),
+                values: Values::Only(values.iter().map(|v| v.to_string()).collect()),
+            }
+        }
+
+        Self {
+            keys: vec![
+                all_except(
+                    "aeroway",
+                    &["jet_bridge", "parking_position", "runway", "taxiway"],
+                ),
+                all_except("amenity", &[]),
+                all_except("building", &[]),
+                all_except("building:part", &[]),
+                all_except("craft", &[]),
+                all_except("historic", &[]),
+                all_except("landuse", &[]),
+                all_except("leisure", &["slipway", "track"]),
+                all_except(
+                    "man_made",
+                    &[
+                        "breakwater",
+                        "cutline",
+                        "dyke",
+                        "embankment",
+                        "groyne",
+                        "pipeline",
+                    ],
+                ),
+                all_except("military", &["trench"]),
+                all_except(
+                    "natural",
+                    &["arete", "cliff", "coastline", "ridge", "tree_row", "valley"],
+                ),
+                all_except("office", &[]),
+                all_except("place", &[]),
+                all_except("shop", &[]),
+                all_except("tourism", &[]),
+                only("highway", &["rest_area", "services"]),
+                only(
+                    "power",
+                    &[
+                        "compensator",
+                        "converter",
+                        "generator",
+                        "plant",
+                        "substation",
+                    ],
+                ),
+                only("public_transport", &["platform", "station"]),
+                only("railway", &["platform", "station", "turntable"]),
+                only("waterway", &["boatyard", "dam", "dock", "riverbank"]),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::features::area::AreaRule;
+
+    #[test]
+    fn osm_heuristics() {
+        let rule = AreaRule::default();
+        assert!(rule.is_area([("building", "yes")]));
+        assert!(rule.is_area([("name", "Park"), ("leisure", "park")]));
+        assert!(!rule.is_area([("junction", "roundabout"), ("highway", "primary")]));
+        assert!(!rule.is_area([("barrier", "fence")]));
+        assert!(!rule.is_area([("natural", "coastline")]));
+        assert!(!rule.is_area([("building", "no")]));
+    }
+
+    #[test]
+    fn explicit_area_tag() {
+        let rule = AreaRule::default();
+        assert!(rule.is_area([("highway", "pedestrian"), ("area", "yes")]));
+        assert!(!rule.is_area([("landuse", "grass"), ("area", "no")]));
+        assert!(!rule.is_area([("area", "no"), ("landuse", "grass")]));
+    }
+}
@@ -1,5 +1,5 @@
 //! Ast and grammar for the custom config language
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::Hash;
 use std::marker::PhantomData;
 
@@ -7,6 +7,7 @@ use pest::iterators::Pair;
 use pest::Parser;
 
 use super::pest_ext::PairsExt;
+use super::Dictionary;
 
 /// The config's grammar defined using [pest](https://pest.rs/)
 #[derive(pest_derive::Parser)]
@@ -24,6 +25,12 @@ pub struct Ast<T> {
 
     /// The `[Ways]` block
     pub ways: Vec<Branch<T>>,
+
+    /// The `[Exclusions]` block matching areas which become exclusion zones
+    pub exclusions: Vec<Branch<T>>,
+
+    /// The aliases declared in each block by their ids
+    pub aliases: Dictionary,
 }
 
 /// A matching branch maps a condition to a result.
@@ -119,6 +126,8 @@ where
                 areas: Vec::new(),
                 nodes: Vec::new(),
                 ways: Vec::new(),
+                exclusions: Vec::new(),
+                aliases: Dictionary::default(),
             })
         }
     }
@@ -127,11 +136,13 @@ where
         let mut area_aliases: HashMap<&'i str, usize> = HashMap::new();
         let mut node_aliases: HashMap<&'i str, usize> = HashMap::new();
         let mut way_aliases: HashMap<&'i str, usize> = HashMap::new();
+        let mut exclusion_aliases: HashMap<&'i str, usize> = HashMap::new();
         Ok(match file.as_rule() {
             Rule::file => {
                 let mut areas = None;
                 let mut nodes = None;
                 let mut ways = None;
+                let mut exclusions = None;
                 for block in file.into_inner() {
                     let rule = block.as_rule();
                     match rule {
@@ -145,10 +156,11 @@ where
                         Rule::areas => (&mut areas, &mut area_aliases),
                         Rule::nodes => (&mut nodes, &mut node_aliases),
                         Rule::ways => (&mut ways, &mut way_aliases),
+                        Rule::exclusions => (&mut exclusions, &mut exclusion_aliases),
                         invalid => {
                             return Err(ParserError::InvalidRule(
                                 invalid,
-                                vec![Rule::areas, Rule::nodes, Rule::ways],
+                                vec![Rule::areas, Rule::nodes, Rule::ways, Rule::exclusions],
                             ))
                         }
                     };
@@ -166,6 +178,13 @@ where
                     areas: areas.unwrap_or_default(),
                     nodes: nodes.unwrap_or_default(),
                     ways: ways.unwrap_or_default(),
+                    exclusions: exclusions.unwrap_or_default(),
+                    aliases: Dictionary {
+                        areas: invert_aliases(area_aliases),
+                        nodes: invert_aliases(node_aliases),
+                        ways: invert_aliases(way_aliases),
+                        exclusions: invert_aliases(exclusion_aliases),
+                    },
                 }
             }
             i => return invalid_rule(i, [Rule::file]),
@@ -299,6 +318,21 @@ impl From<pest::error::Error<Rule>> for ParserError {
         Self::SyntaxError(error)
     }
 }
+/// Map ids to their names, keeping the alphabetically first name if an id has several
+fn invert_aliases(aliases: HashMap<&str, usize>) -> BTreeMap<usize, String> {
+    let mut names = BTreeMap::new();
+    for (name, id) in aliases {
+        names
+            .entry(id)
+            .and_modify(|existing: &mut String| {
+                if name < existing.as_str() {
+                    *existing = name.to_string();
+                }
+            })
+            .or_insert_with(|| name.to_string());
+    }
+    names
+}
 fn invalid_rule<T, const N: usize>(got: Rule, expected: [Rule; N]) -> ParserResult<T> {
     Err(ParserError::InvalidRule(got, expected.to_vec()))
 }
@@ -324,7 +358,7 @@ impl std::fmt::Display for ParserError {
                 )?;
                 write!(f, "Got: {:?}\n", got)?;
                 if exp.len() == 1 {
-                    write!(f, "Expected: {:?}\n", exp[1])
+                    write!(f, "Expected: {:?}\n", exp[0])
                 } else {
                     write!(f, "Expected one of:\n")?;
                     for rule in exp {
@@ -339,5 +373,60 @@ impl std::fmt::Display for ParserError {
         }
     }
 }
+impl std::error::Error for ParserError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParserError::SyntaxError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 type ParserResult<T> = Result<T, ParserError>;
+
+#[cfg(test)]
+mod test {
+    use crate::features::config::ConfigParser;
+
+    #[test]
+    fn keep_aliases() {
+        let ast = ConfigParser::borrowing()
+            .parse_file(
+                r#"
+                [Areas]
+                PARK = 1
+                PARK: "leisure" is "park"
+                [Ways]
+                ROAD = 3
+                STREET = 3
+                ROAD: "highway" exists
+                "#,
+            )
+            .unwrap();
+        assert_eq!(ast.areas[0].id, 1);
+        assert_eq!(ast.aliases.areas.get(&1).map(String::as_str), Some("PARK"));
+        assert!(ast.aliases.nodes.is_empty());
+        assert_eq!(ast.aliases.ways.get(&3).map(String::as_str), Some("ROAD"));
+    }
+
+    #[test]
+    fn exclusions_block() {
+        let ast = ConfigParser::borrowing()
+            .parse_file(
+                r#"
+                [Exclusions]
+                SCHOOL = 1
+                SCHOOL: "amenity" in ["school", "kindergarten"]
+                2: "landuse" is "military"
+                "#,
+            )
+            .unwrap();
+        assert!(ast.areas.is_empty());
+        assert_eq!(ast.exclusions.len(), 2);
+        assert_eq!(ast.exclusions[1].id, 2);
+        assert_eq!(
+            ast.aliases.exclusions.get(&1).map(String::as_str),
+            Some("SCHOOL")
+        );
+    }
+}
@@ -2,8 +2,12 @@
 //!
 //! For example turn a real world shop into a virtual world one
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
+
+pub mod area;
 pub mod automaton;
 pub mod config;
 pub mod pest_ext;
@@ -12,6 +16,17 @@ pub mod simple;
 pub mod simplify;
 pub mod yada;
 
+/// Names of the features' ids for each kind of osm object
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dictionary {
+    pub areas: BTreeMap<usize, String>,
+    pub nodes: BTreeMap<usize, String>,
+    pub ways: BTreeMap<usize, String>,
+
+    #[serde(default)]
+    pub exclusions: BTreeMap<usize, String>,
+}
+
 /// Trait alias for a `IntoIterator` over pairs of `&'t str`
 pub trait Tags<'t>: IntoIterator<Item = (&'t str, &'t str)> {}
 impl<'t, T: IntoIterator<Item = (&'t str, &'t str)>> Tags<'t> for T {}
@@ -22,6 +37,20 @@ pub trait FeatureParser {
     fn area<'t>(&self, area: impl Tags<'t>) -> Option<Self::Feature>;
     fn node<'t>(&self, node: impl Tags<'t>) -> Option<Self::Feature>;
     fn way<'t>(&self, way: impl Tags<'t>) -> Option<Self::Feature>;
+
+    /// Get the class of exclusion zone an area is, see [exclusion](crate::exclusion)
+    ///
+    /// `None` if the area isn't an exclusion zone or the parser doesn't support them.
+    fn exclusion<'t>(&self, _area: impl Tags<'t>) -> Option<usize> {
+        None
+    }
+
+    /// Map the ids of the config's branches to the alias names assigned to them
+    ///
+    /// `None` if the parser doesn't know any names.
+    fn dictionary(&self) -> Option<Dictionary> {
+        None
+    }
 }
 
 impl<P: FeatureParser> FeatureParser for Arc<P> {
@@ -38,4 +67,12 @@ impl<P: FeatureParser> FeatureParser for Arc<P> {
     fn way<'t>(&self, way: impl Tags<'t>) -> Option<Self::Feature> {
         self.as_ref().way(way)
     }
+
+    fn exclusion<'t>(&self, area: impl Tags<'t>) -> Option<usize> {
+        self.as_ref().exclusion(area)
+    }
+
+    fn dictionary(&self) -> Option<Dictionary> {
+        self.as_ref().dictionary()
+    }
 }
@@ -1,20 +1,103 @@
 //! This feature and parser is intended to be used while prototyping to visualize unoptimised spawn rules.
+//!
+//! The config is a JSON object mapping each key to the list of its values of interest.
+//! A feature lists the indices of the key and value of each matching tag.
+//!
+//! Exclusion zones are configured separately by an [ExclusionConfig],
+//! whose `zones` use the same format and whose `rules` are passed on to [Config](crate::Config).
+
+use std::collections::BTreeMap;
 
 use linear_map::LinearMap;
+use serde::{Deserialize, Serialize, Serializer};
 use yada::builder::DoubleArrayBuilder;
 use yada::DoubleArray;
 
-use crate::features::{FeatureParser, Tags};
+use crate::error::Error;
+use crate::exclusion::{ExclusionRule, Exclusions};
+use crate::features::{Dictionary, FeatureParser, Tags};
 
 pub struct Parser {
+    features: Matcher,
+
+    /// Matches the exclusion zones' tags, see [Parser::with_exclusions]
+    exclusions: Option<Matcher>,
+}
+
+/// Tries matching the tags of a config mapping keys to their values
+struct Matcher {
     keys: DoubleArray<Vec<u8>>,
     values: Vec<DoubleArray<Vec<u8>>>,
+
+    /// The parsed config, which is also what the parser serializes into
+    config: LinearMap<String, Vec<String>>,
+
+    /// The [id](Parser::id) of each key's first value
+    offsets: Vec<usize>,
+}
+
+/// File configuring the exclusion zones
+///
+/// ```json
+/// {"zones": {"amenity": ["school"]}, "rules": [{"zone": 0, "kind": "Node", "feature": null}]}
+/// ```
+#[derive(Deserialize, Debug)]
+pub struct ExclusionConfig {
+    /// Tags of the zones, their classes number the tags in order like [Parser::id]
+    pub zones: LinearMap<String, Vec<String>>,
+
+    /// Rules selecting the items to suppress inside the zones
+    #[serde(default)]
+    pub rules: Vec<ExclusionRule<Feature>>,
+}
+
+impl ExclusionConfig {
+    pub fn from_file(file: &str) -> Result<Self, Error> {
+        serde_json::from_str(file).map_err(Error::serialization)
+    }
+
+    /// Split into the zones for [Parser::with_exclusions] and the rules for [Config](crate::Config)
+    pub fn into_parts(self) -> (LinearMap<String, Vec<String>>, Exclusions<Feature>) {
+        (self.zones, Exclusions { rules: self.rules })
+    }
 }
 
 impl Parser {
-    pub fn from_file(file: &str) -> Option<Self> {
-        let config: LinearMap<String, Vec<String>> = serde_json::from_str(file).ok()?;
+    pub fn from_file(file: &str) -> Result<Self, Error> {
+        let config: LinearMap<String, Vec<String>> =
+            serde_json::from_str(file).map_err(Error::serialization)?;
+        Ok(Self {
+            features: Matcher::new(config)?,
+            exclusions: None,
+        })
+    }
+
+    /// Match areas with any of the `zones`' tags as exclusion zones
+    pub fn with_exclusions(self, zones: LinearMap<String, Vec<String>>) -> Result<Self, Error> {
+        Ok(Self {
+            exclusions: Some(Matcher::new(zones)?),
+            ..self
+        })
+    }
 
+    /// Get the id a feature's `[key, value]` pair has in the [dictionary](FeatureParser::dictionary)
+    ///
+    /// The ids number all tags in the config's order.
+    pub fn id(&self, pair: [u32; 2]) -> usize {
+        self.features.id(pair)
+    }
+
+    fn parse<'t>(&self, tags: impl Tags<'t>) -> Option<Feature> {
+        let feature: Feature = tags
+            .into_iter()
+            .filter_map(|(key, value)| self.features.find(key, value))
+            .collect();
+        (!feature.is_empty()).then_some(feature)
+    }
+}
+
+impl Matcher {
+    fn new(config: LinearMap<String, Vec<String>>) -> Result<Self, Error> {
         let mut keys: Vec<_> = config
             .keys()
             .enumerate()
@@ -22,39 +105,78 @@ impl Parser {
             .collect();
         keys.sort_by_key(|(k, _)| *k);
 
-        let mut parser = Self {
-            keys: DoubleArray::new(DoubleArrayBuilder::build(&keys)?),
+        let mut matcher = Self {
+            keys: DoubleArray::new(
+                DoubleArrayBuilder::build(&keys)
+                    .ok_or_else(|| Error::Trie("the config's keys".to_string()))?,
+            ),
             values: Vec::with_capacity(config.values().len()),
+            offsets: Vec::with_capacity(config.values().len()),
+            config: LinearMap::new(),
         };
 
-        for values in config.values() {
+        let mut offset = 0;
+        for (key, values) in config.iter() {
+            matcher.offsets.push(offset);
+            offset += values.len();
+
             let mut values: Vec<_> = values
                 .iter()
                 .enumerate()
                 .map(|(i, v)| (v.as_str(), i as u32))
                 .collect();
             values.sort_by_key(|(v, _)| *v);
-            let values = DoubleArrayBuilder::build(&values)?;
-            parser.values.push(DoubleArray::new(values));
+            let values = DoubleArrayBuilder::build(&values)
+                .ok_or_else(|| Error::Trie(format!("the values of \"{key}\"")))?;
+            matcher.values.push(DoubleArray::new(values));
         }
 
-        Some(parser)
+        matcher.config = config;
+        Ok(matcher)
     }
 
-    fn parse<'t>(&self, tags: impl Tags<'t>) -> Option<Feature> {
-        let mut feature = Vec::new();
-        for (key, value) in tags {
-            if let Some(key) = self.keys.exact_match_search(key) {
-                if let Some(value) = self.values[key as usize].exact_match_search(value) {
-                    feature.push([key, value]);
-                }
+    fn id(&self, [key, value]: [u32; 2]) -> usize {
+        self.offsets[key as usize] + value as usize
+    }
+
+    /// Get a tag's `[key, value]` pair if it is in the config
+    fn find(&self, key: &str, value: &str) -> Option<[u32; 2]> {
+        let key = self.keys.exact_match_search(key)?;
+        let value = self.values[key as usize].exact_match_search(value)?;
+        Some([key, value])
+    }
+
+    /// Name each tag's [id](Matcher::id) as `key=value`
+    fn names(&self) -> BTreeMap<usize, String> {
+        self.config
+            .iter()
+            .flat_map(|(key, values)| values.iter().map(move |value| format!("{key}={value}")))
+            .enumerate()
+            .collect()
+    }
+}
+
+/// Serializes the feature config alone or together with the exclusion zones as `{"features", "exclusions"}`
+impl Serialize for Parser {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct WithExclusions<'p> {
+            features: &'p LinearMap<String, Vec<String>>,
+            exclusions: &'p LinearMap<String, Vec<String>>,
+        }
+
+        match &self.exclusions {
+            None => self.features.config.serialize(serializer),
+            Some(exclusions) => WithExclusions {
+                features: &self.features.config,
+                exclusions: &exclusions.config,
             }
+            .serialize(serializer),
         }
-        (!feature.is_empty()).then_some(feature)
     }
 }
 
-type Feature = Vec<[u32; 2]>;
+pub type Feature = Vec<[u32; 2]>;
 
 impl FeatureParser for Parser {
     type Feature = Feature;
@@ -70,4 +192,85 @@ impl FeatureParser for Parser {
     fn way<'t>(&self, way: impl Tags<'t>) -> Option<Self::Feature> {
         self.parse(way)
     }
+
+    /// The zone's class is the id of its first matching tag
+    fn exclusion<'t>(&self, area: impl Tags<'t>) -> Option<usize> {
+        let exclusions = self.exclusions.as_ref()?;
+        area.into_iter()
+            .find_map(|(key, value)| exclusions.find(key, value))
+            .map(|pair| exclusions.id(pair))
+    }
+
+    /// Name each tag's [id](Parser::id) as `key=value`, the same for all kinds of objects
+    fn dictionary(&self) -> Option<Dictionary> {
+        let names = self.features.names();
+        Some(Dictionary {
+            areas: names.clone(),
+            nodes: names.clone(),
+            ways: names,
+            exclusions: self
+                .exclusions
+                .as_ref()
+                .map(Matcher::names)
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::features::prototyping::{ExclusionConfig, Parser};
+    use crate::features::FeatureParser;
+
+    #[test]
+    fn dictionary() {
+        let parser =
+            Parser::from_file(r#"{"shop": ["bakery", "kiosk"], "amenity": ["bench"]}"#).unwrap();
+
+        let feature = parser
+            .node([("amenity", "bench"), ("shop", "kiosk")])
+            .unwrap();
+        let ids: Vec<_> = feature.into_iter().map(|pair| parser.id(pair)).collect();
+        assert_eq!(ids, vec![2, 1]);
+
+        let dictionary = parser.dictionary().unwrap();
+        let names: Vec<_> = dictionary.nodes.values().map(String::as_str).collect();
+        assert_eq!(names, vec!["shop=bakery", "shop=kiosk", "amenity=bench"]);
+        assert_eq!(dictionary.areas, dictionary.nodes);
+
+        let config = serde_json::to_string(&parser).unwrap();
+        assert_eq!(config, r#"{"shop":["bakery","kiosk"],"amenity":["bench"]}"#);
+    }
+
+    #[test]
+    fn exclusions() {
+        let parser = Parser::from_file(r#"{"amenity": ["bench"]}"#).unwrap();
+        assert_eq!(parser.exclusion([("amenity", "school")]), None);
+
+        let config = ExclusionConfig::from_file(
+            r#"{"zones": {"landuse": ["military"], "amenity": ["kindergarten", "school"]}}"#,
+        )
+        .unwrap();
+        let (zones, exclusions) = config.into_parts();
+        assert!(exclusions.rules.is_empty());
+        let parser = parser.with_exclusions(zones).unwrap();
+
+        assert_eq!(parser.exclusion([("amenity", "bench")]), None);
+        assert_eq!(
+            parser.exclusion([("amenity", "school"), ("landuse", "military")]),
+            Some(2)
+        );
+        let dictionary = parser.dictionary().unwrap();
+        let names: Vec<_> = dictionary.exclusions.values().map(String::as_str).collect();
+        assert_eq!(
+            names,
+            vec!["landuse=military", "amenity=kindergarten", "amenity=school"]
+        );
+
+        let config = serde_json::to_string(&parser).unwrap();
+        assert_eq!(
+            config,
+            r#"{"features":{"amenity":["bench"]},"exclusions":{"landuse":["military"],"amenity":["kindergarten","school"]}}"#
+        );
+    }
 }
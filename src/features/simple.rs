@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use std::hash::Hash;
 
 use crate::features::config::{Ast, Branch, Expr, Lookup};
-use crate::features::{FeatureParser, Tags};
+use crate::features::{Dictionary, FeatureParser, Tags};
 
 impl FeatureParser for Ast<&str> {
     type Feature = usize;
@@ -19,6 +19,14 @@ impl FeatureParser for Ast<&str> {
     fn way<'t>(&self, way: impl Tags<'t>) -> Option<Self::Feature> {
         Self::parse_tags(&self.ways, way)
     }
+
+    fn exclusion<'t>(&self, area: impl Tags<'t>) -> Option<usize> {
+        Self::parse_tags(&self.exclusions, area)
+    }
+
+    fn dictionary(&self) -> Option<Dictionary> {
+        Some(self.aliases.clone())
+    }
 }
 
 impl<'i> Ast<&'i str> {
@@ -3,9 +3,10 @@ use std::collections::HashMap;
 use yada::builder::DoubleArrayBuilder;
 use yada::DoubleArray;
 
+use crate::error::Error;
 use crate::features::config::{Ast, Branch, ConfigParser};
 use crate::features::simple::eval_expr;
-use crate::features::{FeatureParser, Tags};
+use crate::features::{Dictionary, FeatureParser, Tags};
 
 #[derive(Default)]
 pub struct Tokens {
@@ -30,7 +31,7 @@ impl Tokens {
 }
 
 impl Tokens {
-    pub fn finish(self) -> Result<DoubleArray<Vec<u8>>, &'static str> {
+    pub fn finish(self) -> Result<DoubleArray<Vec<u8>>, Error> {
         fn get_first<'t, 's>(tuple: &'t (&'s str, u32)) -> &'s str {
             tuple.0
         }
@@ -43,7 +44,8 @@ impl Tokens {
         keyset.sort_by_key(get_first);
 
         Ok(DoubleArray::new(
-            DoubleArrayBuilder::build(&keyset).ok_or("Couldn't build trie")?,
+            DoubleArrayBuilder::build(&keyset)
+                .ok_or_else(|| Error::Trie("the config's strings".to_string()))?,
         ))
     }
 }
@@ -67,15 +69,23 @@ impl FeatureParser for YadaParser {
     fn way<'t>(&self, way: impl Tags<'t>) -> Option<Self::Feature> {
         self.parse_tags(&self.ast.ways, way)
     }
+
+    fn exclusion<'t>(&self, area: impl Tags<'t>) -> Option<usize> {
+        self.parse_tags(&self.ast.exclusions, area)
+    }
+
+    fn dictionary(&self) -> Option<Dictionary> {
+        Some(self.ast.aliases.clone())
+    }
 }
 
 impl YadaParser {
-    pub fn from_file(file: &str) -> Option<Self> {
+    pub fn from_file(file: &str) -> Result<Self, Error> {
         let mut tokens = Tokens::default();
         let parser = ConfigParser::new(|string| tokens.get_or_insert(string));
-        let ast = parser.parse_file(&file).ok()?;
-        let tokenizer = tokens.finish().ok()?;
-        Some(Self { tokenizer, ast })
+        let ast = parser.parse_file(file)?;
+        let tokenizer = tokens.finish()?;
+        Ok(Self { tokenizer, ast })
     }
 
     fn parse_tags<'t>(&self, statements: &[Branch<u32>], tags: impl Tags<'t>) -> Option<usize> {
@@ -0,0 +1,530 @@
+//! Single file containing many encoded tiles and an index to read them individually
+//!
+//! All numbers are little endian. An archive consists of:
+//!
+//! | Section  | Content                                                                           |
+//! |----------|-----------------------------------------------------------------------------------|
+//! | Header   | magic `RWTA`, version `u32`                                                       |
+//! | Tiles    | the tiles' bytes, each starting at a multiple of 8 bytes                          |
+//! | Metadata | [Metadata]: config hash `u64`, zoom `u8`, projection and crate version as `u32` length and UTF-8 bytes |
+//! | Index    | 32 byte entries sorted by key: `x` and `y` `u32`s, offset and length `u64`s, uncompressed length and compression `u32`s |
+//! | Footer   | metadata offset and length `u64`s, index offset `u64`, number of entries `u32`, magic |
+//!
+//! Uncompressed tiles are aligned, so a mmapped archive can be read with
+//! [TileReader](crate::formats::binary::TileReader) without copying.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::formats::binary::AlignedBytes;
+use crate::formats::mvt::tile_position;
+use crate::formats::Tile;
+
+/// Identifies the archive format
+pub const MAGIC: [u8; 4] = *b"RWTA";
+
+/// Current version of the layout
+///
+/// Version 1 stored the metadata as JSON.
+pub const VERSION: u32 = 2;
+
+const HEADER_SIZE: u64 = 8;
+const ENTRY_SIZE: usize = 32;
+const FOOTER_SIZE: usize = 32;
+
+/// Upper bound of deflate's compression ratio, limits the memory a corrupt entry can request
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+/// Position of a tile in the slippy map scheme of the archive's zoom level
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TileKey {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileKey {
+    /// Get a tile's key at a zoom level, see [tile_position]
+    ///
+    /// Tiles outside the zoom level's range have no key, since several of them would share one.
+    pub fn of<F>(tile: &Tile<F>, zoom: u8) -> Result<Self, Error> {
+        let (x, y) = tile_position(tile, zoom).ok_or_else(|| {
+            Error::InvalidConfig(format!(
+                "The tile at ({}, {}) lies outside the map at zoom {zoom}",
+                tile.min.x, tile.min.y
+            ))
+        })?;
+        Ok(TileKey { x, y })
+    }
+}
+
+/// Describes how an archive was generated
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Metadata {
+    /// Hash of the config used to generate the tiles, see [config_hash]
+    pub config_hash: u64,
+
+    pub zoom: u8,
+
+    /// The projection's [name](crate::projection::Projection::name)
+    pub projection: String,
+
+    /// Version of the crate which wrote the archive
+    pub crate_version: String,
+}
+
+impl Metadata {
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.config_hash.to_le_bytes());
+        bytes.push(self.zoom);
+        for string in [&self.projection, &self.crate_version] {
+            let length = u32::try_from(string.len())
+                .map_err(|_| Error::Format("the metadata is too large".to_string()))?;
+            bytes.extend_from_slice(&length.to_le_bytes());
+            bytes.extend_from_slice(string.as_bytes());
+        }
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let truncated = || Error::Format("the metadata is truncated".to_string());
+        if bytes.len() < 9 {
+            return Err(truncated());
+        }
+        let mut offset = 9;
+        let mut string = || {
+            let length = read_u32(bytes.get(offset..offset + 4).ok_or_else(truncated)?, 0);
+            let start = offset + 4;
+            let end = start.checked_add(length as usize).ok_or_else(truncated)?;
+            let string = bytes.get(start..end).ok_or_else(truncated)?;
+            offset = end;
+            String::from_utf8(string.to_vec())
+                .map_err(|_| Error::Format("the metadata isn't valid UTF-8".to_string()))
+        };
+        let projection = string()?;
+        let crate_version = string()?;
+        Ok(Metadata {
+            config_hash: read_u64(bytes, 0),
+            zoom: bytes[8],
+            projection,
+            crate_version,
+        })
+    }
+}
+
+/// How a single tile is compressed
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+
+    /// Raw deflate stream, requires the `compression` feature
+    Deflate,
+}
+impl Compression {
+    fn to_u32(self) -> u32 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+        }
+    }
+
+    fn from_u32(value: u32) -> Result<Self, Error> {
+        match value {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            _ => Err(Error::Format(format!("unknown compression {value}"))),
+        }
+    }
+
+    fn compress(self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            #[cfg(feature = "compression")]
+            Compression::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                Ok(encoder.finish()?)
+            }
+            #[cfg(not(feature = "compression"))]
+            Compression::Deflate => Err(no_compression()),
+        }
+    }
+
+    fn decompress(self, bytes: &[u8], output: &mut [u8]) -> Result<(), Error> {
+        match self {
+            Compression::None => output.copy_from_slice(bytes),
+            #[cfg(feature = "compression")]
+            Compression::Deflate => {
+                flate2::read::DeflateDecoder::new(bytes).read_exact(output)?;
+            }
+            #[cfg(not(feature = "compression"))]
+            Compression::Deflate => return Err(no_compression()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn no_compression() -> Error {
+    Error::Format("deflate requires the `compression` feature".to_string())
+}
+
+/// Hash a config's bytes using [FNV-1a](https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function)
+///
+/// Unlike std's hashers, the result is stable across versions and platforms.
+pub fn config_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Entry {
+    key: TileKey,
+    offset: u64,
+    length: u64,
+    raw_length: u32,
+    compression: Compression,
+}
+
+/// Writes tiles into an archive
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    compression: Compression,
+    index: Vec<Entry>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Start an archive compressing each tile with `compression`
+    pub fn new(mut writer: W, compression: Compression) -> Result<Self, Error> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(ArchiveWriter {
+            writer,
+            offset: HEADER_SIZE,
+            compression,
+            index: Vec::new(),
+        })
+    }
+
+    /// Add an encoded tile
+    ///
+    /// Fails for tiles of 4 GiB or more, whose length doesn't fit into the index.
+    pub fn add(&mut self, key: TileKey, bytes: &[u8]) -> Result<(), Error> {
+        let raw_length = u32::try_from(bytes.len()).map_err(|_| {
+            Error::Format(format!(
+                "tile {key:?} is too large with {} bytes",
+                bytes.len()
+            ))
+        })?;
+        let stored = self.compression.compress(bytes)?;
+        self.index.push(Entry {
+            key,
+            offset: self.offset,
+            length: stored.len() as u64,
+            raw_length,
+            compression: self.compression,
+        });
+        self.write(&stored)?;
+        self.pad()
+    }
+
+    /// Write the metadata and index and return the inner writer
+    pub fn finish(mut self, metadata: &Metadata) -> Result<W, Error> {
+        let entries = u32::try_from(self.index.len())
+            .map_err(|_| Error::Format(format!("too many tiles: {}", self.index.len())))?;
+        self.index.sort_unstable_by_key(|entry| entry.key);
+        if let Some(entries) = self.index.windows(2).find(|e| e[0].key == e[1].key) {
+            return Err(Error::Format(format!(
+                "tile {:?} was added twice",
+                entries[0].key
+            )));
+        }
+
+        let metadata = metadata.encode()?;
+        let metadata_offset = self.offset;
+        self.write(&metadata)?;
+        self.pad()?;
+
+        let index_offset = self.offset;
+        let mut index = Vec::with_capacity(self.index.len() * ENTRY_SIZE);
+        for entry in self.index.iter() {
+            index.extend_from_slice(&entry.key.x.to_le_bytes());
+            index.extend_from_slice(&entry.key.y.to_le_bytes());
+            index.extend_from_slice(&entry.offset.to_le_bytes());
+            index.extend_from_slice(&entry.length.to_le_bytes());
+            index.extend_from_slice(&entry.raw_length.to_le_bytes());
+            index.extend_from_slice(&entry.compression.to_u32().to_le_bytes());
+        }
+        self.write(&index)?;
+
+        let mut footer = Vec::with_capacity(FOOTER_SIZE);
+        footer.extend_from_slice(&metadata_offset.to_le_bytes());
+        footer.extend_from_slice(&(metadata.len() as u64).to_le_bytes());
+        footer.extend_from_slice(&index_offset.to_le_bytes());
+        footer.extend_from_slice(&entries.to_le_bytes());
+        footer.extend_from_slice(&MAGIC);
+        self.write(&footer)?;
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn pad(&mut self) -> Result<(), Error> {
+        let padding = self.offset.next_multiple_of(8) - self.offset;
+        self.write(&[0; 8][..padding as usize])
+    }
+}
+
+/// Reads single tiles from an archive
+pub struct ArchiveReader<R: Read + Seek> {
+    reader: R,
+    metadata: Metadata,
+    index: Vec<Entry>,
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Read an archive's metadata and index
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut header = [0; HEADER_SIZE as usize];
+        reader.rewind()?;
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(Error::Format("not a tile archive".to_string()));
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::Format(format!(
+                "unsupported archive version {version}, expected {VERSION}"
+            )));
+        }
+
+        // The lengths are checked against the file's size before allocating any buffers
+        let size = reader.seek(SeekFrom::End(0))?;
+        if size < HEADER_SIZE + FOOTER_SIZE as u64 {
+            return Err(Error::Format("the archive is truncated".to_string()));
+        }
+        let end = size - FOOTER_SIZE as u64;
+
+        let mut footer = [0; FOOTER_SIZE];
+        reader.seek(SeekFrom::Start(end))?;
+        reader.read_exact(&mut footer)?;
+        if footer[28..] != MAGIC {
+            return Err(Error::Format("the archive is truncated".to_string()));
+        }
+        let metadata_offset = read_u64(&footer, 0);
+        let metadata_length = read_u64(&footer, 8);
+        let index_offset = read_u64(&footer, 16);
+        let entries = read_u32(&footer, 24) as usize;
+        check_section("metadata", metadata_offset, metadata_length, end)?;
+        check_section("index", index_offset, (entries * ENTRY_SIZE) as u64, end)?;
+
+        let mut metadata = vec![0; metadata_length as usize];
+        reader.seek(SeekFrom::Start(metadata_offset))?;
+        reader.read_exact(&mut metadata)?;
+        let metadata = Metadata::decode(&metadata)?;
+
+        let mut index = vec![0; entries * ENTRY_SIZE];
+        reader.seek(SeekFrom::Start(index_offset))?;
+        reader.read_exact(&mut index)?;
+        let index = index
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| {
+                let entry = Entry {
+                    key: TileKey {
+                        x: read_u32(entry, 0),
+                        y: read_u32(entry, 4),
+                    },
+                    offset: read_u64(entry, 8),
+                    length: read_u64(entry, 16),
+                    raw_length: read_u32(entry, 24),
+                    compression: Compression::from_u32(read_u32(entry, 28))?,
+                };
+                check_section("tile", entry.offset, entry.length, end)?;
+                let raw_length = entry.raw_length as u64;
+                let valid = match entry.compression {
+                    Compression::None => raw_length == entry.length,
+                    Compression::Deflate => raw_length <= entry.length * MAX_DEFLATE_RATIO,
+                };
+                if !valid {
+                    return Err(Error::Format(format!(
+                        "tile {:?} has an invalid length",
+                        entry.key
+                    )));
+                }
+                Ok(entry)
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(ArchiveReader {
+            reader,
+            metadata,
+            index,
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Iterate over the keys of all tiles in ascending order
+    pub fn keys(&self) -> impl Iterator<Item = TileKey> + '_ {
+        self.index.iter().map(|entry| entry.key)
+    }
+
+    /// Read and decompress a tile, `None` if the archive doesn't contain it
+    pub fn read(&mut self, key: TileKey) -> Result<Option<AlignedBytes>, Error> {
+        let Ok(index) = self.index.binary_search_by_key(&key, |entry| entry.key) else {
+            return Ok(None);
+        };
+        let entry = self.index[index];
+
+        let mut stored = vec![0; entry.length as usize];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        self.reader.read_exact(&mut stored)?;
+
+        let mut tile = AlignedBytes::zeroed(entry.raw_length as usize);
+        entry.compression.decompress(&stored, tile.as_bytes_mut())?;
+        Ok(Some(tile))
+    }
+}
+
+/// Check that a section lies between the header and `end`
+fn check_section(name: &str, offset: u64, length: u64, end: u64) -> Result<(), Error> {
+    match offset.checked_add(length) {
+        Some(section_end) if offset >= HEADER_SIZE && section_end <= end => Ok(()),
+        _ => Err(Error::Format(format!(
+            "the {name} lies outside of the archive"
+        ))),
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::formats::archive::{
+        config_hash, ArchiveReader, ArchiveWriter, Compression, Metadata, TileKey, HEADER_SIZE,
+    };
+    use crate::formats::binary::encode;
+    use crate::formats::Tile;
+    use crate::geometry::{BBox, Point};
+
+    fn archive(compression: Compression) {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(1.0, 1.0),
+        });
+        tile.add_node(Point::new(0.5, 0.5), 3usize, 42);
+        let encoded = encode(&tile, |feature| feature.to_le_bytes().to_vec());
+
+        let metadata = Metadata {
+            config_hash: config_hash(b"[Nodes]"),
+            zoom: 1,
+            projection: "web_mercator".to_string(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        };
+        let mut writer = ArchiveWriter::new(Vec::new(), compression).unwrap();
+        writer.add(TileKey { x: 1, y: 0 }, &encoded).unwrap();
+        writer.add(TileKey { x: 0, y: 1 }, b"other").unwrap();
+        let archive = writer.finish(&metadata).unwrap();
+
+        let mut reader = ArchiveReader::new(Cursor::new(archive)).unwrap();
+        assert_eq!(reader.metadata(), &metadata);
+        assert_eq!(
+            reader.keys().collect::<Vec<_>>(),
+            vec![TileKey { x: 0, y: 1 }, TileKey { x: 1, y: 0 }]
+        );
+        assert!(reader.read(TileKey { x: 1, y: 1 }).unwrap().is_none());
+        assert_eq!(
+            reader
+                .read(TileKey { x: 0, y: 1 })
+                .unwrap()
+                .unwrap()
+                .as_bytes(),
+            b"other"
+        );
+
+        let tile = reader.read(TileKey { x: 1, y: 0 }).unwrap().unwrap();
+        let tile = tile.reader().unwrap();
+        let node = tile.iter_nodes().next().unwrap();
+        assert_eq!((node.points, node.oid), (&Point::new(0.5, 0.5), 42));
+    }
+
+    #[test]
+    fn uncompressed() {
+        archive(Compression::None);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn deflate() {
+        archive(Compression::Deflate);
+    }
+
+    #[test]
+    fn reject_invalid_lengths() {
+        let mut writer = ArchiveWriter::new(Vec::new(), Compression::None).unwrap();
+        writer.add(TileKey { x: 0, y: 0 }, b"tile").unwrap();
+        let metadata = Metadata {
+            config_hash: 0,
+            zoom: 0,
+            projection: String::new(),
+            crate_version: String::new(),
+        };
+        let archive = writer.finish(&metadata).unwrap();
+        assert!(ArchiveReader::new(Cursor::new(archive.clone())).is_ok());
+
+        let footer = archive.len() - 32;
+        let index = footer - 32;
+        let corrupt = |offset: usize, bytes: &[u8]| {
+            let mut archive = archive.clone();
+            archive[offset..offset + bytes.len()].copy_from_slice(bytes);
+            ArchiveReader::new(Cursor::new(archive)).is_err()
+        };
+        // Metadata length, number of entries, a tile's length and uncompressed length
+        assert!(corrupt(footer + 8, &u64::MAX.to_le_bytes()));
+        assert!(corrupt(footer + 24, &u32::MAX.to_le_bytes()));
+        assert!(corrupt(index + 16, &(1u64 << 40).to_le_bytes()));
+        assert!(corrupt(index + 24, &u32::MAX.to_le_bytes()));
+        // The projection's length
+        assert!(corrupt(
+            HEADER_SIZE as usize + 8 + 9,
+            &u32::MAX.to_le_bytes()
+        ));
+        assert!(ArchiveReader::new(Cursor::new(&archive[..20])).is_err());
+    }
+
+    #[test]
+    fn duplicate_key() {
+        let mut writer = ArchiveWriter::new(Vec::new(), Compression::None).unwrap();
+        writer.add(TileKey { x: 0, y: 0 }, b"a").unwrap();
+        writer.add(TileKey { x: 0, y: 0 }, b"b").unwrap();
+        let metadata = Metadata {
+            config_hash: 0,
+            zoom: 0,
+            projection: String::new(),
+            crate_version: String::new(),
+        };
+        assert!(writer.finish(&metadata).is_err());
+    }
+}
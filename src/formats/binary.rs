@@ -0,0 +1,590 @@
+//! Compact binary layout of a [Tile] which can be read without copying
+//!
+//! All numbers are little endian. A tile consists of:
+//!
+//! | Section          | Content                                                                        |
+//! |------------------|--------------------------------------------------------------------------------|
+//! | Header           | magic `RWTB`, version `u32`, `min` and `max` as `f64`s, 9 `u32` counts, 4 unused bytes (80 bytes) |
+//! | Feature offsets  | `features + 1` `u32`s delimiting each feature in the feature data              |
+//! | Feature data     | The encoded features, padded to 8 bytes                                        |
+//! | Areas            | 24 byte records: feature `u32`, start and end ring `u32`s, 4 unused bytes, oid `u64` |
+//! | Nodes            | same records with the point's index as start and `start + 1` as end            |
+//! | Ways             | same records with a range of points                                            |
+//! | Rings            | start and end point as `u32`s                                                  |
+//! | Points           | `x` and `y` as `f64`s                                                          |
+//! | Zones            | item records of the [exclusion zones](crate::exclusion) with their class as feature |
+//! | Zone rings       | same as rings but into the zone points                                         |
+//! | Zone points      | same as points                                                                 |
+//!
+//! Every section starts at a multiple of 8 bytes.
+//! So if the whole buffer is 8 byte aligned (e.g. when it's mmapped),
+//! the points can be borrowed as `&[Point]` directly.
+//!
+//! Features are stored once per tile and encoded by the caller,
+//! so the reader hands out each item's feature as the bytes it was encoded to.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::mem::{align_of, size_of};
+
+use crate::error::Error;
+use crate::exclusion::ExclusionMask;
+use crate::formats::{Item, Tile};
+use crate::geometry::polygon::contains_point;
+use crate::geometry::Point;
+
+/// Identifies the binary tile format
+pub const MAGIC: [u8; 4] = *b"RWTB";
+
+/// Current version of the layout
+pub const VERSION: u32 = 2;
+
+const HEADER_SIZE: usize = 80;
+const ITEM_SIZE: usize = 24;
+const RING_SIZE: usize = 8;
+const POINT_SIZE: usize = 16;
+
+/// Encode a tile using `feature` to encode its features
+pub fn encode<F>(tile: &Tile<F>, feature: impl Fn(&F) -> Vec<u8>) -> Vec<u8> {
+    match try_encode(tile, |f| Ok::<_, Infallible>(feature(f))) {
+        Ok(encoded) => encoded,
+        Err(never) => match never {},
+    }
+}
+
+/// Encode a tile using `feature` to encode its features, stopping at the first error
+pub fn try_encode<F, E>(
+    tile: &Tile<F>,
+    feature: impl Fn(&F) -> Result<Vec<u8>, E>,
+) -> Result<Vec<u8>, E> {
+    // Build the feature table
+    let mut offsets = vec![0u32];
+    let mut data = Vec::new();
+    let mut indexes = HashMap::new();
+    let mut feature_index = |f: &F| -> Result<u32, E> {
+        let encoded = feature(f)?;
+        Ok(*indexes.entry(encoded).or_insert_with_key(|encoded| {
+            data.extend_from_slice(encoded);
+            offsets.push(data.len() as u32);
+            offsets.len() as u32 - 2
+        }))
+    };
+    let areas = tile
+        .areas
+        .iter()
+        .map(|item| Ok((feature_index(&item.feature)?, item.oid, item.points)))
+        .collect::<Result<Vec<_>, E>>()?;
+    let nodes = tile
+        .nodes
+        .iter()
+        .map(|item| {
+            Ok((
+                feature_index(&item.feature)?,
+                item.oid,
+                (item.points, item.points + 1),
+            ))
+        })
+        .collect::<Result<Vec<_>, E>>()?;
+    let ways = tile
+        .ways
+        .iter()
+        .map(|item| Ok((feature_index(&item.feature)?, item.oid, item.points)))
+        .collect::<Result<Vec<_>, E>>()?;
+
+    let mut output = Vec::new();
+    output.extend_from_slice(&MAGIC);
+    output.extend_from_slice(&VERSION.to_le_bytes());
+    for value in [tile.min.x, tile.min.y, tile.max.x, tile.max.y] {
+        output.extend_from_slice(&value.to_le_bytes());
+    }
+    let ExclusionMask {
+        zones: zone_items,
+        rings: zone_rings,
+        points: zone_points,
+    } = &tile.exclusions;
+    for count in [
+        offsets.len() - 1,
+        areas.len(),
+        nodes.len(),
+        ways.len(),
+        tile.rings.len(),
+        tile.points.len(),
+        zone_items.len(),
+        zone_rings.len(),
+        zone_points.len(),
+    ] {
+        output.extend_from_slice(&(count as u32).to_le_bytes());
+    }
+    pad(&mut output);
+
+    for offset in offsets.iter() {
+        output.extend_from_slice(&offset.to_le_bytes());
+    }
+    output.extend_from_slice(&data);
+    pad(&mut output);
+
+    for record in areas.into_iter().chain(nodes).chain(ways) {
+        push_record(&mut output, record);
+    }
+    push_rings(&mut output, &tile.rings);
+    push_points(&mut output, &tile.points);
+
+    for zone in zone_items.iter() {
+        push_record(&mut output, (zone.feature as u32, zone.oid, zone.points));
+    }
+    push_rings(&mut output, zone_rings);
+    push_points(&mut output, zone_points);
+
+    Ok(output)
+}
+
+/// Append an item record given as feature, oid and range
+fn push_record(output: &mut Vec<u8>, (feature, oid, (start, end)): (u32, usize, (usize, usize))) {
+    output.extend_from_slice(&feature.to_le_bytes());
+    output.extend_from_slice(&(start as u32).to_le_bytes());
+    output.extend_from_slice(&(end as u32).to_le_bytes());
+    output.extend_from_slice(&[0; 4]);
+    output.extend_from_slice(&(oid as u64).to_le_bytes());
+}
+
+/// Append ring records and pad them for the following points
+fn push_rings(output: &mut Vec<u8>, rings: &[(usize, usize)]) {
+    for &(start, end) in rings.iter() {
+        output.extend_from_slice(&(start as u32).to_le_bytes());
+        output.extend_from_slice(&(end as u32).to_le_bytes());
+    }
+    pad(output);
+}
+
+fn push_points(output: &mut Vec<u8>, points: &[Point]) {
+    for point in points.iter() {
+        output.extend_from_slice(&point.x.to_le_bytes());
+        output.extend_from_slice(&point.y.to_le_bytes());
+    }
+}
+
+/// Pad a buffer with zeros to a multiple of 8 bytes
+fn pad(buffer: &mut Vec<u8>) {
+    buffer.resize(buffer.len().next_multiple_of(8), 0);
+}
+
+/// Borrowing view of an encoded tile
+#[derive(Copy, Clone, Debug)]
+pub struct TileReader<'b> {
+    pub min: Point,
+    pub max: Point,
+
+    feature_offsets: &'b [u8],
+    feature_data: &'b [u8],
+    areas: &'b [u8],
+    nodes: &'b [u8],
+    ways: &'b [u8],
+    rings: &'b [u8],
+    points: &'b [Point],
+
+    zones: &'b [u8],
+    zone_rings: &'b [u8],
+    zone_points: &'b [Point],
+}
+
+impl<'b> TileReader<'b> {
+    /// Check an encoded tile's header and ranges and borrow its sections
+    ///
+    /// `bytes` has to be 8 byte aligned and the machine little endian,
+    /// to borrow the points without copying them.
+    pub fn new(bytes: &'b [u8]) -> Result<Self, Error> {
+        fn error(message: &str) -> Error {
+            Error::Format(message.to_string())
+        }
+
+        if bytes.len() < HEADER_SIZE || bytes[..4] != MAGIC {
+            return Err(error("not a binary tile"));
+        }
+        let version = read_u32(bytes, 4);
+        if version != VERSION {
+            return Err(Error::Format(format!(
+                "unsupported version {version}, expected {VERSION}"
+            )));
+        }
+        let min = Point::new(read_f64(bytes, 8), read_f64(bytes, 16));
+        let max = Point::new(read_f64(bytes, 24), read_f64(bytes, 32));
+        let [features, areas, nodes, ways, rings, points, zones, zone_rings, zone_points] =
+            [40, 44, 48, 52, 56, 60, 64, 68, 72].map(|offset| read_u32(bytes, offset) as usize);
+
+        let mut sections = Sections {
+            bytes,
+            offset: HEADER_SIZE,
+        };
+        let feature_offsets = sections.take((features + 1) * 4)?;
+        let data_size = read_u32(feature_offsets, features * 4) as usize;
+        let feature_data = sections.take(data_size)?;
+        sections.align();
+        let area_bytes = sections.take(areas * ITEM_SIZE)?;
+        let node_bytes = sections.take(nodes * ITEM_SIZE)?;
+        let way_bytes = sections.take(ways * ITEM_SIZE)?;
+        let ring_bytes = sections.take(rings * RING_SIZE)?;
+        sections.align();
+        let point_bytes = sections.take(points * POINT_SIZE)?;
+        let zone_bytes = sections.take(zones * ITEM_SIZE)?;
+        let zone_ring_bytes = sections.take(zone_rings * RING_SIZE)?;
+        sections.align();
+        let zone_point_bytes = sections.take(zone_points * POINT_SIZE)?;
+
+        let points = borrow_points(point_bytes)?;
+        let zone_points = borrow_points(zone_point_bytes)?;
+
+        // Check all ranges once, so the iterators can't panic
+        for index in 0..features {
+            let start = read_u32(feature_offsets, index * 4);
+            let end = read_u32(feature_offsets, index * 4 + 4);
+            if start > end || end as usize > feature_data.len() {
+                return Err(error("feature offsets out of range"));
+            }
+        }
+        // A node's range has to contain exactly its point
+        for (section, max_index, single) in [
+            (area_bytes, rings, false),
+            (node_bytes, points.len(), true),
+            (way_bytes, points.len(), false),
+        ] {
+            for record in section.chunks_exact(ITEM_SIZE) {
+                let (feature, start, end) = (
+                    read_u32(record, 0) as usize,
+                    read_u32(record, 4) as usize,
+                    read_u32(record, 8) as usize,
+                );
+                let valid = if single {
+                    end == start + 1
+                } else {
+                    start <= end
+                };
+                if feature >= features || !valid || end > max_index {
+                    return Err(error("item out of range"));
+                }
+            }
+        }
+        for (section, max_index) in [
+            (ring_bytes, points.len()),
+            (zone_ring_bytes, zone_points.len()),
+        ] {
+            for record in section.chunks_exact(RING_SIZE) {
+                let (start, end) = (read_u32(record, 0), read_u32(record, 4));
+                if start > end || end as usize > max_index {
+                    return Err(error("ring out of range"));
+                }
+            }
+        }
+        for record in zone_bytes.chunks_exact(ITEM_SIZE) {
+            let (start, end) = (read_u32(record, 4), read_u32(record, 8));
+            if start > end || end as usize > zone_rings {
+                return Err(error("zone out of range"));
+            }
+        }
+
+        Ok(TileReader {
+            min,
+            max,
+            feature_offsets,
+            feature_data,
+            areas: area_bytes,
+            nodes: node_bytes,
+            ways: way_bytes,
+            rings: ring_bytes,
+            points,
+            zones: zone_bytes,
+            zone_rings: zone_ring_bytes,
+            zone_points,
+        })
+    }
+
+    /// Get the encoded feature with an index into the feature table
+    pub fn feature(&self, index: usize) -> &'b [u8] {
+        let start = read_u32(self.feature_offsets, index * 4) as usize;
+        let end = read_u32(self.feature_offsets, index * 4 + 4) as usize;
+        &self.feature_data[start..end]
+    }
+
+    /// Get all points
+    pub fn points(&self) -> &'b [Point] {
+        self.points
+    }
+
+    pub fn iter_areas(&self) -> impl Iterator<Item = Item<&'b [u8], Rings<'b>>> + 'b {
+        let this = *self;
+        iter_records(self.areas).map(move |(feature, oid, start, end)| Item {
+            feature: this.feature(feature),
+            oid,
+            points: Rings {
+                points: this.points,
+                rings: &this.rings[start * RING_SIZE..end * RING_SIZE],
+            },
+        })
+    }
+
+    pub fn iter_nodes(&self) -> impl Iterator<Item = Item<&'b [u8], &'b Point>> + 'b {
+        let this = *self;
+        iter_records(self.nodes).map(move |(feature, oid, start, _)| Item {
+            feature: this.feature(feature),
+            oid,
+            points: &this.points[start],
+        })
+    }
+
+    pub fn iter_ways(&self) -> impl Iterator<Item = Item<&'b [u8], &'b [Point]>> + 'b {
+        let this = *self;
+        iter_records(self.ways).map(move |(feature, oid, start, end)| Item {
+            feature: this.feature(feature),
+            oid,
+            points: &this.points[start..end],
+        })
+    }
+
+    /// Iterate over the exclusion zones, whose feature is their class
+    pub fn iter_zones(&self) -> impl Iterator<Item = Item<usize, Rings<'b>>> + 'b {
+        let this = *self;
+        iter_records(self.zones).map(move |(class, oid, start, end)| Item {
+            feature: class,
+            oid,
+            points: Rings {
+                points: this.zone_points,
+                rings: &this.zone_rings[start * RING_SIZE..end * RING_SIZE],
+            },
+        })
+    }
+}
+
+/// Borrow a section's bytes as points
+fn borrow_points(bytes: &[u8]) -> Result<&[Point], Error> {
+    if cfg!(target_endian = "big") {
+        return Err(Error::Format(
+            "borrowing points requires a little endian machine".to_string(),
+        ));
+    }
+    if bytes.as_ptr().align_offset(align_of::<Point>()) != 0 {
+        return Err(Error::Format("the buffer isn't 8 byte aligned".to_string()));
+    }
+    debug_assert_eq!(size_of::<Point>(), POINT_SIZE);
+    // SAFETY: `Point` is a `#[repr(C)]` pair of `f64`s without padding,
+    // the bytes are aligned, in bounds and any bit pattern is a valid `f64`.
+    Ok(unsafe {
+        std::slice::from_raw_parts(bytes.as_ptr() as *const Point, bytes.len() / POINT_SIZE)
+    })
+}
+
+/// An area's rings borrowed from a [TileReader]
+///
+/// Same as [formats::Rings](crate::formats::Rings) but for the binary layout.
+#[derive(Copy, Clone, Debug)]
+pub struct Rings<'b> {
+    points: &'b [Point],
+    rings: &'b [u8],
+}
+impl<'b> Rings<'b> {
+    /// Get the outer ring
+    pub fn outer(&self) -> &'b [Point] {
+        self.iter().next().unwrap_or(&[])
+    }
+
+    /// Iterate over the inner rings i.e. the holes
+    pub fn inner(&self) -> impl Iterator<Item = &'b [Point]> + 'b {
+        self.iter().skip(1)
+    }
+
+    /// Iterate over all rings starting with the outer one
+    pub fn iter(&self) -> impl Iterator<Item = &'b [Point]> + 'b {
+        let points = self.points;
+        self.rings.chunks_exact(RING_SIZE).map(move |ring| {
+            let start = read_u32(ring, 0) as usize;
+            let end = read_u32(ring, 4) as usize;
+            &points[start..end]
+        })
+    }
+
+    /// Check whether a point lies inside the outer ring but outside all holes
+    pub fn contains(&self, point: Point) -> bool {
+        contains_point(self.outer(), point) && !self.inner().any(|ring| contains_point(ring, point))
+    }
+}
+
+/// Owned buffer which is 8 byte aligned as required by [TileReader]
+#[derive(Clone, Debug, Default)]
+pub struct AlignedBytes {
+    words: Vec<u64>,
+    len: usize,
+}
+impl AlignedBytes {
+    /// Create a zeroed buffer
+    pub fn zeroed(len: usize) -> Self {
+        AlignedBytes {
+            words: vec![0; len.div_ceil(8)],
+            len,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: `u8` has no alignment requirement and the words cover `len` bytes
+        unsafe { std::slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        // SAFETY: see `as_bytes` and any byte pattern is a valid `u64`
+        unsafe { std::slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.len) }
+    }
+
+    /// Read the buffer as tile
+    pub fn reader(&self) -> Result<TileReader<'_>, Error> {
+        TileReader::new(self.as_bytes())
+    }
+}
+impl From<&[u8]> for AlignedBytes {
+    fn from(bytes: &[u8]) -> Self {
+        let mut aligned = AlignedBytes::zeroed(bytes.len());
+        aligned.as_bytes_mut().copy_from_slice(bytes);
+        aligned
+    }
+}
+
+/// Consecutive sections of a buffer
+struct Sections<'b> {
+    bytes: &'b [u8],
+    offset: usize,
+}
+impl<'b> Sections<'b> {
+    fn take(&mut self, size: usize) -> Result<&'b [u8], Error> {
+        let section = self
+            .bytes
+            .get(self.offset..self.offset + size)
+            .ok_or_else(|| Error::Format("the tile is truncated".to_string()))?;
+        self.offset += size;
+        Ok(section)
+    }
+
+    fn align(&mut self) {
+        self.offset = self.offset.next_multiple_of(8);
+    }
+}
+
+/// Iterate over item records yielding their feature, oid and range
+fn iter_records(bytes: &[u8]) -> impl Iterator<Item = (usize, usize, usize, usize)> + '_ {
+    bytes.chunks_exact(ITEM_SIZE).map(|record| {
+        (
+            read_u32(record, 0) as usize,
+            read_u64(record, 16) as usize,
+            read_u32(record, 4) as usize,
+            read_u32(record, 8) as usize,
+        )
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buffer = [0; 4];
+    buffer.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buffer)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buffer = [0; 8];
+    buffer.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buffer)
+}
+
+fn read_f64(bytes: &[u8], offset: usize) -> f64 {
+    f64::from_bits(read_u64(bytes, offset))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::formats::binary::{encode, AlignedBytes, TileReader, HEADER_SIZE, VERSION};
+    use crate::formats::Tile;
+    use crate::geometry::{BBox, Point};
+
+    #[test]
+    fn round_trip() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(4.0, 4.0),
+        });
+        let outer = [
+            Point::new(0.0, 0.0),
+            Point::new(4.0, 0.0),
+            Point::new(4.0, 4.0),
+            Point::new(0.0, 4.0),
+        ];
+        let hole = [
+            Point::new(1.0, 1.0),
+            Point::new(3.0, 1.0),
+            Point::new(3.0, 3.0),
+        ];
+        tile.add_area(&outer, 1usize, 10);
+        tile.add_hole(&hole);
+        tile.add_node(Point::new(2.0, 0.5), 2, 20);
+        tile.add_way(&outer[..2], 1, 30);
+        tile.exclusions.add_zone(&hole, 5, 40);
+
+        let encoded = encode(&tile, |feature| feature.to_le_bytes().to_vec());
+        let buffer = AlignedBytes::from(&encoded[..]);
+        let reader = buffer.reader().unwrap();
+        assert_eq!(reader.max, Point::new(4.0, 4.0));
+
+        let areas: Vec<_> = reader.iter_areas().collect();
+        assert_eq!(areas.len(), 1);
+        assert_eq!(areas[0].feature, &1usize.to_le_bytes());
+        assert_eq!(areas[0].oid, 10);
+        assert_eq!(areas[0].points.outer(), &outer);
+        assert_eq!(areas[0].points.inner().collect::<Vec<_>>(), vec![&hole]);
+        assert!(areas[0].points.contains(Point::new(0.5, 0.5)));
+
+        let nodes: Vec<_> = reader.iter_nodes().collect();
+        assert_eq!(nodes[0].points, &Point::new(2.0, 0.5));
+        assert_eq!(nodes[0].feature, &2usize.to_le_bytes());
+
+        let ways: Vec<_> = reader.iter_ways().collect();
+        assert_eq!(ways[0].points, &outer[..2]);
+        assert_eq!(ways[0].oid, 30);
+        // Equal features are only stored once
+        assert_eq!(ways[0].feature.as_ptr(), areas[0].feature.as_ptr());
+
+        let zones: Vec<_> = reader.iter_zones().collect();
+        assert_eq!((zones[0].feature, zones[0].oid), (5, 40));
+        assert_eq!(zones[0].points.outer(), &hole);
+    }
+
+    #[test]
+    fn reject_invalid() {
+        let tile: Tile<usize> = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(1.0, 1.0),
+        });
+        let mut encoded = encode(&tile, |_| Vec::new());
+        let buffer = AlignedBytes::from(&encoded[..]);
+        assert!(buffer.reader().is_ok());
+        assert!(TileReader::new(&buffer.as_bytes()[..encoded.len() - 1]).is_err());
+
+        encoded[4] = VERSION as u8 + 1;
+        assert!(AlignedBytes::from(&encoded[..]).reader().is_err());
+    }
+
+    #[test]
+    fn reject_node_out_of_range() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(1.0, 1.0),
+        });
+        tile.add_node(Point::new(0.5, 0.5), 1usize, 10);
+        let mut encoded = encode(&tile, |_| Vec::new());
+        assert!(AlignedBytes::from(&encoded[..]).reader().is_ok());
+
+        // The node record follows the header and the two offsets of the single, empty feature
+        let record = HEADER_SIZE + 2 * 4;
+        assert_eq!(encoded[record + 4..record + 12], [0, 0, 0, 0, 1, 0, 0, 0]);
+
+        // An empty range past the only point
+        encoded[record + 4] = 1;
+        assert!(AlignedBytes::from(&encoded[..]).reader().is_err());
+
+        // A range of two points
+        encoded[record + 4] = 0;
+        encoded[record + 8] = 2;
+        assert!(AlignedBytes::from(&encoded[..]).reader().is_err());
+    }
+}
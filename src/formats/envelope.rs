@@ -0,0 +1,212 @@
+//! Top-level wrapper around serialized tiles describing how they were generated
+//!
+//! Readers should deserialize an [Envelope] and call [Envelope::check]
+//! and [Envelope::check_payload] (or just [Envelope::into_tiles]) before using the tiles.
+//! Deserializing an `Envelope<serde::de::IgnoredAny>` skips the tiles to inspect only the header,
+//! its [payload](Envelope::payload) tells which kind of tiles follow.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::features::Dictionary;
+use crate::formats::quantized::QuantizedWorld;
+use crate::source::Source;
+use crate::world::World;
+
+/// Version of the serialized tiles' layout
+///
+/// It's increased whenever a change would break existing readers.
+pub const FORMAT_VERSION: u32 = 4;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Envelope<T> {
+    /// The [FORMAT_VERSION] of the writer
+    pub format_version: u32,
+
+    /// Version of the crate which wrote the tiles
+    pub crate_version: String,
+
+    pub parameters: Parameters,
+
+    /// The input file's header, `None` if it couldn't be read (e.g. for `.osm` files)
+    pub source: Option<Source>,
+
+    /// Names of the features' ids, see [FeatureParser::dictionary](crate::features::FeatureParser::dictionary)
+    pub dictionary: Option<Dictionary>,
+
+    /// The kind of [tiles](Envelope::tiles)
+    pub payload: PayloadKind,
+
+    /// The generated [World] or its tiles in another format, see [Payload]
+    pub tiles: T,
+}
+
+/// The kinds of tiles an [Envelope] can wrap
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PayloadKind {
+    /// A [World]
+    World,
+
+    /// A [QuantizedWorld]
+    QuantizedWorld,
+}
+
+/// Tiles which can be wrapped by an [Envelope]
+pub trait Payload {
+    const KIND: PayloadKind;
+
+    /// Check the deserialized tiles' consistency
+    fn check(&self) -> Result<(), Error>;
+}
+impl<Feature, P> Payload for World<Feature, P> {
+    const KIND: PayloadKind = PayloadKind::World;
+
+    fn check(&self) -> Result<(), Error> {
+        World::check(self)
+    }
+}
+impl<Feature, P> Payload for QuantizedWorld<Feature, P> {
+    const KIND: PayloadKind = PayloadKind::QuantizedWorld;
+
+    fn check(&self) -> Result<(), Error> {
+        QuantizedWorld::check(self)
+    }
+}
+
+/// The parameters of a generation taken from its [Config](crate::Config)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Parameters {
+    pub file: String,
+    pub cols: usize,
+    pub rows: usize,
+    pub center_x: f64,
+    pub center_y: f64,
+    pub zoom: u8,
+
+    /// The projection's [name](crate::projection::Projection::name)
+    pub projection: String,
+
+    /// The [Config::config_hash](crate::Config::config_hash) of the other settings
+    ///
+    /// `None` if they couldn't be hashed, e.g. because the feature parser can't be serialized.
+    pub config_hash: Option<u64>,
+}
+
+impl<T: Payload> Envelope<T> {
+    /// Wrap tiles using the current versions
+    pub fn new(
+        parameters: Parameters,
+        source: Option<Source>,
+        dictionary: Option<Dictionary>,
+        tiles: T,
+    ) -> Self {
+        Envelope {
+            format_version: FORMAT_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            parameters,
+            source,
+            dictionary,
+            payload: T::KIND,
+            tiles,
+        }
+    }
+
+    /// Check whether the tiles are of the expected kind and consistent
+    pub fn check_payload(&self) -> Result<(), Error> {
+        if self.payload != T::KIND {
+            return Err(Error::Format(format!(
+                "expected {:?} tiles, got {:?}",
+                T::KIND,
+                self.payload
+            )));
+        }
+        self.tiles.check()
+    }
+
+    /// Check the version and the payload's kind and unwrap the tiles
+    pub fn into_tiles(self) -> Result<T, Error> {
+        self.check()?;
+        self.check_payload()?;
+        Ok(self.tiles)
+    }
+}
+
+impl<T> Envelope<T> {
+    /// Check whether this crate can read the tiles
+    pub fn check(&self) -> Result<(), Error> {
+        if self.format_version == FORMAT_VERSION {
+            Ok(())
+        } else {
+            Err(Error::Format(format!(
+                "tiles have format version {} (written by {}), expected {FORMAT_VERSION}",
+                self.format_version, self.crate_version
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::Vector2;
+    use serde::de::IgnoredAny;
+
+    use crate::formats::envelope::{Envelope, Parameters, PayloadKind};
+    use crate::formats::quantized::QuantizedWorld;
+    use crate::formats::Tile;
+    use crate::geometry::{BBox, Point};
+    use crate::projection::Simple;
+    use crate::world::World;
+
+    #[test]
+    fn check_version() {
+        let envelope = Envelope::new(
+            Parameters {
+                file: "input.pbf".to_string(),
+                cols: 1,
+                rows: 1,
+                center_x: 0.0,
+                center_y: 0.0,
+                zoom: 14,
+                projection: "web_mercator".to_string(),
+                config_hash: None,
+            },
+            None,
+            None,
+            QuantizedWorld::new(
+                World::<usize, _> {
+                    origin: Point::new(0.0, 0.0),
+                    step_size: Vector2::new(1.0, 1.0),
+                    cols: 1,
+                    rows: 1,
+                    projection: Simple,
+                    tiles: vec![Tile::new(BBox {
+                        min: Point::new(0.0, 0.0),
+                        max: Point::new(1.0, 1.0),
+                    })],
+                },
+                4096,
+            ),
+        );
+        let mut json = serde_json::to_value(&envelope).unwrap();
+
+        let header: Envelope<IgnoredAny> = serde_json::from_value(json.clone()).unwrap();
+        assert!(header.check().is_ok());
+        assert_eq!(header.parameters, envelope.parameters);
+        assert_eq!(header.payload, PayloadKind::QuantizedWorld);
+
+        let parse = |json| serde_json::from_value::<Envelope<QuantizedWorld<usize, Simple>>>(json);
+        assert!(parse(json.clone()).unwrap().into_tiles().is_ok());
+
+        let mut other = json.clone();
+        other["payload"] = "World".into();
+        assert!(parse(other).unwrap().into_tiles().is_err());
+
+        // The grid doesn't match the tiles
+        let mut other = json.clone();
+        other["tiles"]["cols"] = 2.into();
+        assert!(parse(other).unwrap().into_tiles().is_err());
+
+        json["format_version"] = 0.into();
+        assert!(parse(json).unwrap().into_tiles().is_err());
+    }
+}
@@ -0,0 +1,336 @@
+//! Convert a [Tile] into a [GeoJSON](https://datatracker.ietf.org/doc/html/rfc7946) FeatureCollection for debugging
+//!
+//! Whole outputs are written as a single FeatureCollection using [Format::GeoJson](crate::formats::Format::GeoJson).
+//! The points are converted back into lon/lat using the projection the tile was generated with.
+//! Every feature carries its `feature`, `oid` and `tile` index as properties.
+//! The tile's exclusion zones are added as polygons with their class as `exclusion` property.
+//! Optionally the tile's bounds are added as an extra polygon with `"bounds": true`.
+
+use serde::Serialize;
+
+#[cfg(feature = "serde_json")]
+use crate::error::Error;
+#[cfg(feature = "serde_json")]
+use crate::formats::envelope::Envelope;
+#[cfg(feature = "serde_json")]
+use crate::formats::quantized::QuantizedWorld;
+use crate::formats::{Rings, Tile};
+use crate::geometry::Point;
+use crate::projection::Projection;
+use crate::world::World;
+
+/// A lon/lat position
+pub type Position = [f64; 2];
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub struct FeatureCollection<'t, F> {
+    pub features: Vec<Feature<'t, F>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type")]
+pub struct Feature<'t, F> {
+    pub geometry: Geometry,
+    pub properties: Properties<'t, F>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    Point(Position),
+    LineString(Vec<Position>),
+    Polygon(Vec<Vec<Position>>),
+}
+
+#[derive(Serialize, Debug)]
+pub struct Properties<'t, F> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feature: Option<&'t F>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oid: Option<usize>,
+
+    /// Class of an exclusion zone from the tile's [ExclusionMask](crate::exclusion::ExclusionMask)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclusion: Option<usize>,
+
+    /// Index of the tile the feature was clipped into
+    pub tile: usize,
+
+    /// Marks the debug feature showing the tile's bounds
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub bounds: bool,
+}
+
+impl<'t, F> FeatureCollection<'t, F> {
+    /// Convert the tile with the given index
+    pub fn new(tile: &'t Tile<F>, index: usize, projection: impl Projection, bounds: bool) -> Self {
+        let position = |point: &Point| -> Position {
+            let point = projection.unproject_nalgebra(*point);
+            [point.x, point.y]
+        };
+        let properties = |feature, oid| Properties {
+            feature: Some(feature),
+            oid: Some(oid),
+            exclusion: None,
+            tile: index,
+            bounds: false,
+        };
+        let polygon = |rings: Rings| {
+            let rings = rings
+                .iter()
+                .enumerate()
+                .map(|(ring_index, ring)| {
+                    close_ring(ring.iter().map(position).collect(), ring_index == 0)
+                })
+                .collect();
+            Geometry::Polygon(rings)
+        };
+
+        let mut features = Vec::with_capacity(
+            tile.areas.len()
+                + tile.nodes.len()
+                + tile.ways.len()
+                + tile.exclusions.zones.len()
+                + bounds as usize,
+        );
+        for area in tile.iter_areas() {
+            features.push(Feature {
+                geometry: polygon(area.points),
+                properties: properties(area.feature, area.oid),
+            });
+        }
+        for node in tile.iter_nodes() {
+            features.push(Feature {
+                geometry: Geometry::Point(position(node.points)),
+                properties: properties(node.feature, node.oid),
+            });
+        }
+        for way in tile.iter_ways() {
+            features.push(Feature {
+                geometry: Geometry::LineString(way.points.iter().map(position).collect()),
+                properties: properties(way.feature, way.oid),
+            });
+        }
+        for zone in tile.exclusions.iter_zones() {
+            features.push(Feature {
+                geometry: polygon(zone.points),
+                properties: Properties {
+                    feature: None,
+                    oid: Some(zone.oid),
+                    exclusion: Some(*zone.feature),
+                    tile: index,
+                    bounds: false,
+                },
+            });
+        }
+        if bounds {
+            let (min, max) = (tile.min, tile.max);
+            let corners = [min, Point::new(max.x, min.y), max, Point::new(min.x, max.y)];
+            features.push(Feature {
+                geometry: Geometry::Polygon(vec![close_ring(
+                    corners.iter().map(position).collect(),
+                    true,
+                )]),
+                properties: Properties {
+                    feature: None,
+                    oid: None,
+                    exclusion: None,
+                    tile: index,
+                    bounds: true,
+                },
+            });
+        }
+
+        FeatureCollection { features }
+    }
+}
+
+impl<'w, F> FeatureCollection<'w, F> {
+    /// Convert all tiles of a world into a single collection
+    pub fn from_world<P: Projection>(world: &'w World<F, P>, bounds: bool) -> Self {
+        let features = world
+            .tiles
+            .iter()
+            .enumerate()
+            .flat_map(|(index, tile)| {
+                FeatureCollection::new(tile, index, world.projection, bounds).features
+            })
+            .collect();
+        FeatureCollection { features }
+    }
+}
+
+/// Outputs which can be written as a single FeatureCollection
+#[cfg(feature = "serde_json")]
+pub trait GeoJson {
+    /// Write the collection, optionally adding each tile's bounds as an extra polygon
+    fn write_geojson(&self, writer: impl std::io::Write, bounds: bool) -> Result<(), Error>;
+}
+#[cfg(feature = "serde_json")]
+impl<F: Serialize, P: Projection> GeoJson for World<F, P> {
+    fn write_geojson(&self, writer: impl std::io::Write, bounds: bool) -> Result<(), Error> {
+        serde_json::to_writer(writer, &FeatureCollection::from_world(self, bounds))
+            .map_err(Error::serialization)
+    }
+}
+/// Writes the decoded tiles, i.e. with the points snapped to the cells
+#[cfg(feature = "serde_json")]
+impl<F: Serialize + Clone, P: Projection> GeoJson for QuantizedWorld<F, P> {
+    fn write_geojson(&self, writer: impl std::io::Write, bounds: bool) -> Result<(), Error> {
+        self.clone().into_world().write_geojson(writer, bounds)
+    }
+}
+#[cfg(feature = "serde_json")]
+impl<T: GeoJson> GeoJson for Envelope<T> {
+    fn write_geojson(&self, writer: impl std::io::Write, bounds: bool) -> Result<(), Error> {
+        self.tiles.write_geojson(writer, bounds)
+    }
+}
+
+/// Repeat a ring's first position and apply the right-hand rule
+///
+/// Exterior rings are counterclockwise, holes are clockwise.
+fn close_ring(mut ring: Vec<Position>, exterior: bool) -> Vec<Position> {
+    if let (Some(first), Some(last)) = (ring.first(), ring.last()) {
+        if first != last {
+            ring.push(*first);
+        }
+    }
+
+    let area: f64 = ring
+        .windows(2)
+        .map(|edge| edge[0][0] * edge[1][1] - edge[1][0] * edge[0][1])
+        .sum();
+    if (area > 0.0) != exterior {
+        ring.reverse();
+    }
+    ring
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::Vector2;
+
+    use crate::formats::geojson::FeatureCollection;
+    use crate::formats::{Format, Tile};
+    use crate::geometry::{BBox, Point};
+    use crate::projection::{Projection, Simple};
+    use crate::world::World;
+
+    /// Project with [Simple] which works in radians
+    fn point(lon: f64, lat: f64) -> Point {
+        Simple.project_nalgebra(Point::new(lon, lat))
+    }
+
+    /// Compare lon/lat positions allowing rounding errors
+    fn assert_positions(json: &serde_json::Value, expected: &[[f64; 2]]) {
+        let positions: Vec<[f64; 2]> = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(positions.len(), expected.len());
+        for (position, expected) in positions.iter().zip(expected) {
+            assert!((position[0] - expected[0]).abs() < 1e-9);
+            assert!((position[1] - expected[1]).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn tile_to_geojson() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(4.0, 4.0),
+        });
+        // Clockwise in lon/lat
+        tile.add_area(&[point(0.0, 0.0), point(0.0, 1.0), point(1.0, 1.0)], 1, 10);
+        tile.add_node(point(2.0, 3.0), 2, 20);
+        tile.exclusions.add_zone(
+            &[
+                point(2.0, 2.0),
+                point(3.0, 2.0),
+                point(3.0, 3.0),
+                point(2.0, 3.0),
+            ],
+            5,
+            30,
+        );
+
+        let collection = FeatureCollection::new(&tile, 7, Simple, true);
+        let json = serde_json::to_value(&collection).unwrap();
+        assert_eq!(json["type"], "FeatureCollection");
+        assert_eq!(json["features"].as_array().unwrap().len(), 4);
+
+        let area = &json["features"][0];
+        assert_eq!(area["type"], "Feature");
+        assert_eq!(area["geometry"]["type"], "Polygon");
+        assert_positions(
+            &area["geometry"]["coordinates"][0],
+            &[[0.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]],
+        );
+        assert_eq!(
+            area["properties"],
+            serde_json::json!({"feature": 1, "oid": 10, "tile": 7})
+        );
+
+        let node = &json["features"][1];
+        assert_positions(
+            &serde_json::json!([node["geometry"]["coordinates"]]),
+            &[[2.0, 3.0]],
+        );
+
+        let zone = &json["features"][2];
+        assert_eq!(zone["geometry"]["type"], "Polygon");
+        assert_eq!(
+            zone["geometry"]["coordinates"][0].as_array().unwrap().len(),
+            5
+        );
+        assert_eq!(
+            zone["properties"],
+            serde_json::json!({"oid": 30, "exclusion": 5, "tile": 7})
+        );
+
+        let bounds = &json["features"][3];
+        assert_eq!(
+            bounds["properties"],
+            serde_json::json!({"tile": 7, "bounds": true})
+        );
+    }
+
+    #[test]
+    fn world_to_geojson() {
+        let tiles = (0..2)
+            .map(|x| {
+                let mut tile = Tile::new(BBox {
+                    min: Point::new(x as f64, 0.0),
+                    max: Point::new(x as f64 + 1.0, 1.0),
+                });
+                tile.add_node(Point::new(x as f64 + 0.5, 0.5), 2, 20 + x);
+                tile
+            })
+            .collect();
+        let world = World {
+            origin: Point::new(0.0, 0.0),
+            step_size: Vector2::new(1.0, 1.0),
+            cols: 2,
+            rows: 1,
+            projection: Simple,
+            tiles,
+        };
+
+        let mut output = Vec::new();
+        Format::GeoJson
+            .write_geojson(&mut output, &world, false)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(json["type"], "FeatureCollection");
+        let features = json["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(
+            features[1]["properties"],
+            serde_json::json!({"feature": 2, "oid": 21, "tile": 1})
+        );
+
+        assert!(Format::GeoJson.write(Vec::new(), &world).is_err());
+        let read: Result<World<usize, Simple>, _> = Format::GeoJson.read(output.as_slice());
+        assert!(read.is_err());
+    }
+}
@@ -1,71 +1,312 @@
 use serde::{Deserialize, Serialize};
 
+use crate::exclusion::ExclusionMask;
+use crate::geometry::polygon::contains_point;
+use crate::geometry::polyline::distance_to;
+use crate::geometry::rtree::PackedRTree;
 use crate::geometry::{BBox, Point};
 
+pub mod archive;
+pub mod binary;
+pub mod envelope;
+pub mod geojson;
+pub mod mvt;
+pub mod quantized;
+
+/// The kind of an item
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Kind {
+    Area,
+    Node,
+    Way,
+}
+
+/// Data formats for whole outputs like an [Envelope](envelope::Envelope)
+#[cfg(feature = "serde_json")]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+
+    #[cfg(feature = "message-pack")]
+    MessagePack,
+
+    /// A single FeatureCollection of all tiles for debugging, see [geojson]
+    ///
+    /// It can only be written, not read.
+    #[cfg_attr(feature = "clap", value(name = "geojson"))]
+    GeoJson,
+}
+#[cfg(feature = "serde_json")]
+impl Format {
+    pub fn read<T: serde::de::DeserializeOwned>(
+        &self,
+        reader: impl std::io::Read,
+    ) -> Result<T, crate::Error> {
+        match self {
+            Format::Json => serde_json::from_reader(reader).map_err(crate::Error::serialization),
+            #[cfg(feature = "message-pack")]
+            Format::MessagePack => {
+                rmp_serde::from_read(reader).map_err(crate::Error::serialization)
+            }
+            Format::GeoJson => Err(crate::Error::Format(
+                "GeoJSON outputs can't be read back".to_string(),
+            )),
+        }
+    }
+
+    /// Write some data, [Format::GeoJson] requires [write_geojson](Self::write_geojson) instead
+    pub fn write(
+        &self,
+        mut writer: impl std::io::Write,
+        data: &impl Serialize,
+    ) -> Result<(), crate::Error> {
+        match self {
+            Format::Json => {
+                serde_json::to_writer(&mut writer, data).map_err(crate::Error::serialization)
+            }
+            #[cfg(feature = "message-pack")]
+            Format::MessagePack => {
+                rmp_serde::encode::write(&mut writer, data).map_err(crate::Error::serialization)
+            }
+            Format::GeoJson => Err(crate::Error::Format(
+                "GeoJSON outputs can only be written from data convertible to GeoJSON".to_string(),
+            )),
+        }
+    }
+
+    /// Write some data, which [Format::GeoJson] converts using [GeoJson](geojson::GeoJson)
+    ///
+    /// `bounds` adds each tile's bounds to the GeoJSON output, the other formats ignore it.
+    pub fn write_geojson(
+        &self,
+        writer: impl std::io::Write,
+        data: &(impl Serialize + geojson::GeoJson),
+        bounds: bool,
+    ) -> Result<(), crate::Error> {
+        match self {
+            Format::GeoJson => data.write_geojson(writer, bounds),
+            format => format.write(writer, data),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Tile<Feature> {
     pub min: Point,
     pub max: Point,
 
+    /// Areas define a range of `rings`, whose first one is the outer ring and the others are holes.
     pub areas: Vec<Item<Feature, (usize, usize)>>,
     pub nodes: Vec<Item<Feature, usize>>,
     pub ways: Vec<Item<Feature, (usize, usize)>>,
 
+    /// Ranges of `points` forming the areas' rings
+    pub rings: Vec<(usize, usize)>,
+
     /// Common pool of points used by all areas, nodes and ways
     pub points: Vec<Point>,
+
+    /// Optional spatial index speeding up the point queries, see [Tile::build_index]
+    #[serde(default)]
+    pub index: Option<TileIndex>,
+
+    /// The exclusion zones inside the tile, see [exclusion](crate::exclusion)
+    #[serde(default)]
+    pub exclusions: ExclusionMask,
+}
+
+/// R-trees over a [Tile]'s items' bounding boxes
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TileIndex {
+    /// Indexes the areas' outer rings
+    pub areas: PackedRTree,
+    pub nodes: PackedRTree,
+    pub ways: PackedRTree,
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub struct Item<Feature, Index> {
     pub feature: Feature,
+
+    /// Id of the osm object the item was generated from
+    ///
+    /// Nodes and ways use their osm id.
+    /// Areas use libosmium's area id: `2 * id` for areas from closed ways and `2 * id + 1` for areas from multipolygon relations.
+    /// So items of different kinds may share an oid.
+    /// Objects with negative ids, which only appear in unsaved editor files, are skipped.
     pub oid: usize,
 
     /// Ether `usize` for nodes or `(usize, usize)` defining a range for areas and ways.
     pub points: Index,
 }
 
+/// An area's rings borrowed from a [Tile]
+#[derive(Copy, Clone, Debug)]
+pub struct Rings<'t> {
+    pub(crate) points: &'t [Point],
+    pub(crate) rings: &'t [(usize, usize)],
+}
+impl<'t> Rings<'t> {
+    /// Get the outer ring
+    pub fn outer(&self) -> &'t [Point] {
+        self.rings
+            .first()
+            .map_or(&[], |&(start, end)| &self.points[start..end])
+    }
+
+    /// Iterate over the inner rings i.e. the holes
+    pub fn inner(&self) -> impl Iterator<Item = &'t [Point]> + 't {
+        let points = self.points;
+        self.rings
+            .iter()
+            .skip(1)
+            .map(move |&(start, end)| &points[start..end])
+    }
+
+    /// Iterate over all rings starting with the outer one
+    pub fn iter(&self) -> impl Iterator<Item = &'t [Point]> + 't {
+        let points = self.points;
+        self.rings
+            .iter()
+            .map(move |&(start, end)| &points[start..end])
+    }
+
+    /// Check whether a point lies inside the outer ring but outside all holes
+    pub fn contains(&self, point: Point) -> bool {
+        contains_point(self.outer(), point) && !self.inner().any(|ring| contains_point(ring, point))
+    }
+}
+
 /// Implements iterators hiding the flattened points
 impl<Feature> Tile<Feature> {
-    pub fn iter_areas(&self) -> impl Iterator<Item = Item<&Feature, &[Point]>> {
-        self.areas.iter().map(
-            |Item {
-                 feature,
-                 oid,
-                 points: (start, end),
-             }| Item {
-                feature,
-                oid: *oid,
-                points: &self.points[*start..*end],
-            },
-        )
+    pub fn iter_areas(&self) -> impl Iterator<Item = Item<&Feature, Rings<'_>>> {
+        (0..self.areas.len()).map(|index| self.area(index))
     }
 
     pub fn iter_nodes(&self) -> impl Iterator<Item = Item<&Feature, &Point>> {
-        self.nodes.iter().map(
-            |Item {
-                 feature,
-                 oid,
-                 points,
-             }| Item {
-                feature,
-                oid: *oid,
-                points: &self.points[*points],
-            },
-        )
+        (0..self.nodes.len()).map(|index| self.node(index))
     }
 
     pub fn iter_ways(&self) -> impl Iterator<Item = Item<&Feature, &[Point]>> {
-        self.ways.iter().map(
-            |Item {
-                 feature,
-                 oid,
-                 points: (start, end),
-             }| Item {
-                feature,
-                oid: *oid,
-                points: &self.points[*start..*end],
+        (0..self.ways.len()).map(|index| self.way(index))
+    }
+
+    /// Get the area at an index into [Tile::areas]
+    pub fn area(&self, index: usize) -> Item<&Feature, Rings<'_>> {
+        let Item {
+            feature,
+            oid,
+            points: (start, end),
+        } = &self.areas[index];
+        Item {
+            feature,
+            oid: *oid,
+            points: Rings {
+                points: &self.points,
+                rings: &self.rings[*start..*end],
             },
-        )
+        }
+    }
+
+    /// Get the node at an index into [Tile::nodes]
+    pub fn node(&self, index: usize) -> Item<&Feature, &Point> {
+        let Item {
+            feature,
+            oid,
+            points,
+        } = &self.nodes[index];
+        Item {
+            feature,
+            oid: *oid,
+            points: &self.points[*points],
+        }
+    }
+
+    /// Get the way at an index into [Tile::ways]
+    pub fn way(&self, index: usize) -> Item<&Feature, &[Point]> {
+        let Item {
+            feature,
+            oid,
+            points: (start, end),
+        } = &self.ways[index];
+        Item {
+            feature,
+            oid: *oid,
+            points: &self.points[*start..*end],
+        }
+    }
+}
+
+/// Implements point queries which use the [TileIndex] if it has been built
+impl<Feature> Tile<Feature> {
+    /// Build the spatial index over the current items
+    ///
+    /// Adding items afterwards drops the index again.
+    pub fn build_index(&mut self) {
+        let bbox = |points: &[Point]| BBox::from_iter(points.iter().copied());
+        self.index = Some(TileIndex {
+            areas: PackedRTree::new(self.iter_areas().map(|area| bbox(area.points.outer()))),
+            nodes: PackedRTree::new(self.iter_nodes().map(|node| BBox {
+                min: *node.points,
+                max: *node.points,
+            })),
+            ways: PackedRTree::new(self.iter_ways().map(|way| bbox(way.points))),
+        });
+    }
+
+    /// Get all areas containing a point
+    pub fn areas_containing(&self, point: Point) -> Vec<Item<&Feature, Rings<'_>>> {
+        let mut candidates = Vec::new();
+        match self.index.as_ref() {
+            Some(index) => index.areas.search(
+                BBox {
+                    min: point,
+                    max: point,
+                },
+                |area| candidates.push(area),
+            ),
+            None => candidates.extend(0..self.areas.len()),
+        }
+        candidates.sort_unstable();
+        candidates
+            .into_iter()
+            .map(|index| self.area(index))
+            .filter(|area| area.points.contains(point))
+            .collect()
+    }
+
+    /// Get all ways passing within `radius` of a point
+    pub fn ways_within(&self, point: Point, radius: f64) -> Vec<Item<&Feature, &[Point]>> {
+        let mut candidates = Vec::new();
+        match self.index.as_ref() {
+            Some(index) => {
+                let offset = Point::new(radius, radius);
+                let bbox = BBox {
+                    min: point - offset,
+                    max: point + offset,
+                };
+                index.ways.search(bbox, |way| candidates.push(way));
+            }
+            None => candidates.extend(0..self.ways.len()),
+        }
+        candidates.sort_unstable();
+        candidates
+            .into_iter()
+            .map(|index| self.way(index))
+            .filter(|way| way.points.len() > 1 && distance_to(way.points, point) <= radius)
+            .collect()
+    }
+
+    /// Get the node closest to a point
+    pub fn nearest_node(&self, point: Point) -> Option<Item<&Feature, &Point>> {
+        let distance = |index: usize| self.node(index).points.metric_distance(&point);
+        let index = match self.index.as_ref() {
+            Some(index) => index.nodes.nearest(point, distance)?.0,
+            None => (0..self.nodes.len()).min_by(|&a, &b| distance(a).total_cmp(&distance(b)))?,
+        };
+        Some(self.node(index))
     }
 }
 
@@ -76,41 +317,237 @@ impl<Feature> Tile<Feature> {
             min: bbox.min,
             max: bbox.max,
             points: Vec::new(),
+            rings: Vec::new(),
             areas: Vec::new(),
             nodes: Vec::new(),
             ways: Vec::new(),
+            index: None,
+            exclusions: ExclusionMask::default(),
         }
     }
 
-    pub fn add_area(&mut self, area: &[Point], feature: Feature) {
-        let start = self.points.len();
-        self.points.extend_from_slice(area);
-        let end = self.points.len();
+    /// Add an area consisting only of its outer ring
+    pub fn add_area(&mut self, outer_ring: &[Point], feature: Feature, oid: usize) {
+        self.index = None;
+        let ring = self.rings.len();
+        self.push_ring(outer_ring);
         self.areas.push(Item {
             feature,
-            oid: 0,
-            points: (start, end),
+            oid,
+            points: (ring, ring + 1),
         });
     }
 
-    pub fn add_node(&mut self, node: Point, feature: Feature) {
+    /// Add a hole to the last added area
+    pub fn add_hole(&mut self, inner_ring: &[Point]) {
+        let Some(area) = self.areas.last_mut() else {
+            return;
+        };
+        debug_assert_eq!(
+            area.points.1,
+            self.rings.len(),
+            "The last area's rings have to be the last ones"
+        );
+        area.points.1 += 1;
+        self.push_ring(inner_ring);
+    }
+
+    /// Replace the rings of the last added area, whose rings have to be the last ones
+    pub(crate) fn replace_last_rings<'r>(&mut self, rings: impl IntoIterator<Item = &'r [Point]>) {
+        let Some(area) = self.areas.last() else {
+            return;
+        };
+        let first = area.points.0;
+        if let Some(&(start, _)) = self.rings.get(first) {
+            self.points.truncate(start);
+        }
+        self.rings.truncate(first);
+        for ring in rings {
+            self.push_ring(ring);
+        }
+        if let Some(area) = self.areas.last_mut() {
+            area.points.1 = self.rings.len();
+        }
+    }
+
+    fn push_ring(&mut self, ring: &[Point]) {
+        let start = self.points.len();
+        self.points.extend_from_slice(ring);
+        let end = self.points.len();
+        self.rings.push((start, end));
+    }
+
+    pub fn add_node(&mut self, node: Point, feature: Feature, oid: usize) {
+        self.index = None;
         let index = self.points.len();
         self.points.push(node);
         self.nodes.push(Item {
             feature,
-            oid: 0,
+            oid,
             points: index,
         });
     }
 
-    pub fn add_way(&mut self, way: &[Point], feature: Feature) {
+    pub fn add_way(&mut self, way: &[Point], feature: Feature, oid: usize) {
+        self.index = None;
         let start = self.points.len();
         self.points.extend_from_slice(way);
         let end = self.points.len();
         self.ways.push(Item {
             feature,
-            oid: 0,
+            oid,
             points: (start, end),
         });
     }
+
+    /// Sort the items by their oid and rewrite the points in that order
+    ///
+    /// Items sharing an oid, like the pieces of a clipped way, keep their relative order.
+    /// This makes a tile's layout independent of the order its items were added in.
+    pub fn canonicalize(&mut self) {
+        let mut points = Vec::with_capacity(self.points.len());
+        let mut rings = Vec::with_capacity(self.rings.len());
+
+        self.areas.sort_by_key(|area| area.oid);
+        for area in self.areas.iter_mut() {
+            let first = rings.len();
+            for &(start, end) in &self.rings[area.points.0..area.points.1] {
+                let offset = points.len();
+                points.extend_from_slice(&self.points[start..end]);
+                rings.push((offset, points.len()));
+            }
+            area.points = (first, rings.len());
+        }
+
+        self.nodes.sort_by_key(|node| node.oid);
+        for node in self.nodes.iter_mut() {
+            points.push(self.points[node.points]);
+            node.points = points.len() - 1;
+        }
+
+        self.ways.sort_by_key(|way| way.oid);
+        for way in self.ways.iter_mut() {
+            let start = points.len();
+            points.extend_from_slice(&self.points[way.points.0..way.points.1]);
+            way.points = (start, points.len());
+        }
+
+        self.points = points;
+        self.rings = rings;
+        self.exclusions.canonicalize();
+        if self.index.is_some() {
+            self.build_index();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::formats::{Item, Tile};
+    use crate::geometry::{square, BBox, Point};
+
+    #[test]
+    fn area_with_hole() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(4.0, 4.0),
+        });
+        tile.add_area(&square(0.0, 1.0), 1, 10);
+        tile.add_area(&square(0.0, 4.0), 2, 20);
+        tile.add_hole(&square(1.0, 3.0));
+
+        let areas: Vec<_> = tile.iter_areas().collect();
+        assert_eq!(areas.len(), 2);
+        assert_eq!(areas[0].points.inner().count(), 0);
+        assert_eq!(areas[1].points.outer(), &square(0.0, 4.0));
+        assert_eq!(
+            areas[1].points.inner().collect::<Vec<_>>(),
+            vec![&square(1.0, 3.0)]
+        );
+
+        assert!(areas[1].points.contains(Point::new(0.5, 0.5)));
+        assert!(!areas[1].points.contains(Point::new(2.0, 2.0)));
+    }
+
+    fn oids<F, I>(items: Vec<Item<F, I>>) -> Vec<usize> {
+        items.into_iter().map(|item| item.oid).collect()
+    }
+
+    #[test]
+    fn point_queries() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(4.0, 4.0),
+        });
+        tile.add_area(&square(0.0, 4.0), 1, 10);
+        tile.add_hole(&square(1.0, 3.0));
+        tile.add_area(&square(1.5, 2.5), 2, 20);
+        tile.add_way(&[Point::new(0.0, 2.0), Point::new(4.0, 2.0)], 3, 30);
+        tile.add_way(&[Point::new(2.0, 0.0), Point::new(2.0, 1.0)], 4, 40);
+        for i in 0..50 {
+            tile.add_node(Point::new(i as f64 * 0.08, 0.5), 5, i);
+        }
+
+        for indexed in [false, true] {
+            if indexed {
+                tile.build_index();
+            }
+            assert_eq!(oids(tile.areas_containing(Point::new(0.5, 0.5))), vec![10]);
+            assert_eq!(oids(tile.areas_containing(Point::new(2.0, 2.0))), vec![20]);
+            assert_eq!(
+                oids(tile.areas_containing(Point::new(1.2, 1.2))),
+                Vec::<usize>::new()
+            );
+
+            assert_eq!(oids(tile.ways_within(Point::new(1.0, 2.5), 0.6)), vec![30]);
+            assert_eq!(
+                oids(tile.ways_within(Point::new(2.0, 1.5), 0.6)),
+                vec![30, 40]
+            );
+
+            let node = tile.nearest_node(Point::new(1.0, 3.0)).unwrap();
+            assert_eq!((node.oid, *node.feature), (12, 5));
+        }
+
+        // Adding items invalidates the index
+        tile.add_node(Point::new(1.0, 2.9), 6, 60);
+        assert!(tile.index.is_none());
+        assert_eq!(tile.nearest_node(Point::new(1.0, 3.0)).unwrap().oid, 60);
+    }
+
+    #[test]
+    fn canonicalize() {
+        let bbox = BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(4.0, 4.0),
+        };
+        let way = [Point::new(0.0, 1.0), Point::new(1.0, 1.0)];
+
+        let mut a = Tile::new(bbox);
+        a.add_node(Point::new(1.0, 1.0), 1, 3);
+        a.add_area(&square(0.0, 4.0), 2, 20);
+        a.add_hole(&square(1.0, 3.0));
+        a.add_way(&way, 3, 7);
+        a.add_node(Point::new(2.0, 2.0), 1, 2);
+        a.add_area(&square(0.0, 1.0), 2, 10);
+
+        let mut b = Tile::new(bbox);
+        b.add_area(&square(0.0, 1.0), 2, 10);
+        b.add_way(&way, 3, 7);
+        b.add_node(Point::new(2.0, 2.0), 1, 2);
+        b.add_area(&square(0.0, 4.0), 2, 20);
+        b.add_hole(&square(1.0, 3.0));
+        b.add_node(Point::new(1.0, 1.0), 1, 3);
+
+        a.canonicalize();
+        b.canonicalize();
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            serde_json::to_string(&b).unwrap()
+        );
+        assert_eq!(oids(a.iter_areas().collect()), vec![10, 20]);
+        assert_eq!(a.area(1).points.inner().count(), 1);
+        assert_eq!(a.node(0).points, &Point::new(2.0, 2.0));
+        assert_eq!(a.way(0).points, &way);
+    }
 }
@@ -0,0 +1,517 @@
+//! Encoder for [Mapbox Vector Tiles](https://github.com/mapbox/vector-tile-spec/tree/master/2.1)
+//!
+//! A [Tile] becomes an MVT with up to four layers: `areas`, `nodes`, `ways` and `exclusions`.
+//! Every feature has the attributes `feature` and `oid` and uses the oid as its id.
+//! The pieces of an object clipped into the same tile are merged into one feature
+//! with a multi geometry, so the ids are unique within a layer.
+//! The `exclusions` layer contains the tile's [exclusion zones](crate::exclusion),
+//! whose `feature` is their class.
+//!
+//! MVT's tile coordinates have their y axis pointing down,
+//! which matches [WebMercator](crate::projection::WebMercator).
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use crate::formats::quantized::zigzag_encode;
+use crate::formats::{Rings, Tile};
+use crate::geometry::Point;
+
+/// Value of a feature's attribute
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Double(f64),
+    Int(i64),
+    Uint(u64),
+    Bool(bool),
+}
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Uint(value as u64)
+    }
+}
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+/// Get a tile's `x` and `y` in the slippy map scheme of a zoom level
+///
+/// Returns `None` for tiles outside the zoom level's range, i.e. from grids extending beyond the map's edge.
+pub fn tile_position<F>(tile: &Tile<F>, zoom: u8) -> Option<(u32, u32)> {
+    position(tile.min, zoom)
+}
+
+/// Get the `x` and `y` of the tile whose "min" corner is `min`, see [tile_position]
+pub fn position(min: Point, zoom: u8) -> Option<(u32, u32)> {
+    let tiles = (1u64 << zoom) as f64;
+    let index = |value: f64| {
+        let index = (value * tiles).round();
+        (index >= 0.0 && index < tiles).then_some(index as u32)
+    };
+    Some((index(min.x)?, index(min.y)?))
+}
+
+/// Encode a tile as MVT using `extent` cells per axis
+///
+/// `feature` converts a feature into the value of the `feature` attribute.
+pub fn encode<F>(tile: &Tile<F>, extent: u32, feature: impl Fn(&F) -> Value) -> Vec<u8> {
+    match try_encode(tile, extent, |f| Ok::<_, Infallible>(feature(f))) {
+        Ok(encoded) => encoded,
+        Err(never) => match never {},
+    }
+}
+
+/// Encode a tile as MVT like [encode], stopping at the first error `feature` returns
+pub fn try_encode<F, E>(
+    tile: &Tile<F>,
+    extent: u32,
+    feature: impl Fn(&F) -> Result<Value, E>,
+) -> Result<Vec<u8>, E> {
+    let quantize = Quantize::new(tile, extent);
+    let mut output = Writer::default();
+
+    let mut layer = Layer::new("areas", extent);
+    for area in tile.iter_areas() {
+        let value = feature(area.feature)?;
+        layer.add(area.oid, value, GeomType::Polygon, |cursor, geometry| {
+            encode_polygon(&quantize, &area.points, cursor, geometry)
+        });
+    }
+    layer.write(&mut output);
+
+    // A node is never split, so each oid occurs at most once and stays a single point
+    let mut layer = Layer::new("nodes", extent);
+    for node in tile.iter_nodes() {
+        let value = feature(node.feature)?;
+        layer.add(node.oid, value, GeomType::Point, |cursor, geometry| {
+            geometry.push(command(MOVE_TO, 1));
+            encode_points(&[quantize.point(node.points)], cursor, geometry);
+        });
+    }
+    layer.write(&mut output);
+
+    let mut layer = Layer::new("ways", extent);
+    for way in tile.iter_ways() {
+        let value = feature(way.feature)?;
+        layer.add(way.oid, value, GeomType::LineString, |cursor, geometry| {
+            encode_line(&quantize.line(way.points), cursor, geometry)
+        });
+    }
+    layer.write(&mut output);
+
+    let mut layer = Layer::new("exclusions", extent);
+    for zone in tile.exclusions.iter_zones() {
+        let value = Value::from(*zone.feature);
+        layer.add(zone.oid, value, GeomType::Polygon, |cursor, geometry| {
+            encode_polygon(&quantize, &zone.points, cursor, geometry)
+        });
+    }
+    layer.write(&mut output);
+
+    Ok(output.0)
+}
+
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0x7) | (count << 3)
+}
+
+#[derive(Copy, Clone)]
+enum GeomType {
+    Point = 1,
+    LineString = 2,
+    Polygon = 3,
+}
+
+/// Append the `LineTo`'s parameters for `points` starting at `cursor`
+fn encode_points(points: &[[i32; 2]], cursor: &mut [i32; 2], geometry: &mut Vec<u32>) {
+    for point in points {
+        geometry.push(zigzag_encode(point[0] - cursor[0]));
+        geometry.push(zigzag_encode(point[1] - cursor[1]));
+        *cursor = *point;
+    }
+}
+
+/// Append a linestring starting at `cursor`, nothing is appended if it is too short
+fn encode_line(line: &[[i32; 2]], cursor: &mut [i32; 2], geometry: &mut Vec<u32>) {
+    if line.len() < 2 {
+        return;
+    }
+    geometry.push(command(MOVE_TO, 1));
+    encode_points(&line[..1], cursor, geometry);
+    geometry.push(command(LINE_TO, line.len() as u32 - 1));
+    encode_points(&line[1..], cursor, geometry);
+}
+
+/// Append a polygon's rings starting at `cursor`, nothing is appended if the outer ring is degenerate
+fn encode_polygon(
+    quantize: &Quantize,
+    rings: &Rings,
+    cursor: &mut [i32; 2],
+    geometry: &mut Vec<u32>,
+) {
+    for (index, ring) in rings.iter().enumerate() {
+        let ring = quantize.ring(ring);
+        let exterior = index == 0;
+        if !encode_ring(&ring, exterior, cursor, geometry) && exterior {
+            break;
+        }
+    }
+}
+
+/// Encode a ring with the winding order required by the spec, returns `false` if it was degenerate
+///
+/// Exterior rings have a positive area in tile coordinates (clockwise when y points down),
+/// interior rings a negative one.
+fn encode_ring(
+    ring: &[[i32; 2]],
+    exterior: bool,
+    cursor: &mut [i32; 2],
+    geometry: &mut Vec<u32>,
+) -> bool {
+    let area = signed_area(ring);
+    if ring.len() < 3 || area == 0 {
+        return false;
+    }
+
+    let reversed: Vec<[i32; 2]>;
+    let ring = if (area > 0) == exterior {
+        ring
+    } else {
+        reversed = ring.iter().rev().copied().collect();
+        &reversed
+    };
+
+    geometry.push(command(MOVE_TO, 1));
+    encode_points(&ring[..1], cursor, geometry);
+    geometry.push(command(LINE_TO, ring.len() as u32 - 1));
+    encode_points(&ring[1..], cursor, geometry);
+    geometry.push(command(CLOSE_PATH, 1));
+    true
+}
+
+/// Twice the signed area using the surveyor's formula
+fn signed_area(ring: &[[i32; 2]]) -> i64 {
+    let mut area = 0;
+    for (index, from) in ring.iter().enumerate() {
+        let to = ring[(index + 1) % ring.len()];
+        area += from[0] as i64 * to[1] as i64 - to[0] as i64 * from[1] as i64;
+    }
+    area
+}
+
+/// Maps points into a tile's integer coordinates
+struct Quantize {
+    min: Point,
+    scale: Point,
+}
+impl Quantize {
+    fn new<F>(tile: &Tile<F>, extent: u32) -> Self {
+        Quantize {
+            min: tile.min,
+            scale: Point::new(
+                extent as f64 / (tile.max.x - tile.min.x),
+                extent as f64 / (tile.max.y - tile.min.y),
+            ),
+        }
+    }
+
+    fn point(&self, point: &Point) -> [i32; 2] {
+        let local = (point - self.min).component_mul(&self.scale);
+        [local.x.round() as i32, local.y.round() as i32]
+    }
+
+    /// Quantize a line dropping points which collapse onto their predecessor
+    fn line(&self, line: &[Point]) -> Vec<[i32; 2]> {
+        let mut quantized: Vec<[i32; 2]> = line.iter().map(|point| self.point(point)).collect();
+        quantized.dedup();
+        quantized
+    }
+
+    /// Quantize a ring dropping duplicated points including a repeated first one
+    fn ring(&self, ring: &[Point]) -> Vec<[i32; 2]> {
+        let mut quantized = self.line(ring);
+        if quantized.len() > 1 && quantized.first() == quantized.last() {
+            quantized.pop();
+        }
+        quantized
+    }
+}
+
+/// A layer being built
+struct Layer {
+    name: &'static str,
+    extent: u32,
+    features: Vec<Feature>,
+
+    /// Index of each oid's feature in `features`
+    oids: HashMap<usize, usize>,
+
+    values: Vec<Vec<u8>>,
+    value_indexes: HashMap<Vec<u8>, u32>,
+}
+
+/// A feature being built, which collects all pieces of its object
+struct Feature {
+    oid: usize,
+    tags: [u32; 4],
+    geom_type: GeomType,
+    geometry: Vec<u32>,
+
+    /// The position the geometry's last command ended at
+    cursor: [i32; 2],
+}
+
+impl Layer {
+    fn new(name: &'static str, extent: u32) -> Self {
+        Layer {
+            name,
+            extent,
+            features: Vec::new(),
+            oids: HashMap::new(),
+            values: Vec::new(),
+            value_indexes: HashMap::new(),
+        }
+    }
+
+    /// Get a value's index in the layer's value table
+    fn value(&mut self, value: &Value) -> u32 {
+        let mut message = Writer::default();
+        match value {
+            Value::String(string) => message.bytes(1, string.as_bytes()),
+            Value::Double(double) => message.double(3, *double),
+            Value::Int(int) => message.uint(4, *int as u64),
+            Value::Uint(uint) => message.uint(5, *uint),
+            Value::Bool(bool) => message.uint(7, *bool as u64),
+        }
+        let next = self.values.len() as u32;
+        *self
+            .value_indexes
+            .entry(message.0)
+            .or_insert_with_key(|message| {
+                self.values.push(message.clone());
+                next
+            })
+    }
+
+    /// Add a piece of an object using `encode` to append its geometry
+    ///
+    /// Pieces of an object which was already added are appended to its feature.
+    /// A new feature is only created if `encode` appended anything.
+    fn add(
+        &mut self,
+        oid: usize,
+        feature: Value,
+        geom_type: GeomType,
+        encode: impl FnOnce(&mut [i32; 2], &mut Vec<u32>),
+    ) {
+        if let Some(&index) = self.oids.get(&oid) {
+            let Feature {
+                cursor, geometry, ..
+            } = &mut self.features[index];
+            encode(cursor, geometry);
+            return;
+        }
+
+        let (mut cursor, mut geometry) = ([0, 0], Vec::new());
+        encode(&mut cursor, &mut geometry);
+        if geometry.is_empty() {
+            return;
+        }
+        let tags = [0, self.value(&feature), 1, self.value(&Value::from(oid))];
+        self.oids.insert(oid, self.features.len());
+        self.features.push(Feature {
+            oid,
+            tags,
+            geom_type,
+            geometry,
+            cursor,
+        });
+    }
+
+    /// Write the layer as field of the tile message, if it contains any features
+    fn write(self, tile: &mut Writer) {
+        if self.features.is_empty() {
+            return;
+        }
+        let mut message = Writer::default();
+        message.uint(15, 2);
+        message.bytes(1, self.name.as_bytes());
+        for feature in self.features.iter() {
+            let mut encoded = Writer::default();
+            encoded.uint(1, feature.oid as u64);
+            encoded.packed(2, &feature.tags);
+            encoded.uint(3, feature.geom_type as u64);
+            encoded.packed(4, &feature.geometry);
+            message.bytes(2, &encoded.0);
+        }
+        message.bytes(3, b"feature");
+        message.bytes(3, b"oid");
+        for value in self.values.iter() {
+            message.bytes(4, value);
+        }
+        message.uint(5, self.extent as u64);
+        tile.bytes(3, &message.0);
+    }
+}
+
+/// Minimal protobuf writer
+#[derive(Default)]
+struct Writer(Vec<u8>);
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u32) {
+        self.varint(((field << 3) | wire_type) as u64);
+    }
+
+    fn uint(&mut self, field: u32, value: u64) {
+        self.key(field, 0);
+        self.varint(value);
+    }
+
+    fn double(&mut self, field: u32, value: f64) {
+        self.key(field, 1);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn packed(&mut self, field: u32, values: &[u32]) {
+        let mut packed = Writer::default();
+        for value in values {
+            packed.varint(*value as u64);
+        }
+        self.bytes(field, &packed.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::formats::mvt::{encode, encode_ring, tile_position};
+    use crate::formats::Tile;
+    use crate::geometry::{BBox, Point};
+
+    #[test]
+    fn ring_winding() {
+        // Counter-clockwise when y points down i.e. negative area
+        let ring = [[0, 0], [0, 2], [2, 2], [2, 0]];
+
+        let mut geometry = Vec::new();
+        assert!(encode_ring(&ring, true, &mut [0, 0], &mut geometry));
+        // Reversed: MoveTo(2, 0) LineTo(+0, +2) (-2, +0) (+0, -2) ClosePath
+        assert_eq!(geometry, vec![9, 4, 0, 26, 0, 4, 3, 0, 0, 3, 15]);
+
+        geometry.clear();
+        assert!(encode_ring(&ring, false, &mut [0, 0], &mut geometry));
+        assert_eq!(geometry, vec![9, 0, 0, 26, 0, 4, 4, 0, 0, 3, 15]);
+
+        assert!(!encode_ring(
+            &[[0, 0], [1, 1], [2, 2]],
+            true,
+            &mut [0, 0],
+            &mut geometry
+        ));
+    }
+
+    #[test]
+    fn single_node() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.5, 0.25),
+            max: Point::new(0.75, 0.5),
+        });
+        tile.add_node(Point::new(0.5625, 0.25), 3, 7);
+        assert_eq!(tile_position(&tile, 2), Some((2, 1)));
+        assert_eq!(tile_position(&tile, 0), None);
+        let outside = Tile::<usize>::new(BBox {
+            min: Point::new(-0.25, 0.25),
+            max: Point::new(0.0, 0.5),
+        });
+        assert_eq!(tile_position(&outside, 2), None);
+
+        let encoded = encode(&tile, 16, |feature| (*feature).into());
+        #[rustfmt::skip]
+        let expected = [
+            0x1a, 50, // Layer
+                0x78, 2, // Version
+                0x0a, 5, b'n', b'o', b'd', b'e', b's', // Name
+                0x12, 15, // Feature
+                    0x08, 7, // Id
+                    0x12, 4, 0, 0, 1, 1, // Tags
+                    0x18, 1, // Type
+                    0x22, 3, 9, 8, 0, // Geometry
+                0x1a, 7, b'f', b'e', b'a', b't', b'u', b'r', b'e', // Keys
+                0x1a, 3, b'o', b'i', b'd',
+                0x22, 2, 0x28, 3, // Values
+                0x22, 2, 0x28, 7,
+                0x28, 16, // Extent
+        ];
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn exclusion_layer() {
+        let mut tile: Tile<usize> = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(1.0, 1.0),
+        });
+        let square = [
+            Point::new(0.25, 0.25),
+            Point::new(0.75, 0.25),
+            Point::new(0.75, 0.75),
+            Point::new(0.25, 0.75),
+        ];
+        tile.exclusions.add_zone(&square, 5, 9);
+
+        let encoded = encode(&tile, 4, |feature| (*feature).into());
+        let layer = b"\x0a\x0aexclusions";
+        assert!(encoded.windows(layer.len()).any(|window| window == layer));
+        // Feature with id 9, tags and type polygon
+        let feature = [0x08, 9, 0x12, 4, 0, 0, 1, 1, 0x18, 3];
+        assert!(encoded
+            .windows(feature.len())
+            .any(|window| window == feature));
+    }
+
+    #[test]
+    fn merged_pieces() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(1.0, 1.0),
+        });
+        tile.add_way(&[Point::new(0.0, 0.0), Point::new(0.5, 0.0)], 4, 7);
+        tile.add_way(&[Point::new(0.0, 0.5), Point::new(0.5, 0.5)], 4, 7);
+
+        let encoded = encode(&tile, 4, |feature| (*feature).into());
+        #[rustfmt::skip]
+        let feature = [
+            0x12, 24, // Feature
+                0x08, 7, // Id
+                0x12, 4, 0, 0, 1, 1, // Tags
+                0x18, 2, // Type
+                0x22, 12, // Geometry continuing at the first piece's end
+                    9, 0, 0, 10, 4, 0,
+                    9, 3, 4, 10, 4, 0,
+        ];
+        assert!(encoded
+            .windows(feature.len())
+            .any(|window| window == feature));
+        assert_eq!(encoded.windows(2).filter(|w| w == &[0x08, 7]).count(), 1);
+    }
+}
@@ -0,0 +1,302 @@
+//! Compact encoding of a [Tile] using tile-local integer coordinates
+//!
+//! Every point is mapped from the tile's box onto a square grid of `extent` × `extent` cells
+//! (just like an MVT's extent) and rounded to the closest integer.
+//! The integers are stored as the difference to the previous point in the pool
+//! and zigzag encoded, so nearby points end up as small unsigned numbers.
+//!
+//! The axes keep the projection's orientation, i.e. `(0, 0)` is the tile's `min` corner.
+
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::exclusion::ExclusionMask;
+use crate::formats::{Item, Tile};
+use crate::geometry::Point;
+use crate::world::{check_grid, World};
+
+/// Default number of cells per axis, the same as MVT's default extent
+pub const EXTENT: u32 = 4096;
+
+/// Largest number of cells per axis, the tile-local coordinates have to fit into an `i32`
+pub const MAX_EXTENT: u32 = i32::MAX as u32;
+
+/// A [Tile] whose points are quantized, delta and zigzag encoded
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QuantizedTile<Feature> {
+    pub min: Point,
+    pub max: Point,
+
+    /// Number of cells per axis
+    pub extent: u32,
+
+    /// Same as [Tile::areas]
+    pub areas: Vec<Item<Feature, (usize, usize)>>,
+    /// Same as [Tile::nodes]
+    pub nodes: Vec<Item<Feature, usize>>,
+    /// Same as [Tile::ways]
+    pub ways: Vec<Item<Feature, (usize, usize)>>,
+    /// Same as [Tile::rings]
+    pub rings: Vec<(usize, usize)>,
+
+    /// Encoded coordinates, `x` followed by `y` for every point of [Tile::points]
+    pub points: Vec<u32>,
+
+    /// Same as [Tile::exclusions], whose points aren't quantized
+    #[serde(default)]
+    pub exclusions: ExclusionMask,
+}
+
+impl<Feature> QuantizedTile<Feature> {
+    /// Encode a tile using `extent` cells per axis
+    ///
+    /// The tile's spatial index is dropped.
+    pub fn new(tile: Tile<Feature>, extent: u32) -> Self {
+        let Tile {
+            min,
+            max,
+            areas,
+            nodes,
+            ways,
+            rings,
+            points,
+            index: _,
+            exclusions,
+        } = tile;
+
+        let scale = Point::new(
+            extent as f64 / (max.x - min.x),
+            extent as f64 / (max.y - min.y),
+        );
+        let mut previous = [0, 0];
+        let mut encoded = Vec::with_capacity(points.len() * 2);
+        for point in points {
+            let local = (point - min).component_mul(&scale);
+            let current = [local.x.round() as i32, local.y.round() as i32];
+            encoded.push(zigzag_encode(current[0].wrapping_sub(previous[0])));
+            encoded.push(zigzag_encode(current[1].wrapping_sub(previous[1])));
+            previous = current;
+        }
+
+        QuantizedTile {
+            min,
+            max,
+            extent,
+            areas,
+            nodes,
+            ways,
+            rings,
+            points: encoded,
+            exclusions,
+        }
+    }
+
+    /// Decode the tile-local integer coordinates
+    pub fn iter_local(&self) -> impl Iterator<Item = [i32; 2]> + '_ {
+        self.points
+            .chunks_exact(2)
+            .scan([0i32, 0i32], |current, delta| {
+                current[0] = current[0].wrapping_add(zigzag_decode(delta[0]));
+                current[1] = current[1].wrapping_add(zigzag_decode(delta[1]));
+                Some(*current)
+            })
+    }
+
+    /// Decode the tile back into global coordinates
+    ///
+    /// The points are snapped to the cells' corners, i.e. they are off by at most half a cell.
+    pub fn into_tile(self) -> Tile<Feature> {
+        let scale = Point::new(
+            (self.max.x - self.min.x) / self.extent as f64,
+            (self.max.y - self.min.y) / self.extent as f64,
+        );
+        let points = self
+            .iter_local()
+            .map(|[x, y]| self.min + Point::new(x as f64, y as f64).component_mul(&scale))
+            .collect();
+
+        let QuantizedTile {
+            min,
+            max,
+            areas,
+            nodes,
+            ways,
+            rings,
+            exclusions,
+            ..
+        } = self;
+        Tile {
+            min,
+            max,
+            areas,
+            nodes,
+            ways,
+            rings,
+            points,
+            index: None,
+            exclusions,
+        }
+    }
+}
+
+/// A [World] whose tiles are quantized
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QuantizedWorld<Feature, P> {
+    /// Same as [World::origin]
+    pub origin: Point,
+
+    /// Same as [World::step_size]
+    pub step_size: Vector2<f64>,
+
+    pub cols: usize,
+    pub rows: usize,
+
+    /// Same as [World::projection]
+    pub projection: P,
+
+    pub tiles: Vec<QuantizedTile<Feature>>,
+}
+
+impl<Feature, P> QuantizedWorld<Feature, P> {
+    /// Encode a world's tiles using `extent` cells per axis
+    pub fn new(world: World<Feature, P>, extent: u32) -> Self {
+        let World {
+            origin,
+            step_size,
+            cols,
+            rows,
+            projection,
+            tiles,
+        } = world;
+        QuantizedWorld {
+            origin,
+            step_size,
+            cols,
+            rows,
+            projection,
+            tiles: tiles
+                .into_iter()
+                .map(|tile| QuantizedTile::new(tile, extent))
+                .collect(),
+        }
+    }
+
+    /// Same as [World::check], also checking the tiles' extents
+    pub fn check(&self) -> Result<(), Error> {
+        check_grid(self.cols, self.rows, self.step_size, self.tiles.len())?;
+        if let Some(tile) = self
+            .tiles
+            .iter()
+            .find(|tile| !(1..=MAX_EXTENT).contains(&tile.extent))
+        {
+            return Err(Error::Format(format!(
+                "a tile's extent has to be between 1 and {MAX_EXTENT}, got {}",
+                tile.extent
+            )));
+        }
+        Ok(())
+    }
+
+    /// Decode the tiles back into a world, see [QuantizedTile::into_tile]
+    pub fn into_world(self) -> World<Feature, P> {
+        let QuantizedWorld {
+            origin,
+            step_size,
+            cols,
+            rows,
+            projection,
+            tiles,
+        } = self;
+        World {
+            origin,
+            step_size,
+            cols,
+            rows,
+            projection,
+            tiles: tiles.into_iter().map(QuantizedTile::into_tile).collect(),
+        }
+    }
+}
+
+/// Map signed integers onto unsigned ones such that small absolute values stay small
+///
+/// `0, -1, 1, -2, 2, ...` become `0, 1, 2, 3, 4, ...`.
+pub fn zigzag_encode(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+/// Reverse [zigzag_encode]
+pub fn zigzag_decode(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::Vector2;
+
+    use crate::formats::quantized::{zigzag_decode, zigzag_encode, QuantizedTile, QuantizedWorld};
+    use crate::formats::Tile;
+    use crate::geometry::{BBox, Point};
+    use crate::projection::Simple;
+    use crate::world::World;
+
+    #[test]
+    fn zigzag() {
+        let values = [0, -1, 1, -2, 2, i32::MAX, i32::MIN];
+        let encoded: Vec<u32> = values.iter().map(|v| zigzag_encode(*v)).collect();
+        assert_eq!(&encoded[..5], &[0, 1, 2, 3, 4]);
+        for (value, encoded) in values.iter().zip(encoded) {
+            assert_eq!(zigzag_decode(encoded), *value);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut tile = Tile::new(BBox {
+            min: Point::new(0.5, 0.25),
+            max: Point::new(0.75, 0.5),
+        });
+        let way = [
+            Point::new(0.5, 0.25),
+            Point::new(0.6, 0.3),
+            Point::new(0.75, 0.5),
+        ];
+        tile.add_way(&way, 1, 10);
+        tile.add_node(Point::new(0.7, 0.26), 2, 20);
+
+        let quantized = QuantizedTile::new(tile, 4096);
+        assert_eq!(quantized.iter_local().next(), Some([0, 0]));
+        assert_eq!(quantized.iter_local().nth(2), Some([4096, 4096]));
+
+        let tile = quantized.into_tile();
+        let cell = 0.25 / 4096.0;
+        let decoded: Vec<_> = tile.iter_ways().next().unwrap().points.to_vec();
+        for (decoded, original) in decoded.iter().zip(way) {
+            assert!((decoded - original).amax() <= cell / 2.0);
+        }
+        assert_eq!(tile.iter_nodes().next().unwrap().feature, &2);
+    }
+
+    #[test]
+    fn invalid_extent() {
+        let world = |extent| {
+            let tile = Tile::<usize>::new(BBox {
+                min: Point::new(0.0, 0.0),
+                max: Point::new(1.0, 1.0),
+            });
+            let world = World {
+                origin: Point::new(0.0, 0.0),
+                step_size: Vector2::new(1.0, 1.0),
+                cols: 1,
+                rows: 1,
+                projection: Simple,
+                tiles: vec![tile],
+            };
+            QuantizedWorld::new(world, extent)
+        };
+        assert!(world(4096).check().is_ok());
+        assert!(world(0).check().is_err());
+        assert!(world(1 << 31).check().is_err());
+    }
+}
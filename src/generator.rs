@@ -1,23 +1,29 @@
+use std::ops::Range;
+
 use libosmium::handler::Handler;
 use libosmium::node_ref_list::NodeRefList;
-use libosmium::{Area, Node, Way, PRECISION};
+use libosmium::{Area, Location, Node, OSMObject, Way, PRECISION};
 use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
 
-use crate::features::FeatureParser;
-use crate::formats::Tile;
+use crate::features::area::AreaRule;
+use crate::features::{FeatureParser, Tags};
+use crate::formats::{Kind, Tile};
 use crate::geometry::bbox::GenericBox;
 use crate::geometry::grid::Grid;
-use crate::geometry::polygon::combine_rings;
-use crate::geometry::{BBox, Point};
-use crate::projection::Projection;
+use crate::geometry::{polygon, polyline, BBox, Point};
+use crate::metadata::{Excluded, Metadata, MetadataFilter};
+use crate::progress::Monitor;
+use crate::projection::{GetLocation, Projection};
 
 #[derive(Clone)]
 pub struct WorldGenerator<P: Projection, V: FeatureParser> {
+    // The grid's bounding box in libosmium's integer lon/lat coordinates
     pub int_box: GenericBox<i32>,
     pub projection: P,
 
-    // Buffer to copy rings into before combining them.
-    pub rings: Vec<Vec<Point>>,
+    // Buffer for the indexes of the tiles an area's outer ring was added to
+    pub area_tiles: Vec<usize>,
 
     // Grid
     pub grid: Grid,
@@ -28,9 +34,101 @@ pub struct WorldGenerator<P: Projection, V: FeatureParser> {
     pub area_type: V::Feature,
     pub node_type: V::Feature,
     pub way_type: V::Feature,
+
+    // Decides whether closed ways are areas or rings
+    pub area_rule: AreaRule,
+
+    // Progress reporting and cancellation
+    pub monitor: Monitor,
+
+    // Tolerances for simplifying clipped geometries at this zoom
+    pub simplification: Simplification<V::Feature>,
+
+    // Buffer for a simplified ring or path
+    pub simplified: Vec<Point>,
+
+    // Which tiles to generate, all tiles if `None`
+    pub tile_mask: Option<Vec<bool>>,
+
+    // Excludes objects by their latest edit, applied before parsing their features
+    pub metadata_filter: Option<MetadataFilter>,
+
+    // Whether processed objects are counted, bands leave it to the producer feeding them
+    pub count_objects: bool,
+}
+
+/// Tolerances for simplifying areas and ways after clipping
+///
+/// Tolerances are given in tile units, i.e. `0.01` is a hundredth of a tile's width.
+/// The first rule matching a geometry's zoom and feature is used.
+/// Without any matching rule the geometry is left as is.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Simplification<Feature> {
+    pub rules: Vec<SimplificationRule<Feature>>,
+}
+
+/// A tolerance applying to a zoom level and feature
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimplificationRule<Feature> {
+    /// Zoom level to apply to, `None` for all zoom levels
+    pub zoom: Option<u8>,
+
+    /// Feature to apply to, `None` for all features
+    pub feature: Option<Feature>,
+
+    /// Tolerance in tile units, `0.0` disables simplification
+    pub tolerance: f64,
+}
+
+impl<Feature> Simplification<Feature> {
+    /// Drop all rules which don't apply to a zoom level
+    pub fn for_zoom(mut self, zoom: u8) -> Self {
+        self.rules
+            .retain(|rule| rule.zoom.is_none_or(|rule_zoom| rule_zoom == zoom));
+        self
+    }
+}
+
+impl<Feature: PartialEq> Simplification<Feature> {
+    /// Get the tolerance for a feature, ignoring the rules' zoom levels
+    pub fn tolerance(&self, feature: &Feature) -> f64 {
+        self.rules
+            .iter()
+            .find(|rule| rule.feature.as_ref().is_none_or(|f| f == feature))
+            .map_or(0.0, |rule| rule.tolerance)
+    }
+}
+
+impl<Feature> Default for Simplification<Feature> {
+    fn default() -> Self {
+        Self { rules: Vec::new() }
+    }
+}
+
+/// Simplify `points` into `buffer` and return the result
+///
+/// Returns `points` unchanged if `tolerance` isn't positive.
+fn simplify<'p>(
+    points: &'p [Point],
+    tolerance: f64,
+    buffer: &'p mut Vec<Point>,
+    simplify: fn(&[Point], f64, &mut Vec<Point>),
+) -> &'p [Point] {
+    if tolerance <= 0.0 {
+        return points;
+    }
+    buffer.clear();
+    simplify(points, tolerance, buffer);
+    buffer
 }
 
 impl<P: Projection, V: FeatureParser> WorldGenerator<P, V> {
+    /// Create a generator for a grid of `num_cols` by `num_rows` tiles around `center`
+    ///
+    /// The grid consists of whole tiles of the zoom level.
+    /// The tile containing `center` is at the column `num_cols / 2` and the row `num_rows / 2`.
+    /// This lets the tiles be addressed by their zoom level's `x`/`y` keys,
+    /// see [mvt::tile_position](crate::formats::mvt::tile_position).
     pub fn new(
         center: Point,
         (num_cols, num_rows): (usize, usize),
@@ -46,14 +144,18 @@ impl<P: Projection, V: FeatureParser> WorldGenerator<P, V> {
         let step_size = Vector2::new(step_size, step_size);
 
         // The "min" corner of the center tile.
+        // Older versions shifted x by y's remainder, didn't snap y
+        // and put grids with an odd size half a tile off,
+        // so their outputs cover other tiles than the same arguments do now.
         let mut center = projection.project_nalgebra(center);
         center.x -= center.x % step_size.x;
-        center.x -= center.y % step_size.y;
+        center.y -= center.y % step_size.y;
 
         // The "min" corner of the entire grid
+        // (using whole tiles to keep the grid aligned with the zoom level's tiles)
         let min = Vector2::new(
-            center.x - num_cols as f64 * step_size.x / 2.0,
-            center.y - num_rows as f64 * step_size.y / 2.0,
+            center.x - (num_cols / 2) as f64 * step_size.x,
+            center.y - (num_rows / 2) as f64 * step_size.y,
         );
 
         let mut tiles = Vec::with_capacity(num_cols * num_rows);
@@ -78,14 +180,18 @@ impl<P: Projection, V: FeatureParser> WorldGenerator<P, V> {
             ),
         };
 
+        // The projection might flip an axis
+        let a = projection.unproject_nalgebra(bbox.min);
+        let b = projection.unproject_nalgebra(bbox.max);
+
         WorldGenerator {
             int_box: GenericBox {
-                min: bbox.min.map(|f| (f * PRECISION as f64).floor() as i32),
-                max: bbox.max.map(|f| (f * PRECISION as f64).ceil() as i32),
+                min: a.inf(&b).map(|f| (f * PRECISION as f64).floor() as i32),
+                max: a.sup(&b).map(|f| (f * PRECISION as f64).ceil() as i32),
             },
             projection,
 
-            rings: Vec::new(),
+            area_tiles: Vec::new(),
 
             grid: Grid::new(min, Vector2::new(num_cols, num_rows), step_size),
             tiles,
@@ -94,6 +200,59 @@ impl<P: Projection, V: FeatureParser> WorldGenerator<P, V> {
             area_type: Default::default(), // Only every read
             node_type: Default::default(), // directly after
             way_type: Default::default(),  // assignment.
+
+            area_rule: AreaRule::default(),
+
+            monitor: Monitor::default(),
+
+            simplification: Simplification::default(),
+            simplified: Vec::new(),
+
+            tile_mask: None,
+
+            metadata_filter: None,
+
+            count_objects: true,
+        }
+    }
+
+    /// Create an "empty" generator which only covers a horizontal band of this generator's rows
+    pub fn band(&self, rows: Range<usize>) -> Self
+    where
+        V: Clone,
+        V::Feature: Clone,
+    {
+        let cols = self.grid.size().x;
+        WorldGenerator {
+            int_box: self.int_box,
+            projection: self.projection,
+
+            area_tiles: Vec::new(),
+
+            grid: self.grid.band(rows.clone()),
+            tiles: self.tiles[rows.start * cols..rows.end * cols].to_vec(),
+
+            visual_parser: self.visual_parser.clone(),
+            area_type: self.area_type.clone(),
+            node_type: self.node_type.clone(),
+            way_type: self.way_type.clone(),
+
+            area_rule: self.area_rule.clone(),
+
+            monitor: self.monitor.clone(),
+
+            simplification: self.simplification.clone(),
+            simplified: Vec::new(),
+
+            tile_mask: self
+                .tile_mask
+                .as_ref()
+                .map(|mask| mask[rows.start * cols..rows.end * cols].to_vec()),
+
+            // The objects are filtered and counted before being sent to a band,
+            // which might receive an object also sent to other bands
+            metadata_filter: None,
+            count_objects: false,
         }
     }
 
@@ -104,6 +263,67 @@ impl<P: Projection, V: FeatureParser> WorldGenerator<P, V> {
         self.tiles
     }
 
+    /// Check whether the bounding box of some locations overlaps the [int_box](Self::int_box)
+    ///
+    /// This is cheaper than projecting them and rejects most objects outside the grid.
+    pub fn touches_int_box(&self, locations: impl IntoIterator<Item = Location>) -> bool {
+        self.touches(locations.into_iter().filter(Location::is_defined).map(raw))
+    }
+
+    /// Check whether the bounding box of some integer coordinates overlaps the [int_box](Self::int_box)
+    fn touches(&self, corners: impl IntoIterator<Item = Vector2<i32>>) -> bool {
+        let mut bbox = GenericBox::<i32>::default();
+        for corner in corners {
+            bbox.fit(corner);
+        }
+        bbox.overlaps(&self.int_box)
+    }
+
+    /// Check an item's locations against the [int_box](Self::int_box) and count it if it's rejected
+    pub(crate) fn skip<'l, L: GetLocation + 'l>(
+        &self,
+        locations: impl IntoIterator<Item = &'l L>,
+    ) -> bool {
+        let touches = self.touches_int_box(
+            locations
+                .into_iter()
+                .filter_map(|location| location.get_location()),
+        );
+        if !touches && self.count_objects {
+            self.monitor.add_skipped();
+        }
+        !touches
+    }
+
+    /// Get the oid of an object's items, see [Item::oid](crate::formats::Item::oid)
+    ///
+    /// Objects with negative ids, e.g. from an editor's unsaved changes, are counted as skipped.
+    pub(crate) fn oid(&self, id: i64) -> Option<usize> {
+        let oid = usize::try_from(id).ok();
+        if oid.is_none() && self.count_objects {
+            self.monitor.add_skipped();
+        }
+        oid
+    }
+
+    /// Check an object's metadata against the [metadata_filter](Self::metadata_filter) and report it if it's excluded
+    pub(crate) fn exclude(&self, kind: Kind, object: &OSMObject) -> bool {
+        let Some(filter) = self.metadata_filter.as_ref() else {
+            return false;
+        };
+        let metadata = Metadata::from(object);
+        let Some(reason) = filter.check(metadata) else {
+            return false;
+        };
+        self.monitor.exclude(&Excluded {
+            kind,
+            id: object.id(),
+            metadata,
+            reason,
+        });
+        true
+    }
+
     fn iter_nodes(projection: P, nodes: &NodeRefList) -> impl Iterator<Item = Point> + '_ {
         nodes
             .iter()
@@ -113,100 +333,542 @@ impl<P: Projection, V: FeatureParser> WorldGenerator<P, V> {
 
 impl<P: Projection, V: FeatureParser> Handler for WorldGenerator<P, V>
 where
-    V::Feature: Clone,
+    V::Feature: Clone + PartialEq,
 {
     fn area(&mut self, area: &Area) {
-        if area.tags().is_empty() {
+        let corners = area
+            .outer_rings()
+            .flat_map(|ring| ring.iter())
+            .filter_map(|node| node.get_location())
+            .filter(Location::is_defined)
+            .map(raw);
+        let Some(oid) = self.accept(Monitor::add_area, area.id(), corners) else {
             return;
-        }
-        if let Some(feature) = self.visual_parser.area(area.tags()) {
-            self.area_type = feature;
-        } else {
+        };
+        if self.exclude(Kind::Area, area) {
             return;
         }
+        if let Some(class) = self.parse_exclusion(area.id(), area.tags()) {
+            for ring in area.outer_rings() {
+                let polygon: Vec<Point> = Self::iter_nodes(self.projection, ring).collect();
+                self.clip_zone_outer_ring(polygon, class, oid);
+                for inner_ring in area.inner_rings(ring) {
+                    let polygon: Vec<Point> =
+                        Self::iter_nodes(self.projection, inner_ring).collect();
+                    self.clip_zone_inner_ring(polygon);
+                }
+            }
+        }
+        // libosmium's area id which encodes whether it's from a way or relation
+        let Some(tolerance) = self.parse_area(area.id(), area.tags()) else {
+            return;
+        };
 
         for ring in area.outer_rings() {
-            let mut polygon: Vec<Point> = Self::iter_nodes(self.projection, ring).collect();
-
-            // Collect the inner rings into reused vectors
-            let mut num_rings = 0;
+            let polygon: Vec<Point> = Self::iter_nodes(self.projection, ring).collect();
+            self.clip_outer_ring(polygon, oid);
             for inner_ring in area.inner_rings(ring) {
-                // Reuse old Vec or push new one
-                if num_rings < self.rings.len() {
-                    self.rings[num_rings].clear();
-                    let inner_ring = Self::iter_nodes(self.projection, inner_ring);
-                    self.rings[num_rings].extend(inner_ring);
-                } else {
-                    self.rings
-                        .push(Self::iter_nodes(self.projection, inner_ring).collect());
-                }
+                let polygon: Vec<Point> = Self::iter_nodes(self.projection, inner_ring).collect();
+                self.clip_inner_ring(polygon);
+            }
+            self.simplify_areas(tolerance);
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        let location = node.location();
+        let corners = location.is_defined().then(|| raw(location));
+        let Some(oid) = self.accept(Monitor::add_node, node.id(), corners) else {
+            return;
+        };
+        if self.exclude(Kind::Node, node) {
+            return;
+        }
+        if !self.parse_node(node.tags()) {
+            return;
+        }
+        if let Some(point) = self.projection.project(node) {
+            self.clip_node(point, oid);
+        }
+    }
+
+    fn way(&mut self, way: &Way) {
+        let nodes = way.nodes();
+        let corners = nodes
+            .iter()
+            .filter_map(|node| node.get_location())
+            .filter(Location::is_defined)
+            .map(raw);
+        let Some(oid) = self.accept(Monitor::add_way, way.id(), corners) else {
+            return;
+        };
+        if self.exclude(Kind::Way, way) {
+            return;
+        }
+        let Some(tolerance) = self.parse_way(way.tags()) else {
+            return;
+        };
+
+        // Check for closed ways (only checking nodes' ids)
+        let closed = match (nodes.first(), nodes.last()) {
+            (Some(first), Some(last)) => first.id == last.id,
+            _ => return,
+        };
+        if closed && self.area_rule.is_area(way.tags()) {
+            // Closed ways which are areas are handled in `area`
+            return;
+        }
+        let path = Self::iter_nodes(self.projection, nodes);
+        self.clip_way(path, closed, oid, tolerance);
+    }
+}
+
+/// Implements generating from osm objects which don't come from libosmium
+///
+/// Locations are given in degrees as `(lon, lat)`.
+/// See [MemorySource](crate::memory::MemorySource) for a more convenient interface.
+impl<P: Projection, V: FeatureParser> WorldGenerator<P, V>
+where
+    V::Feature: Clone + PartialEq,
+{
+    /// Add a node
+    pub fn add_node<'t>(&mut self, id: i64, tags: impl Tags<'t>, location: Point) {
+        let Some(oid) = self.accept(Monitor::add_node, id, [to_int(location)]) else {
+            return;
+        };
+        if self.parse_node(tags) {
+            let point = self.projection.project_nalgebra(location);
+            self.clip_node(point, oid);
+        }
+    }
+
+    /// Add a way
+    ///
+    /// A way is closed if its first and last location are equal.
+    /// Closed ways which are areas according to the [area_rule](Self::area_rule) are ignored,
+    /// they have to be added as areas using [add_area](Self::add_area).
+    pub fn add_way<'t>(&mut self, id: i64, tags: impl Tags<'t> + Clone, locations: &[Point]) {
+        let corners = locations.iter().copied().map(to_int);
+        let Some(oid) = self.accept(Monitor::add_way, id, corners) else {
+            return;
+        };
+        let Some(tolerance) = self.parse_way(tags.clone()) else {
+            return;
+        };
 
-                // Only count non-empty rings
-                if !self.rings[num_rings].is_empty() {
-                    num_rings += 1;
+        let closed = match (locations.first(), locations.last()) {
+            (Some(first), Some(last)) => first == last,
+            _ => return,
+        };
+        if closed && self.area_rule.is_area(tags) {
+            return;
+        }
+        let projection = self.projection;
+        let path = locations
+            .iter()
+            .map(|location| projection.project_nalgebra(*location));
+        self.clip_way(path, closed, oid, tolerance);
+    }
+
+    /// Add an area consisting of outer rings paired with their inner rings
+    ///
+    /// The `id` is encoded like libosmium's area ids:
+    /// `2 * id` for areas from closed ways and `2 * id + 1` for areas from multipolygon relations.
+    pub fn add_area<'t>(
+        &mut self,
+        id: i64,
+        tags: impl Tags<'t> + Clone,
+        rings: &[(Vec<Point>, Vec<Vec<Point>>)],
+    ) {
+        let corners = rings
+            .iter()
+            .flat_map(|(outer, _)| outer.iter().copied().map(to_int));
+        let Some(oid) = self.accept(Monitor::add_area, id, corners) else {
+            return;
+        };
+
+        let projection = self.projection;
+        let project = |ring: &[Point]| -> Vec<Point> {
+            ring.iter()
+                .map(|location| projection.project_nalgebra(*location))
+                .collect()
+        };
+        if let Some(class) = self.parse_exclusion(id, tags.clone()) {
+            for (outer, inner) in rings {
+                self.clip_zone_outer_ring(project(outer), class, oid);
+                for inner in inner {
+                    self.clip_zone_inner_ring(project(inner));
                 }
             }
-            // Add the inner rings to the outer ring before clipping
-            if num_rings > 0 {
-                combine_rings(&mut polygon, &mut self.rings[0..num_rings]);
+        }
+        let Some(tolerance) = self.parse_area(id, tags) else {
+            return;
+        };
+        for (outer, inner) in rings {
+            self.clip_outer_ring(project(outer), oid);
+            for inner in inner {
+                self.clip_inner_ring(project(inner));
+            }
+            self.simplify_areas(tolerance);
+        }
+    }
+}
+
+/// Implements the steps shared by all inputs
+impl<P: Projection, V: FeatureParser> WorldGenerator<P, V>
+where
+    V::Feature: Clone + PartialEq,
+{
+    /// Count an object and get its oid if it should be processed
+    ///
+    /// `corners` are its locations in libosmium's integer coordinates which are checked against the [int_box](Self::int_box).
+    fn accept(
+        &self,
+        count: fn(&Monitor),
+        id: i64,
+        corners: impl IntoIterator<Item = Vector2<i32>>,
+    ) -> Option<usize> {
+        if self.monitor.is_cancelled() {
+            return None;
+        }
+        if self.count_objects {
+            count(&self.monitor);
+        }
+        self.monitor.report();
+
+        if !self.touches(corners) {
+            if self.count_objects {
+                self.monitor.add_skipped();
             }
+            return None;
+        }
+        self.oid(id)
+    }
 
-            self.grid.clip_polygon(polygon, |index, polygon| {
-                if let Some(tile) = self.tiles.get_mut(index) {
-                    if !polygon.is_empty() {
-                        tile.add_area(polygon, self.area_type.clone());
-                    }
-                }
-            });
+    /// Parse an area's feature into `area_type` and get its tolerance, `None` if it should be ignored
+    fn parse_area<'t>(&mut self, id: i64, tags: impl Tags<'t> + Clone) -> Option<f64> {
+        tags.clone().into_iter().next()?;
+        // Closed ways which aren't areas are handled as rings in `way`.
+        // libosmium derives an area's id from its origin's id: `2 * id` for ways and `2 * id + 1` for relations.
+        if id % 2 == 0 && !self.area_rule.is_area(tags.clone()) {
+            return None;
         }
+        self.area_type = self.visual_parser.area(tags)?;
+        // Tolerance in the map's coordinates
+        Some(self.simplification.tolerance(&self.area_type) * self.grid.step_size().x)
     }
 
-    fn node(&mut self, node: &Node) {
-        if node.tags().is_empty() {
-            return;
+    /// Get the class of exclusion zone an area is, `None` if it isn't one
+    fn parse_exclusion<'t>(&self, id: i64, tags: impl Tags<'t> + Clone) -> Option<usize> {
+        tags.clone().into_iter().next()?;
+        if id % 2 == 0 && !self.area_rule.is_area(tags.clone()) {
+            return None;
         }
-        if let Some(feature) = self.visual_parser.node(node.tags()) {
+        self.visual_parser.exclusion(tags)
+    }
+
+    /// Parse a node's feature into `node_type` and check whether it should be added
+    fn parse_node<'t>(&mut self, tags: impl Tags<'t>) -> bool {
+        let mut tags = tags.into_iter().peekable();
+        if tags.peek().is_none() {
+            return false;
+        }
+        if let Some(feature) = self.visual_parser.node(tags) {
             self.node_type = feature;
+            true
         } else {
-            return;
+            false
         }
+    }
 
-        if let Some(point) = self.projection.project(node) {
-            self.grid.clip_point(point, |index, point| {
-                if let Some(tile) = self.tiles.get_mut(index) {
-                    tile.add_node(point, self.node_type.clone());
+    /// Parse a way's feature into `way_type` and get its tolerance, `None` if it should be ignored
+    fn parse_way<'t>(&mut self, tags: impl Tags<'t>) -> Option<f64> {
+        let mut tags = tags.into_iter().peekable();
+        tags.peek()?;
+        self.way_type = self.visual_parser.way(tags)?;
+        // Tolerance in the map's coordinates
+        Some(self.simplification.tolerance(&self.way_type) * self.grid.step_size().x)
+    }
+
+    /// Clip an area's outer ring and remember which tiles it was added to
+    fn clip_outer_ring(&mut self, polygon: Vec<Point>, oid: usize) {
+        // `clip_polygon` publishes the tiles in ascending order.
+        self.area_tiles.clear();
+        self.grid.clip_polygon(polygon, |index, polygon| {
+            if let Some(tile) = tile_mut(&mut self.tiles, &self.tile_mask, index) {
+                if !polygon.is_empty() {
+                    tile.add_area(polygon, self.area_type.clone(), oid);
+                    self.area_tiles.push(index);
                 }
-            });
-        }
+            }
+        });
     }
 
-    fn way(&mut self, way: &Way) {
-        if way.tags().is_empty() {
+    /// Clip an inner ring and add it to the tiles' last area
+    fn clip_inner_ring(&mut self, polygon: Vec<Point>) {
+        if polygon.is_empty() {
             return;
         }
-        if let Some(feature) = self.visual_parser.way(way.tags()) {
-            self.way_type = feature;
-        } else {
+        self.grid.clip_polygon(polygon, |index, polygon| {
+            if polygon.is_empty() || self.area_tiles.binary_search(&index).is_err() {
+                return;
+            }
+            if let Some(tile) = tile_mut(&mut self.tiles, &self.tile_mask, index) {
+                tile.add_hole(polygon);
+            }
+        });
+    }
+
+    /// Simplify the last area of the tiles an outer ring was added to together with its holes
+    ///
+    /// The vertices on a tile's edges are kept, so the area's pieces still meet at the edges.
+    fn simplify_areas(&mut self, tolerance: f64) {
+        if tolerance <= 0.0 {
             return;
         }
+        let mut ranges = Vec::new();
+        for &index in self.area_tiles.iter() {
+            let tile = &mut self.tiles[index];
+            let Some(last) = tile.areas.len().checked_sub(1) else {
+                continue;
+            };
+            let rings: Vec<&[Point]> = tile.area(last).points.iter().collect();
 
-        let nodes = way.nodes();
+            // The clipping computes the edges' intersections, which might be off by a rounding error
+            let (min, max) = (tile.min, tile.max);
+            let epsilon = (max.x - min.x) * 1e-9;
+            let on_edge = |point: Point| {
+                (point.x - min.x).abs() <= epsilon
+                    || (point.x - max.x).abs() <= epsilon
+                    || (point.y - min.y).abs() <= epsilon
+                    || (point.y - max.y).abs() <= epsilon
+            };
 
-        // Skip closed ways (only checking nodes' ids)
-        match (nodes.first(), nodes.last()) {
-            (Some(first), Some(last)) => {
-                if first.id == last.id {
-                    return;
+            self.simplified.clear();
+            ranges.clear();
+            polygon::simplify_rings(
+                &rings,
+                tolerance,
+                on_edge,
+                &mut self.simplified,
+                &mut ranges,
+            );
+            tile.replace_last_rings(ranges.iter().map(|&(from, to)| &self.simplified[from..to]));
+        }
+    }
+
+    /// Clip an exclusion zone's outer ring into the tiles' masks and remember which tiles it was added to
+    fn clip_zone_outer_ring(&mut self, polygon: Vec<Point>, class: usize, oid: usize) {
+        self.area_tiles.clear();
+        self.grid.clip_polygon(polygon, |index, polygon| {
+            if let Some(tile) = tile_mut(&mut self.tiles, &self.tile_mask, index) {
+                if !polygon.is_empty() {
+                    tile.exclusions.add_zone(polygon, class, oid);
+                    self.area_tiles.push(index);
                 }
             }
-            _ => return,
+        });
+    }
+
+    /// Clip an inner ring and add it to the tiles' last exclusion zone
+    fn clip_zone_inner_ring(&mut self, polygon: Vec<Point>) {
+        if polygon.is_empty() {
+            return;
         }
+        self.grid.clip_polygon(polygon, |index, polygon| {
+            if polygon.is_empty() || self.area_tiles.binary_search(&index).is_err() {
+                return;
+            }
+            if let Some(tile) = tile_mut(&mut self.tiles, &self.tile_mask, index) {
+                tile.exclusions.add_hole(polygon);
+            }
+        });
+    }
 
-        self.grid
-            .clip_path(Self::iter_nodes(self.projection, nodes), |index, path| {
-                if let Some(tile) = self.tiles.get_mut(index) {
-                    tile.add_way(path, self.way_type.clone());
-                }
-            });
+    fn clip_node(&mut self, point: Point, oid: usize) {
+        self.grid.clip_point(point, |index, point| {
+            if let Some(tile) = tile_mut(&mut self.tiles, &self.tile_mask, index) {
+                tile.add_node(point, self.node_type.clone(), oid);
+            }
+        });
+    }
+
+    /// Clip a way as path or, if it's closed, as ring
+    fn clip_way(
+        &mut self,
+        path: impl Iterator<Item = Point>,
+        closed: bool,
+        oid: usize,
+        tolerance: f64,
+    ) {
+        let mut publish = |index: usize, path: &[Point]| {
+            if let Some(tile) = tile_mut(&mut self.tiles, &self.tile_mask, index) {
+                let path = simplify(path, tolerance, &mut self.simplified, polyline::simplify);
+                tile.add_way(path, self.way_type.clone(), oid);
+            }
+        };
+        if closed {
+            self.grid.clip_ring(path, &mut publish);
+        } else {
+            self.grid.clip_path(path, &mut publish);
+        }
+    }
+}
+
+/// Get a tile unless it's excluded by the mask
+fn tile_mut<'t, F>(
+    tiles: &'t mut [Tile<F>],
+    mask: &Option<Vec<bool>>,
+    index: usize,
+) -> Option<&'t mut Tile<F>> {
+    match mask {
+        Some(mask) if !mask.get(index).copied().unwrap_or(false) => None,
+        _ => tiles.get_mut(index),
+    }
+}
+
+/// Convert a location in degrees into libosmium's integer coordinates
+fn to_int(location: Point) -> Vector2<i32> {
+    location.map(|f| (f * PRECISION as f64).round() as i32)
+}
+
+/// Get a libosmium location's integer coordinates
+fn raw(location: Location) -> Vector2<i32> {
+    Vector2::new(location.raw_x, location.raw_y)
+}
+
+#[cfg(test)]
+mod test {
+    use libosmium::{Location, PRECISION};
+    use nalgebra::Vector2;
+
+    use crate::features::config::ConfigParser;
+    use crate::generator::WorldGenerator;
+    use crate::memory::MemorySource;
+    use crate::projection::{Projection, WebMercator};
+
+    #[test]
+    fn grid_aligned_to_zoom() {
+        let ast = ConfigParser::borrowing().parse_file("").unwrap();
+        let center = Vector2::new(13.4, 52.5);
+        let (cols, rows) = (3, 2);
+        let generator = WorldGenerator::new(center, (cols, rows), 2, ast, WebMercator);
+
+        // The tiles are whole tiles of the zoom level
+        for tile in generator.tiles.iter() {
+            let index = tile.min * 4.0;
+            assert!((index - index.map(f64::round)).norm() < 1e-9);
+            assert!((tile.max - tile.min - Vector2::new(0.25, 0.25)).norm() < 1e-9);
+        }
+
+        // The tile at the grid's center contains the center
+        let center = WebMercator.project_nalgebra(center);
+        let tile = &generator.tiles[rows / 2 * cols + cols / 2];
+        assert!(tile.min.x <= center.x && center.x < tile.max.x);
+        assert!(tile.min.y <= center.y && center.y < tile.max.y);
+    }
+
+    #[test]
+    fn int_box_in_lon_lat() {
+        let ast = ConfigParser::borrowing().parse_file("").unwrap();
+
+        // The right half of the world at zoom 1
+        let generator = WorldGenerator::new(Vector2::new(90.0, 0.0), (1, 2), 1, ast, WebMercator);
+        let int_box = generator.int_box;
+        let degrees = |value: i32| value as f64 / PRECISION as f64;
+        assert!(degrees(int_box.min.x).abs() < 1e-6);
+        assert!((degrees(int_box.max.x) - 180.0).abs() < 1e-6);
+        assert!((degrees(int_box.min.y) + 85.0511).abs() < 1e-4);
+        assert!((degrees(int_box.max.y) - 85.0511).abs() < 1e-4);
+    }
+
+    #[test]
+    fn locations_against_int_box() {
+        let ast = ConfigParser::borrowing().parse_file("").unwrap();
+
+        // The right half of the world at zoom 1
+        let generator = WorldGenerator::new(Vector2::new(90.0, 0.0), (1, 2), 1, ast, WebMercator);
+        let location = |lon: f64, lat: f64| Location {
+            raw_x: (lon * PRECISION as f64) as i32,
+            raw_y: (lat * PRECISION as f64) as i32,
+        };
+        let undefined = Location {
+            raw_x: i32::MAX,
+            raw_y: i32::MAX,
+        };
+
+        assert!(generator.touches_int_box([location(90.0, 45.0)]));
+        assert!(!generator.touches_int_box([location(-90.0, 45.0)]));
+        assert!(!generator.touches_int_box([location(90.0, 89.0)]));
+
+        // The box spanned by the locations touches the grid
+        assert!(generator.touches_int_box([location(-90.0, 10.0), location(170.0, 10.0)]));
+
+        // Undefined locations are ignored
+        assert!(generator.touches_int_box([undefined, location(90.0, 45.0)]));
+        assert!(!generator.touches_int_box([undefined]));
+    }
+
+    #[test]
+    fn in_memory_objects() {
+        let ast = ConfigParser::borrowing()
+            .parse_file(
+                r#"
+                [Areas]
+                1: "leisure" is "park"
+                [Nodes]
+                2: "amenity" exists
+                [Ways]
+                3: "highway" exists
+                "#,
+            )
+            .unwrap();
+
+        // The right half of the world at zoom 1
+        let mut generator =
+            WorldGenerator::new(Vector2::new(90.0, 0.0), (1, 2), 1, ast, WebMercator);
+        let park = [
+            Vector2::new(100.0, -20.0),
+            Vector2::new(120.0, -20.0),
+            Vector2::new(120.0, -10.0),
+            Vector2::new(100.0, -20.0),
+        ];
+        let mut source = MemorySource::new();
+        source
+            .node(1, &[("amenity", "bench")], Vector2::new(90.0, 45.0))
+            .node(2, &[("amenity", "bench")], Vector2::new(-90.0, 45.0))
+            .way(
+                3,
+                &[("highway", "path")],
+                &[Vector2::new(-90.0, 10.0), Vector2::new(90.0, 10.0)],
+            )
+            .way(
+                4,
+                &[("highway", "path")],
+                &[Vector2::new(-90.0, 10.0), Vector2::new(-10.0, 10.0)],
+            )
+            .multipolygon(5, &[("leisure", "park")], vec![(park.to_vec(), Vec::new())]);
+        source.apply(&mut generator);
+
+        let progress = generator.monitor.progress();
+        assert_eq!((progress.nodes, progress.ways, progress.areas), (2, 2, 1));
+        assert_eq!(progress.skipped, 2);
+
+        let tiles = generator.into_tiles();
+        let node = tiles[0].iter_nodes().next().unwrap();
+        assert_eq!((node.oid, *node.feature), (1, 2));
+        assert!(
+            (node.points - WebMercator.project_nalgebra(Vector2::new(90.0, 45.0))).norm() < 1e-9
+        );
+
+        // The way is cut at the grid's left border
+        let way = tiles[0].iter_ways().next().unwrap();
+        assert_eq!((way.oid, *way.feature), (3, 3));
+        assert!((way.points[0].x - 0.5).abs() < 1e-9);
+        assert_eq!(tiles[0].ways.len(), 1);
+
+        let area = tiles[1].iter_areas().next().unwrap();
+        assert_eq!((area.oid, *area.feature), (11, 1));
+        assert!(tiles[1].nodes.is_empty() && tiles[1].ways.is_empty());
     }
 }
@@ -7,9 +7,10 @@
 use super::primitives::{Gt, HalfPlane, Lt, X, Y};
 use super::Point;
 use nalgebra::{Scalar, Vector2};
+use serde::{Deserialize, Serialize};
 
 /// An axis aligned bounding box
-#[derive(Copy, Clone, Debug)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub struct GenericBox<T: Scalar> {
     pub min: Vector2<T>,
     pub max: Vector2<T>,
@@ -48,6 +49,15 @@ impl<T: PartialOrd + Scalar + Copy> GenericBox<T> {
         }
     }
 
+    /// Check if two bounding boxes share at least a single point
+    #[inline]
+    pub fn overlaps(&self, other: &GenericBox<T>) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+    }
+
     /// Check if two bounding boxes intersect
     #[allow(dead_code)]
     pub fn intersects_box(&self, other: GenericBox<T>) -> bool {
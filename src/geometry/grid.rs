@@ -3,6 +3,7 @@ use super::{bbox::BBox, Point};
 use crate::geometry::bbox::GenericBox;
 use nalgebra::Vector2;
 use smallvec::SmallVec;
+use std::ops::Range;
 
 pub type Index = Vector2<isize>;
 pub type IndexBox = GenericBox<isize>;
@@ -40,6 +41,54 @@ impl Grid {
         }
     }
 
+    /// Get the number of boxes in each direction
+    pub fn size(&self) -> Vector2<usize> {
+        self.boxes_num.map(|i| i as usize)
+    }
+
+    /// Get the "min" corner of the first box
+    pub fn min(&self) -> Vector2<f64> {
+        self.boundary.min
+    }
+
+    /// Get the size of each box
+    pub fn step_size(&self) -> Vector2<f64> {
+        self.boxes_size
+    }
+
+    /// Create a grid which only covers a horizontal band of this grid's rows
+    ///
+    /// The new grid's indexes start at the band's first row.
+    /// Since the tiles are stored row-major, concatenating the bands' tiles produces this grid's tiles.
+    pub fn band(&self, rows: Range<usize>) -> Self {
+        let min = Vector2::new(
+            self.boundary.min.x,
+            self.boundary.min.y + rows.start as f64 * self.boxes_size.y,
+        );
+        Self::new(
+            min,
+            Vector2::new(self.boxes_num.x as usize, rows.len()),
+            self.boxes_size,
+        )
+    }
+
+    /// Get the range of rows the bounding box of some points touches
+    ///
+    /// Returns `None` if the points lie entirely above or below the grid.
+    pub fn row_range(&self, points: impl IntoIterator<Item = Point>) -> Option<Range<usize>> {
+        let mut min = isize::MAX;
+        let mut max = isize::MIN;
+        for point in points {
+            let row = self.lookup_point(point).y;
+            min = min.min(row);
+            max = max.max(row);
+        }
+        if max < 0 || min >= self.boxes_num.y {
+            return None;
+        }
+        Some(min.max(0) as usize..(max + 1).min(self.boxes_num.y) as usize)
+    }
+
     fn flatten_index(&self, index: Index) -> Option<usize> {
         if (0..self.boxes_num.x).contains(&index.x) && (0..self.boxes_num.y).contains(&index.y) {
             Some((index.x + self.boxes_num.x * index.y) as usize)
@@ -68,9 +117,16 @@ impl Grid {
         point: Point,
         publish: &mut impl FnMut(usize, &[Point]),
     ) {
-        let Some(index) = self.flatten_index(index) else {return;};
+        self.path_push(index, point);
+        self.path_flush(index, publish);
+    }
+
+    /// Publish a partial path and then empty it.
+    fn path_flush(&mut self, index: Index, publish: &mut impl FnMut(usize, &[Point])) {
+        let Some(index) = self.flatten_index(index) else {
+            return;
+        };
         if let Some(path) = self.path_buffer.get_mut(index) {
-            path.push(point);
             publish(index, path);
             path.clear();
         }
@@ -127,7 +183,7 @@ impl Grid {
             index_box.max.x = size.x;
         }
         if index_box.max.y > size.y {
-            index_box.max.y = size.x;
+            index_box.max.y = size.y;
         }
 
         // Three reusable vectors for the clipping process
@@ -234,7 +290,50 @@ impl Grid {
             current_i = self.lookup_point(next_p);
         }
 
-        self.path_publish(current_i, current_p, &mut publish);
+        // The last point has already been pushed in the loop above
+        self.path_flush(current_i, &mut publish);
+    }
+
+    /// Clip a closed path whose last point equals its first
+    ///
+    /// Unlike [`clip_path`], this joins the pieces at the ring's start point.
+    /// A ring lying entirely inside a tile is published as a single closed path.
+    ///
+    /// [`clip_path`]: Grid::clip_path
+    pub fn clip_ring(
+        &mut self,
+        ring: impl Iterator<Item = Point>,
+        mut publish: impl FnMut(usize, &[Point]),
+    ) {
+        // Hold back the first and the latest piece, because they might need to be joined.
+        let mut first: Option<(usize, Vec<Point>)> = None;
+        let mut last: Option<(usize, Vec<Point>)> = None;
+        self.clip_path(ring, |index, path| {
+            if first.is_none() {
+                first = Some((index, path.to_vec()));
+            } else if let Some((previous, previous_path)) = last.as_mut() {
+                publish(*previous, previous_path);
+                *previous = index;
+                previous_path.clear();
+                previous_path.extend_from_slice(path);
+            } else {
+                last = Some((index, path.to_vec()));
+            }
+        });
+
+        match (first, last) {
+            (Some((first_index, first_path)), Some((last_index, mut last_path)))
+                if first_index == last_index && first_path.first() == last_path.last() =>
+            {
+                last_path.extend_from_slice(&first_path[1..]);
+                publish(last_index, &last_path);
+            }
+            (first, last) => {
+                for (index, path) in first.into_iter().chain(last) {
+                    publish(index, &path);
+                }
+            }
+        }
     }
 
     pub fn clip_point(&mut self, point: Point, mut publish: impl FnMut(usize, Point)) {
@@ -244,3 +343,107 @@ impl Grid {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::geometry::grid::Grid;
+    use crate::geometry::Point;
+    use nalgebra::Vector2;
+
+    /// Two unit squares next to each other
+    fn grid() -> Grid {
+        Grid::new(
+            Vector2::new(0.0, 0.0),
+            Vector2::new(2, 1),
+            Vector2::new(1.0, 1.0),
+        )
+    }
+
+    fn clip_ring(ring: &[Point]) -> Vec<(usize, Vec<Point>)> {
+        let mut pieces = Vec::new();
+        grid().clip_ring(ring.iter().copied(), |index, path| {
+            pieces.push((index, path.to_vec()))
+        });
+        pieces
+    }
+
+    #[test]
+    fn ring_inside_tile() {
+        let ring = [
+            Point::new(0.25, 0.25),
+            Point::new(0.75, 0.25),
+            Point::new(0.75, 0.75),
+            Point::new(0.25, 0.25),
+        ];
+        assert_eq!(clip_ring(&ring), vec![(0, ring.to_vec())]);
+    }
+
+    #[test]
+    fn ring_across_tiles() {
+        let ring = [
+            Point::new(0.5, 0.25),
+            Point::new(1.5, 0.25),
+            Point::new(1.5, 0.75),
+            Point::new(0.5, 0.75),
+            Point::new(0.5, 0.25),
+        ];
+        assert_eq!(
+            clip_ring(&ring),
+            vec![
+                (
+                    1,
+                    vec![
+                        Point::new(1.0, 0.25),
+                        Point::new(1.5, 0.25),
+                        Point::new(1.5, 0.75),
+                        Point::new(1.0, 0.75),
+                    ]
+                ),
+                (
+                    0,
+                    vec![
+                        Point::new(1.0, 0.75),
+                        Point::new(0.5, 0.75),
+                        Point::new(0.5, 0.25),
+                        Point::new(1.0, 0.25),
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn polygon_on_tall_grid() {
+        // Reaches past the grid, whose rows used to be clamped to the number of columns
+        let mut grid = Grid::new(
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1, 3),
+            Vector2::new(1.0, 1.0),
+        );
+        let polygon = vec![
+            Point::new(0.25, 0.5),
+            Point::new(0.75, 0.5),
+            Point::new(0.75, 4.5),
+            Point::new(0.25, 4.5),
+        ];
+        let mut indexes = Vec::new();
+        grid.clip_polygon(polygon, |index, points| {
+            if !points.is_empty() {
+                indexes.push(index);
+            }
+        });
+        assert_eq!(indexes, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn row_range() {
+        let grid = grid();
+        assert_eq!(grid.row_range([Point::new(0.5, 0.5)]), Some(0..1));
+        assert_eq!(
+            grid.row_range([Point::new(0.5, -0.5), Point::new(0.5, 1.5)]),
+            Some(0..1)
+        );
+        assert_eq!(grid.row_range([Point::new(0.5, 1.5)]), None);
+        assert_eq!(grid.row_range([]), None);
+    }
+}
@@ -3,7 +3,19 @@ pub mod grid;
 pub mod polygon;
 pub mod polyline;
 pub mod primitives;
+pub mod rtree;
 
 pub use bbox::BBox;
 
 pub type Point = nalgebra::Vector2<f64>;
+
+/// A square ring from `(min, min)` to `(max, max)` without repeating its first point, used by the tests
+#[cfg(test)]
+pub(crate) fn square(min: f64, max: f64) -> [Point; 4] {
+    [
+        Point::new(min, min),
+        Point::new(max, min),
+        Point::new(max, max),
+        Point::new(min, max),
+    ]
+}
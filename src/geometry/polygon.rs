@@ -1,78 +1,8 @@
 //! Various function working with polygons
 
+use crate::geometry::polyline::{distance_to_segment, mark_kept};
 use crate::geometry::primitives::{Line, X};
-use crate::geometry::Point;
-
-/// Combine an outer ring with its inner rings into a single polygon
-pub fn combine_rings(outer_ring: &mut Vec<Point>, inner_rings: &mut [Vec<Point>]) {
-    let mut temp = Vec::new();
-
-    // Find the points in the inner rings whose x coordinate is lowest
-    let mut inner_indexes = Vec::with_capacity(inner_rings.len());
-    for inner_ring in inner_rings.iter() {
-        inner_indexes.push(
-            inner_ring
-                .iter()
-                .enumerate()
-                .min_by(|(_, &a), (_, &b)| a.x.partial_cmp(&b.x).unwrap())
-                .map(|(index, _)| index)
-                .unwrap_or(0),
-        );
-    }
-
-    // Rotate the inner rings to make their selected point the first in memory
-    for (i, inner_ring) in inner_rings.iter_mut().enumerate() {
-        let index = inner_indexes[i];
-        temp.clear();
-        temp.extend_from_slice(&inner_ring[index..]);
-        temp.extend_from_slice(&inner_ring[0..index]);
-        inner_ring.clone_from(&temp);
-    }
-
-    // Sort inner rings by their point with lowest x
-    inner_rings.sort_by(|ring1, ring2| {
-        let coord1 = ring1.first().map_or(f64::INFINITY, |point| point.x);
-        let coord2 = ring2.first().map_or(f64::INFINITY, |point| point.x);
-        coord1
-            .partial_cmp(&coord2)
-            .unwrap_or(std::cmp::Ordering::Equal)
-    });
-
-    // Merge the inner rings into the outer ring
-    for inner_ring in inner_rings.iter_mut() {
-        // Take any point from the inner ring
-        let inner_point = if let Some(&point) = inner_ring.first() {
-            point
-        } else {
-            // Skip empty inner ring
-            continue;
-        };
-
-        // Find the point on the outer ring which is closest to the arbitrary inner one
-        let index = outer_ring
-            .iter()
-            .enumerate()
-            .filter(|(_, point)| point.x <= inner_point.x)
-            .map(|(index, point)| (index, (point - inner_point).norm_squared()))
-            .min_by(|(_, dist_a), (_, dist_b)| dist_a.partial_cmp(dist_b).unwrap())
-            .map(|(index, _)| index);
-        let index = if let Some(index) = index {
-            index
-        } else {
-            // Skip if:
-            // - outer ring is empty (i.e. iter() didn't yield anything)
-            // - the inner ring's most left point lies left of the outer ring's ones
-            continue;
-        };
-
-        // Add the inner ring starting at the inner point to the outer ring after the outer point
-        // and add the outer point again
-        inner_ring.push(inner_point);
-        inner_ring.extend_from_slice(&outer_ring[index..]);
-        outer_ring.truncate(index + 1);
-        outer_ring.extend_from_slice(inner_ring);
-    }
-}
+use crate::geometry::{BBox, Point};
 
 /// Check whether a polygon contains a point.
 ///
@@ -99,6 +29,488 @@ pub fn contains_point(polygon: &[Point], point: Point) -> bool {
     winding_number != 0
 }
 
+/// Simplify a polygon using the [Douglas-Peucker algorithm](https://en.wikipedia.org/wiki/Ramer%E2%80%93Douglas%E2%80%93Peucker_algorithm)
+///
+/// The result never has less than 3 vertices.
+/// If simplifying would make a simple polygon self-intersecting,
+/// the tolerance is halved a few times before falling back to the unsimplified polygon.
+/// The simplified polygon is appended to `output`.
+pub fn simplify(polygon: &[Point], tolerance: f64, output: &mut Vec<Point>) {
+    simplify_rings(&[polygon], tolerance, |_| false, output, &mut Vec::new());
+}
+
+/// Simplify an area's outer ring and holes together, see [simplify]
+///
+/// Vertices for which `pinned` returns `true` are always kept, e.g. the ones a tile's edges created.
+/// If simplifying would make the rings intersect themselves or each other,
+/// while they didn't before, the tolerance is halved a few times before falling back to the unsimplified rings.
+/// The simplified rings' points are appended to `output` and their ranges into it to `ranges`.
+pub fn simplify_rings(
+    rings: &[&[Point]],
+    tolerance: f64,
+    pinned: impl Fn(Point) -> bool,
+    output: &mut Vec<Point>,
+    ranges: &mut Vec<(usize, usize)>,
+) {
+    let start = (output.len(), ranges.len());
+    let mut tolerance = tolerance;
+    let mut is_simple = None;
+    for _ in 0..4 {
+        for ring in rings {
+            let ring_start = output.len();
+            simplify_unchecked(ring, tolerance, &pinned, output);
+            ranges.push((ring_start, output.len()));
+        }
+        let simplified: Vec<&[Point]> = ranges[start.1..]
+            .iter()
+            .map(|&(from, to)| &output[from..to])
+            .collect();
+        if !rings_intersect(&simplified)
+            || !*is_simple.get_or_insert_with(|| !rings_intersect(rings))
+        {
+            return;
+        }
+        output.truncate(start.0);
+        ranges.truncate(start.1);
+        tolerance /= 2.0;
+    }
+    for ring in rings {
+        let ring_start = output.len();
+        output.extend_from_slice(ring);
+        ranges.push((ring_start, output.len()));
+    }
+}
+
+/// Simplify a polygon without checking the result for self-intersections
+fn simplify_unchecked(
+    polygon: &[Point],
+    tolerance: f64,
+    pinned: impl Fn(Point) -> bool,
+    output: &mut Vec<Point>,
+) {
+    let len = polygon.len();
+    if len <= 3 {
+        output.extend_from_slice(polygon);
+        return;
+    }
+
+    // Split the polygon into two polylines at the vertex farthest from the first one
+    let farthest = (1..len)
+        .max_by(|&a, &b| {
+            let a = polygon[a].metric_distance(&polygon[0]);
+            let b = polygon[b].metric_distance(&polygon[0]);
+            a.total_cmp(&b)
+        })
+        .unwrap_or(1);
+
+    let mut closed = Vec::with_capacity(len + 1);
+    closed.extend_from_slice(polygon);
+    closed.push(polygon[0]);
+
+    let mut keep: Vec<bool> = closed.iter().map(|&point| pinned(point)).collect();
+    keep[0] = true;
+    keep[farthest] = true;
+    keep[len] = true;
+
+    // Further split the polylines at the pinned vertices
+    let anchors: Vec<usize> = (0..=len).filter(|&index| keep[index]).collect();
+    for range in anchors.windows(2) {
+        mark_kept(&closed, range[0], range[1], tolerance, &mut keep);
+    }
+
+    // Keep the vertex farthest from the split's diagonal if only its ends are left
+    if keep[..len].iter().filter(|keep| **keep).count() < 3 {
+        let third = (1..len)
+            .filter(|&index| index != farthest)
+            .max_by(|&a, &b| {
+                let a = distance_to_segment(polygon[0], polygon[farthest], polygon[a]);
+                let b = distance_to_segment(polygon[0], polygon[farthest], polygon[b]);
+                a.total_cmp(&b)
+            });
+        if let Some(third) = third {
+            keep[third] = true;
+        }
+    }
+
+    output.extend(
+        polygon
+            .iter()
+            .zip(keep)
+            .filter_map(|(point, keep)| keep.then_some(*point)),
+    );
+}
+
+/// Check whether any two non-adjacent edges of a polygon touch or intersect
+pub fn is_self_intersecting(polygon: &[Point]) -> bool {
+    rings_intersect(&[polygon])
+}
+
+/// Number of edges up to which all pairs of edges are compared directly
+const BRUTE_FORCE_EDGES: usize = 32;
+
+/// Check whether any two non-adjacent edges of some rings touch or intersect
+///
+/// Larger inputs sort their edges into a grid of cells, so only the edges sharing a cell are compared.
+pub fn rings_intersect(rings: &[&[Point]]) -> bool {
+    // Edges as (ring, index in ring, ring's length, from, to)
+    let edges: Vec<(usize, usize, usize, Point, Point)> = rings
+        .iter()
+        .enumerate()
+        .flat_map(|(ring, points)| {
+            iter_edges(points)
+                .enumerate()
+                .map(move |(index, (a, b))| (ring, index, points.len(), *a, *b))
+        })
+        .collect();
+    let check = |i: usize, j: usize| {
+        let (ring_i, i, len, a, b) = edges[i];
+        let (ring_j, j, _, c, d) = edges[j];
+        let adjacent =
+            ring_i == ring_j && (i.abs_diff(j) <= 1 || (i.min(j) == 0 && i.max(j) == len - 1));
+        !adjacent && segments_intersect(a, b, c, d)
+    };
+
+    if edges.len() <= BRUTE_FORCE_EDGES {
+        return (0..edges.len()).any(|i| (i + 1..edges.len()).any(|j| check(i, j)));
+    }
+
+    let bbox = BBox::from_iter(rings.iter().flat_map(|ring| ring.iter().copied()));
+    let cells = (edges.len() as f64).sqrt().ceil() as usize;
+    let size = (bbox.max - bbox.min) / cells as f64;
+    let cell = |point: Point, axis: usize| {
+        if size[axis] > 0.0 {
+            (((point[axis] - bbox.min[axis]) / size[axis]) as usize).min(cells - 1)
+        } else {
+            0
+        }
+    };
+    let mut grid = vec![Vec::new(); cells * cells];
+    for (index, &(_, _, _, a, b)) in edges.iter().enumerate() {
+        let (min, max) = (a.inf(&b), a.sup(&b));
+        for y in cell(min, 1)..=cell(max, 1) {
+            for x in cell(min, 0)..=cell(max, 0) {
+                grid[y * cells + x].push(index);
+            }
+        }
+    }
+    grid.iter()
+        .any(|cell| (0..cell.len()).any(|i| (i + 1..cell.len()).any(|j| check(cell[i], cell[j]))))
+}
+
+/// Check whether the segments `a`-`b` and `c`-`d` touch or intersect
+pub fn segments_intersect(a: Point, b: Point, c: Point, d: Point) -> bool {
+    fn side(from: Point, to: Point, point: Point) -> f64 {
+        (to - from).perp(&(point - from))
+    }
+    fn on_segment(from: Point, to: Point, point: Point) -> bool {
+        point.x >= from.x.min(to.x)
+            && point.x <= from.x.max(to.x)
+            && point.y >= from.y.min(to.y)
+            && point.y <= from.y.max(to.y)
+    }
+
+    let d1 = side(c, d, a);
+    let d2 = side(c, d, b);
+    let d3 = side(a, b, c);
+    let d4 = side(a, b, d);
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return true;
+    }
+    (d1 == 0.0 && on_segment(c, d, a))
+        || (d2 == 0.0 && on_segment(c, d, b))
+        || (d3 == 0.0 && on_segment(a, b, c))
+        || (d4 == 0.0 && on_segment(a, b, d))
+}
+
+/// Collect the parameters `t` at which the segment `from + t * (to - from)` crosses a polygon's edges
+///
+/// Edges parallel to the segment are ignored.
+pub fn crossings(polygon: &[Point], from: Point, to: Point, output: &mut Vec<f64>) {
+    let direction = to - from;
+    for (a, b) in iter_edges(polygon) {
+        let edge = b - a;
+        let denominator = direction.perp(&edge);
+        if denominator == 0.0 {
+            continue;
+        }
+        let t = (a - from).perp(&edge) / denominator;
+        let u = (a - from).perp(&direction) / denominator;
+        if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+            output.push(t);
+        }
+    }
+}
+
+/// Subtract a polygon from another one, keeping the parts of `subject` outside of `clip`
+///
+/// Both polygons and the result are given as rings using the "even-odd" rule,
+/// e.g. an outer ring followed by its holes. Use [nest] to group the resulting rings.
+///
+/// It implements the [Greiner-Hormann algorithm](https://en.wikipedia.org/wiki/Greiner%E2%80%93Hormann_clipping_algorithm).
+/// Its degenerate cases, i.e. vertices lying on the other polygon's edges,
+/// are avoided by moving `clip`'s vertices by a tiny amount.
+pub fn difference(subject: &[&[Point]], clip: &[&[Point]]) -> Vec<Vec<Point>> {
+    let subject: Vec<Vec<Point>> = subject.iter().filter_map(|ring| dedup(ring)).collect();
+    let mut clip: Vec<Vec<Point>> = clip.iter().filter_map(|ring| dedup(ring)).collect();
+    let bbox = BBox::from_iter(subject.iter().chain(&clip).flatten().copied());
+    let epsilon = (bbox.max - bbox.min).amax() * 1e-7;
+    for (index, point) in clip.iter_mut().flatten().enumerate() {
+        // Points of the R2 sequence spread the offsets evenly
+        let index = index as f64 + 1.0;
+        let offset = Point::new(
+            (index * 0.7548776662466927).fract() * 2.0 - 1.0,
+            (index * 0.5698402909980532).fract() * 2.0 - 1.0,
+        );
+        *point += offset * epsilon;
+    }
+    let inside = |rings: &[Vec<Point>], point: Point| {
+        rings
+            .iter()
+            .filter(|ring| contains_point(ring, point))
+            .count()
+            % 2
+            == 1
+    };
+
+    // Intersections as (subject's edge, its parameter, clip's edge, its parameter, point)
+    let mut intersections = Vec::new();
+    let clip_edges: Vec<(Point, Point)> = clip
+        .iter()
+        .flat_map(|ring| iter_edges(ring).map(|(c, d)| (*c, *d)))
+        .collect();
+    for (s, (a, b)) in subject.iter().flat_map(|ring| iter_edges(ring)).enumerate() {
+        let direction = b - a;
+        for (c, &(from, to)) in clip_edges.iter().enumerate() {
+            let edge = to - from;
+            let denominator = direction.perp(&edge);
+            if denominator == 0.0 {
+                continue;
+            }
+            let t = (from - a).perp(&edge) / denominator;
+            let u = (from - a).perp(&direction) / denominator;
+            if t > 0.0 && t < 1.0 && u > 0.0 && u < 1.0 {
+                intersections.push((s, t, c, u, a + direction * t));
+            }
+        }
+    }
+
+    let mut output = Vec::new();
+    let edges = intersections.iter().map(|&(s, t, _, _, p)| (s, t, p));
+    let mut subject_list = VertexList::new(&subject, edges, intersections.len());
+    let edges = intersections.iter().map(|&(_, _, c, u, p)| (c, u, p));
+    let mut clip_list = VertexList::new(&clip, edges, intersections.len());
+
+    // Rings without intersections are either kept or dropped as a whole
+    for (ring, &(start, end)) in subject.iter().zip(&subject_list.rings) {
+        if subject_list.is_plain(start, end) && !inside(&clip, ring[0]) {
+            output.push(ring.clone());
+        }
+    }
+    for (ring, &(start, end)) in clip.iter().zip(&clip_list.rings) {
+        if clip_list.is_plain(start, end) && inside(&subject, ring[0]) {
+            output.push(ring.clone());
+        }
+    }
+
+    // Leave the subject's intersections towards the outside of clip and the clip's ones towards the inside of subject
+    subject_list.mark_entries(|point| inside(&clip, point));
+    clip_list.mark_entries(|point| !inside(&subject, point));
+    let lists = [&subject_list, &clip_list];
+    let mut visited = vec![false; intersections.len()];
+    for start in 0..intersections.len() {
+        if visited[start] {
+            continue;
+        }
+        let mut ring = Vec::new();
+        let (mut list, mut intersection) = (0, start);
+        while !visited[intersection] {
+            visited[intersection] = true;
+            let vertices = &lists[list].vertices;
+            let mut current = lists[list].positions[intersection];
+            let forward = vertices[current].entry;
+            loop {
+                current = if forward {
+                    vertices[current].next
+                } else {
+                    vertices[current].prev
+                };
+                ring.push(vertices[current].point);
+                if let Some(next) = vertices[current].intersection {
+                    intersection = next;
+                    break;
+                }
+            }
+            list = 1 - list;
+        }
+        output.extend(remove_slivers(ring, epsilon * 4.0));
+    }
+    output
+}
+
+/// Remove the vertices lying on the line through their neighbours
+///
+/// This drops the thin spikes left by [difference] along edges shared by both polygons.
+fn remove_slivers(mut ring: Vec<Point>, tolerance: f64) -> Option<Vec<Point>> {
+    let mut index = 0;
+    let mut unchanged = 0;
+    while ring.len() >= 3 && unchanged < ring.len() {
+        let len = ring.len();
+        let (prev, point, next) = (
+            ring[(index + len - 1) % len],
+            ring[index],
+            ring[(index + 1) % len],
+        );
+        let base = next - prev;
+        let norm = base.norm();
+        if norm == 0.0 || base.perp(&(point - prev)).abs() / norm < tolerance {
+            ring.remove(index);
+            index %= ring.len().max(1);
+            unchanged = 0;
+        } else {
+            index = (index + 1) % len;
+            unchanged += 1;
+        }
+    }
+    (ring.len() >= 3).then_some(ring)
+}
+
+/// Remove consecutive duplicates from a ring, dropping it if less than 3 points remain
+fn dedup(ring: &[Point]) -> Option<Vec<Point>> {
+    let mut points = ring.to_vec();
+    points.dedup();
+    while points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    (points.len() >= 3).then_some(points)
+}
+
+/// A polygon's vertices with the intersections inserted as doubly linked rings
+struct VertexList {
+    vertices: Vec<Vertex>,
+
+    /// Ranges of `vertices` forming the rings
+    rings: Vec<(usize, usize)>,
+
+    /// Index into `vertices` for every intersection
+    positions: Vec<usize>,
+}
+
+struct Vertex {
+    point: Point,
+    next: usize,
+    prev: usize,
+
+    /// Index of the intersection this vertex is
+    intersection: Option<usize>,
+
+    /// Whether the output continues along `next` from this intersection
+    entry: bool,
+}
+
+impl VertexList {
+    /// Insert intersections given as (global edge index, parameter along the edge, point) into the rings
+    fn new(
+        rings: &[Vec<Point>],
+        intersections: impl Iterator<Item = (usize, f64, Point)>,
+        len: usize,
+    ) -> Self {
+        let mut edges = vec![Vec::new(); rings.iter().map(Vec::len).sum()];
+        for (index, (edge, t, point)) in intersections.enumerate() {
+            edges[edge].push((t, index, point));
+        }
+        let mut list = VertexList {
+            vertices: Vec::new(),
+            rings: Vec::new(),
+            positions: vec![0; len],
+        };
+        let mut edges = edges.into_iter();
+        for ring in rings {
+            let start = list.vertices.len();
+            for &point in ring {
+                list.push(point, None);
+                let mut crossings = edges.next().unwrap_or_default();
+                crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+                for (_, index, point) in crossings {
+                    list.positions[index] = list.vertices.len();
+                    list.push(point, Some(index));
+                }
+            }
+            let end = list.vertices.len();
+            for index in start..end {
+                list.vertices[index].next = if index + 1 < end { index + 1 } else { start };
+                list.vertices[index].prev = if index > start { index - 1 } else { end - 1 };
+            }
+            list.rings.push((start, end));
+        }
+        list
+    }
+
+    fn push(&mut self, point: Point, intersection: Option<usize>) {
+        self.vertices.push(Vertex {
+            point,
+            next: 0,
+            prev: 0,
+            intersection,
+            entry: false,
+        });
+    }
+
+    fn is_plain(&self, start: usize, end: usize) -> bool {
+        self.vertices[start..end]
+            .iter()
+            .all(|vertex| vertex.intersection.is_none())
+    }
+
+    /// Alternate the intersections' `entry` flags along each ring, starting with `forward(first point)`
+    fn mark_entries(&mut self, forward: impl Fn(Point) -> bool) {
+        for &(start, end) in &self.rings {
+            let mut forward = forward(self.vertices[start].point);
+            for vertex in &mut self.vertices[start..end] {
+                if vertex.intersection.is_some() {
+                    vertex.entry = forward;
+                    forward = !forward;
+                }
+            }
+        }
+    }
+}
+
+/// Group rings using the "even-odd" rule into outer rings and their holes
+///
+/// A ring is a hole if it lies inside an odd number of other rings.
+pub fn nest(rings: Vec<Vec<Point>>) -> Vec<(Vec<Point>, Vec<Vec<Point>>)> {
+    let parents: Vec<Vec<usize>> = rings
+        .iter()
+        .enumerate()
+        .map(|(index, ring)| {
+            (0..rings.len())
+                .filter(|&other| other != index && contains_point(&rings[other], ring[0]))
+                .collect()
+        })
+        .collect();
+    let mut output: Vec<(Vec<Point>, Vec<Vec<Point>>)> = Vec::new();
+    let mut outer = vec![None; rings.len()];
+    for (index, ring) in rings.iter().enumerate() {
+        if parents[index].len().is_multiple_of(2) {
+            outer[index] = Some(output.len());
+            output.push((ring.clone(), Vec::new()));
+        }
+    }
+    for (index, ring) in rings.into_iter().enumerate() {
+        if !parents[index].len().is_multiple_of(2) {
+            // The innermost ring around a hole is its outer one
+            let parent = parents[index]
+                .iter()
+                .max_by_key(|&&parent| parents[parent].len())
+                .and_then(|&parent| outer[parent]);
+            if let Some(parent) = parent {
+                output[parent].1.push(ring);
+            }
+        }
+    }
+    output
+}
+
 /// Create an iterator over a polygon's edges
 pub fn iter_edges(polygon: &[Point]) -> impl Iterator<Item = (&Point, &Point)> {
     EdgeIterator {
@@ -133,8 +545,11 @@ impl<'a> Iterator for EdgeIterator<'a> {
 
 #[cfg(test)]
 mod test {
-    use crate::geometry::polygon::contains_point;
     use crate::geometry::polygon::iter_edges;
+    use crate::geometry::polygon::{contains_point, crossings, difference, nest};
+    use crate::geometry::polygon::{
+        is_self_intersecting, rings_intersect, simplify, simplify_rings,
+    };
     use crate::geometry::Point;
 
     static SQUARE: [Point; 4] = [
@@ -164,4 +579,165 @@ mod test {
         assert!(contains_point(&SQUARE, Point::new(0.13, 0.37)));
         assert!(!contains_point(&SQUARE, Point::new(-1.13, -1.37)));
     }
+
+    #[test]
+    pub fn test_simplify() {
+        let polygon = [
+            Point::new(1.0, 1.0),
+            Point::new(0.0, 1.05),
+            Point::new(-1.0, 1.0),
+            Point::new(-1.0, -1.0),
+            Point::new(0.0, -0.95),
+            Point::new(1.0, -1.0),
+        ];
+        let mut output = Vec::new();
+        simplify(&polygon, 0.1, &mut output);
+        assert_eq!(output, SQUARE.to_vec());
+
+        // Never collapses below a triangle
+        output.clear();
+        simplify(&polygon, 10.0, &mut output);
+        assert_eq!(output.len(), 3);
+        assert!(!is_self_intersecting(&output));
+    }
+
+    #[test]
+    pub fn test_is_self_intersecting() {
+        assert!(!is_self_intersecting(&SQUARE));
+        let bowtie = [SQUARE[0], SQUARE[2], SQUARE[1], SQUARE[3]];
+        assert!(is_self_intersecting(&bowtie));
+    }
+
+    #[test]
+    pub fn test_rings_intersect() {
+        // A circle with enough vertices to use the grid of cells
+        let circle: Vec<Point> = (0..100)
+            .map(|i| {
+                let angle = i as f64 / 100.0 * std::f64::consts::TAU;
+                Point::new(angle.cos(), angle.sin())
+            })
+            .collect();
+        assert!(!rings_intersect(&[&circle]));
+
+        let mut crossed = circle.clone();
+        crossed.swap(10, 60);
+        assert!(is_self_intersecting(&crossed));
+
+        let inside = [
+            Point::new(0.5, 0.5),
+            Point::new(-0.5, 0.5),
+            Point::new(-0.5, -0.5),
+        ];
+        assert!(!rings_intersect(&[&circle, &inside]));
+        assert!(rings_intersect(&[&circle, &SQUARE]));
+    }
+
+    #[test]
+    pub fn test_simplify_rings() {
+        let outer = [
+            Point::new(0.0, 0.0),
+            Point::new(4.0, 0.0),
+            Point::new(4.0, 2.0),
+            Point::new(4.0, 4.0),
+            Point::new(2.0, 4.5),
+            Point::new(0.0, 4.0),
+        ];
+        // Removing the outer ring's vertex at (2, 4.5) would cut through the hole
+        let hole = [
+            Point::new(1.0, 3.5),
+            Point::new(3.0, 3.5),
+            Point::new(2.0, 4.2),
+        ];
+
+        let mut output = Vec::new();
+        let mut ranges = Vec::new();
+        simplify_rings(&[&outer, &hole], 1.0, |_| false, &mut output, &mut ranges);
+        let rings: Vec<_> = ranges.iter().map(|&(from, to)| &output[from..to]).collect();
+        assert!(!rings_intersect(&rings));
+        assert!(rings[0].contains(&Point::new(2.0, 4.5)));
+        assert!(!rings[0].contains(&Point::new(4.0, 2.0)));
+
+        // Vertices on the pinned edge are kept
+        output.clear();
+        ranges.clear();
+        simplify_rings(
+            &[&outer],
+            1.0,
+            |point| point.x == 4.0,
+            &mut output,
+            &mut ranges,
+        );
+        assert!(output.contains(&Point::new(4.0, 2.0)));
+    }
+
+    #[test]
+    pub fn test_crossings() {
+        let mut output = Vec::new();
+        crossings(
+            &SQUARE,
+            Point::new(-2.0, 0.0),
+            Point::new(2.0, 0.0),
+            &mut output,
+        );
+        output.sort_by(f64::total_cmp);
+        assert_eq!(output, vec![0.25, 0.75]);
+
+        output.clear();
+        crossings(
+            &SQUARE,
+            Point::new(-2.0, 2.0),
+            Point::new(2.0, 2.0),
+            &mut output,
+        );
+        assert!(output.is_empty());
+    }
+
+    fn signed_area(ring: &[Point]) -> f64 {
+        iter_edges(ring).map(|(a, b)| a.perp(b)).sum::<f64>() / 2.0
+    }
+
+    /// Area of rings using the "even-odd" rule, assuming the holes lie directly inside their outer rings
+    fn area(rings: &[Vec<Point>]) -> f64 {
+        nest(rings.to_vec())
+            .iter()
+            .map(|(outer, holes)| {
+                signed_area(outer).abs()
+                    - holes
+                        .iter()
+                        .map(|hole| signed_area(hole).abs())
+                        .sum::<f64>()
+            })
+            .sum()
+    }
+
+    #[test]
+    pub fn test_difference() {
+        let shifted = SQUARE.map(|point| point + Point::new(1.0, 0.0));
+        let small = SQUARE.map(|point| point * 0.5);
+        let far = SQUARE.map(|point| point + Point::new(5.0, 0.0));
+
+        // Half of the square is left
+        let output = difference(&[&SQUARE], &[&shifted]);
+        assert_eq!(output.len(), 1);
+        assert!((area(&output) - 2.0).abs() < 1e-6);
+        assert!(output[0].iter().all(|point| point.x < 1e-6));
+
+        // A clip inside the subject becomes a hole
+        let output = difference(&[&SQUARE], &[&small]);
+        let nested = nest(output.clone());
+        assert_eq!(nested.len(), 1);
+        assert_eq!(nested[0].0, SQUARE.to_vec());
+        assert_eq!(nested[0].1.len(), 1);
+        assert!((area(&output) - 3.0).abs() < 1e-6);
+
+        // The subject's hole stays, the clip is cut out around it
+        let output = difference(&[&SQUARE, &small], &[&shifted]);
+        assert!((area(&output) - 1.5).abs() < 1e-6);
+
+        assert_eq!(difference(&[&SQUARE], &[&far]), vec![SQUARE.to_vec()]);
+        assert!(difference(&[&small], &[&SQUARE]).is_empty());
+        assert!(difference(&[&SQUARE], &[&SQUARE])
+            .iter()
+            .all(|ring| signed_area(ring).abs() < 1e-6));
+    }
 }
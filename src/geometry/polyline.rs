@@ -11,32 +11,78 @@ pub fn iter_segments(polyline: &[Point]) -> impl Iterator<Item = (&Point, &Point
 /// Compute a point distance to a polyline
 pub fn distance_to(polyline: &[Point], point: Point) -> f64 {
     iter_segments(polyline)
-        .map(|(from, to)| {
-            let delta = to - from;
-
-            // Calculate projection onto the line
-            let lambda = (point - from).tr_mul(&delta).x / delta.norm();
-
-            if 0.0 <= lambda && lambda <= 1.0 {
-                (from + lambda * delta).metric_distance(&point)
-            } else {
-                let from_dist = point.metric_distance(from);
-                let to_dist = point.metric_distance(to);
-
-                if from_dist < to_dist {
-                    from_dist
-                } else {
-                    to_dist
-                }
-            }
-        })
+        .map(|(from, to)| distance_to_segment(*from, *to, point))
         .min_by(|a, b| a.partial_cmp(b).expect("Distance shouldn't be NaN"))
         .expect("Polyline should contain at least 2 points to form at least one segment")
 }
 
+/// Compute a point's distance to a line segment
+pub fn distance_to_segment(from: Point, to: Point, point: Point) -> f64 {
+    let delta = to - from;
+    let length = delta.norm_squared();
+    if length == 0.0 {
+        return point.metric_distance(&from);
+    }
+
+    // Calculate projection onto the line clamped to the segment
+    let lambda = ((point - from).dot(&delta) / length).clamp(0.0, 1.0);
+    (from + lambda * delta).metric_distance(&point)
+}
+
+/// Simplify a polyline using the [Douglas-Peucker algorithm](https://en.wikipedia.org/wiki/Ramer%E2%80%93Douglas%E2%80%93Peucker_algorithm)
+///
+/// The endpoints are always kept and every removed point lies within `tolerance` of the result.
+/// The simplified polyline is appended to `output`.
+pub fn simplify(polyline: &[Point], tolerance: f64, output: &mut Vec<Point>) {
+    let len = polyline.len();
+    if len < 3 {
+        output.extend_from_slice(polyline);
+        return;
+    }
+
+    let mut keep = vec![false; len];
+    keep[0] = true;
+    keep[len - 1] = true;
+    mark_kept(polyline, 0, len - 1, tolerance, &mut keep);
+
+    output.extend(
+        polyline
+            .iter()
+            .zip(keep)
+            .filter_map(|(point, keep)| keep.then_some(*point)),
+    );
+}
+
+/// Mark the points between `first` and `last` which the Douglas-Peucker algorithm keeps
+pub(crate) fn mark_kept(
+    points: &[Point],
+    first: usize,
+    last: usize,
+    tolerance: f64,
+    keep: &mut [bool],
+) {
+    let mut stack = vec![(first, last)];
+    while let Some((first, last)) = stack.pop() {
+        let mut farthest = (0.0, first);
+        for index in first + 1..last {
+            let distance = distance_to_segment(points[first], points[last], points[index]);
+            if distance > farthest.0 {
+                farthest = (distance, index);
+            }
+        }
+
+        let (distance, index) = farthest;
+        if distance > tolerance {
+            keep[index] = true;
+            stack.push((first, index));
+            stack.push((index, last));
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::geometry::polyline::distance_to;
+    use crate::geometry::polyline::{distance_to, simplify};
     use crate::geometry::Point;
 
     #[test]
@@ -58,4 +104,37 @@ mod test {
             "A point diagonal to the line"
         );
     }
+
+    #[test]
+    fn test_simplify() {
+        let polyline = [
+            Point::new(0.0, 0.0),
+            Point::new(1.0, 0.1),
+            Point::new(2.0, -0.1),
+            Point::new(3.0, 5.0),
+            Point::new(4.0, 6.0),
+            Point::new(5.0, 7.0),
+        ];
+
+        let mut output = Vec::new();
+        simplify(&polyline, 0.5, &mut output);
+        assert_eq!(
+            output,
+            vec![
+                Point::new(0.0, 0.0),
+                Point::new(2.0, -0.1),
+                Point::new(3.0, 5.0),
+                Point::new(5.0, 7.0),
+            ]
+        );
+
+        // Only collinear points are removed without a tolerance
+        output.clear();
+        simplify(&polyline, 0.0, &mut output);
+        assert_eq!(output, [&polyline[..4], &polyline[5..]].concat());
+
+        output.clear();
+        simplify(&polyline[..2], 10.0, &mut output);
+        assert_eq!(output, polyline[..2].to_vec());
+    }
 }
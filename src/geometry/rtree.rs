@@ -0,0 +1,243 @@
+//! A static R-tree packed once after all of its items are known
+//!
+//! The items' bounding boxes are sorted using the Sort-Tile-Recursive algorithm
+//! and then grouped into nodes of [NODE_SIZE] children level by level up to a single root.
+//! All nodes are stored in flat arrays, so the tree is cheap to serialize.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::{BBox, Point};
+
+/// Maximum number of children per node
+pub const NODE_SIZE: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PackedRTree {
+    /// Bounding boxes of all nodes, the leaves first followed by each level up to the root
+    boxes: Vec<BBox>,
+
+    /// The item's index for leaves and the position of the first child in `boxes` for other nodes
+    indices: Vec<usize>,
+
+    /// End of each level in `boxes` starting with the leaves
+    levels: Vec<usize>,
+}
+
+impl PackedRTree {
+    /// Pack the items' bounding boxes
+    ///
+    /// The items are identified by their position in the iterator.
+    pub fn new(items: impl IntoIterator<Item = BBox>) -> Self {
+        let mut leaves: Vec<(BBox, usize)> = items
+            .into_iter()
+            .enumerate()
+            .map(|(index, bbox)| (bbox, index))
+            .collect();
+
+        // Sort into vertical slices which are each sorted from bottom to top
+        let center = |bbox: &BBox| (bbox.min + bbox.max) / 2.0;
+        let nodes = leaves.len().div_ceil(NODE_SIZE);
+        let slices = (nodes as f64).sqrt().ceil() as usize;
+        leaves.sort_by(|(a, _), (b, _)| center(a).x.total_cmp(&center(b).x));
+        for slice in leaves.chunks_mut((slices * NODE_SIZE).max(1)) {
+            slice.sort_by(|(a, _), (b, _)| center(a).y.total_cmp(&center(b).y));
+        }
+
+        let (mut boxes, mut indices): (Vec<_>, Vec<_>) = leaves.into_iter().unzip();
+        let mut levels = vec![boxes.len()];
+        let mut start = 0;
+        while boxes.len() - start > 1 {
+            let end = boxes.len();
+            for first in (start..end).step_by(NODE_SIZE) {
+                let mut bbox = BBox::new();
+                for child in &boxes[first..(first + NODE_SIZE).min(end)] {
+                    bbox.fit(child.min);
+                    bbox.fit(child.max);
+                }
+                boxes.push(bbox);
+                indices.push(first);
+            }
+            start = end;
+            levels.push(boxes.len());
+        }
+
+        PackedRTree {
+            boxes,
+            indices,
+            levels,
+        }
+    }
+
+    /// Get the number of items
+    pub fn len(&self) -> usize {
+        self.levels.first().copied().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Call `visit` with every item whose bounding box overlaps `bbox`
+    pub fn search(&self, bbox: BBox, mut visit: impl FnMut(usize)) {
+        let Some(root) = self.root() else {
+            return;
+        };
+        let mut stack = vec![root];
+        while let Some((node, level)) = stack.pop() {
+            if !self.boxes[node].overlaps(&bbox) {
+                continue;
+            }
+            if level == 0 {
+                visit(self.indices[node]);
+            } else {
+                stack.extend(self.children(node, level).map(|child| (child, level - 1)));
+            }
+        }
+    }
+
+    /// Find the item closest to a point
+    ///
+    /// `distance` computes an item's exact distance which mustn't be smaller than the distance to its bounding box.
+    pub fn nearest(
+        &self,
+        point: Point,
+        mut distance: impl FnMut(usize) -> f64,
+    ) -> Option<(usize, f64)> {
+        let root = self.root()?;
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: box_distance(&self.boxes[root.0], point),
+            kind: Kind::Node(root.0, root.1),
+        });
+
+        // Box distances are lower bounds, so the first item taken from the queue is the closest one
+        while let Some(Candidate { distance: _, kind }) = queue.pop() {
+            match kind {
+                Kind::Item(index, distance) => return Some((index, distance)),
+                Kind::Node(node, 0) => {
+                    let index = self.indices[node];
+                    let distance = distance(index);
+                    queue.push(Candidate {
+                        distance,
+                        kind: Kind::Item(index, distance),
+                    });
+                }
+                Kind::Node(node, level) => {
+                    for child in self.children(node, level) {
+                        queue.push(Candidate {
+                            distance: box_distance(&self.boxes[child], point),
+                            kind: Kind::Node(child, level - 1),
+                        });
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Get the root's position and level
+    fn root(&self) -> Option<(usize, usize)> {
+        if self.boxes.is_empty() {
+            None
+        } else {
+            Some((self.boxes.len() - 1, self.levels.len() - 1))
+        }
+    }
+
+    /// Iterate over the positions of an inner node's children
+    fn children(&self, node: usize, level: usize) -> std::ops::Range<usize> {
+        let first = self.indices[node];
+        first..(first + NODE_SIZE).min(self.levels[level - 1])
+    }
+}
+
+fn box_distance(bbox: &BBox, point: Point) -> f64 {
+    let dx = (bbox.min.x - point.x).max(point.x - bbox.max.x).max(0.0);
+    let dy = (bbox.min.y - point.y).max(point.y - bbox.max.y).max(0.0);
+    dx.hypot(dy)
+}
+
+/// An entry in [PackedRTree::nearest]'s queue
+struct Candidate {
+    distance: f64,
+    kind: Kind,
+}
+enum Kind {
+    /// A node's position and level
+    Node(usize, usize),
+
+    /// An item's index and exact distance
+    Item(usize, f64),
+}
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    /// Reversed to turn [BinaryHeap] into a min-heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::geometry::rtree::PackedRTree;
+    use crate::geometry::{BBox, Point};
+
+    fn grid(size: usize) -> Vec<BBox> {
+        (0..size * size)
+            .map(|index| {
+                let min = Point::new((index % size) as f64, (index / size) as f64);
+                BBox {
+                    min,
+                    max: min + Point::new(0.5, 0.5),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn search() {
+        let boxes = grid(20);
+        let tree = PackedRTree::new(boxes.iter().copied());
+        assert_eq!(tree.len(), 400);
+
+        let query = BBox {
+            min: Point::new(2.25, 3.25),
+            max: Point::new(4.0, 4.0),
+        };
+        let mut found = Vec::new();
+        tree.search(query, |index| found.push(index));
+        found.sort();
+        assert_eq!(found, vec![62, 63, 64, 82, 83, 84]);
+
+        let empty = PackedRTree::new([]);
+        empty.search(query, |_| panic!("The tree is empty"));
+        assert_eq!(empty.nearest(Point::new(0.0, 0.0), |_| 0.0), None);
+    }
+
+    #[test]
+    fn nearest() {
+        let boxes = grid(20);
+        let tree = PackedRTree::new(boxes.iter().copied());
+        let point = Point::new(7.9, 12.1);
+        let distance = |index: usize| boxes[index].min.metric_distance(&point);
+
+        let expected = (0..boxes.len())
+            .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+            .unwrap();
+        let (index, _) = tree.nearest(point, distance).unwrap();
+        assert_eq!(index, expected);
+    }
+}
@@ -7,17 +7,33 @@ use nalgebra::Vector2;
 use serde::ser::SerializeSeq;
 use serde::{Deserialize, Serialize, Serializer};
 
-use crate::buffered::MultithreadedGenerator;
+use crate::buffered::{MultithreadedGenerator, CAPACITY, DEPTH};
+pub use crate::error::Error;
+use crate::exclusion::Exclusions;
+use crate::features::area::AreaRule;
 use crate::features::FeatureParser;
+use crate::formats::mvt;
+use crate::generator::Simplification;
+use crate::metadata::MetadataFilter;
+use crate::progress::Monitor;
 use crate::projection::Projection;
 
 pub mod buffered;
+pub mod diff;
+pub mod error;
+pub mod exclusion;
 pub mod features;
 pub mod formats;
 pub mod generator;
 pub mod geometry;
 pub mod measurements;
+pub mod memory;
+pub mod metadata;
+pub mod progress;
 pub mod projection;
+pub mod source;
+pub mod update;
+pub mod world;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config<Visual: FeatureParser, Prjctn: Projection> {
@@ -29,14 +45,194 @@ pub struct Config<Visual: FeatureParser, Prjctn: Projection> {
     pub zoom: u8,
     pub visual: Visual,
     pub projection: Prjctn,
+
+    /// Number of worker threads, `None` to use one per available core
+    #[serde(default)]
+    pub workers: Option<usize>,
+
+    /// Bytes size of the buffers sent to the workers
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+
+    /// Number of buffers which can be queued for a single worker before the reader blocks
+    #[serde(default = "default_channel_depth")]
+    pub channel_depth: usize,
+
+    /// Decides whether closed ways are areas or rings
+    #[serde(default)]
+    pub area_rule: AreaRule,
+
+    /// Tolerances for simplifying areas and ways per zoom and feature
+    #[serde(
+        default,
+        bound(
+            serialize = "Visual::Feature: Serialize",
+            deserialize = "Visual::Feature: Deserialize<'de>"
+        )
+    )]
+    pub simplification: Simplification<Visual::Feature>,
+
+    /// Which items the exclusion zones suppress, see [exclusion]
+    ///
+    /// The zones are recorded in the tiles' masks even without any rules.
+    #[serde(
+        default,
+        bound(
+            serialize = "Visual::Feature: Serialize",
+            deserialize = "Visual::Feature: Deserialize<'de>"
+        )
+    )]
+    pub exclusions: Exclusions<Visual::Feature>,
+
+    /// Options for assembling areas from closed ways and multipolygon relations
+    ///
+    /// `None` skips the area assembly, which is faster for runs only interested in nodes and ways.
+    /// Closed ways which would be areas are dropped in that case.
+    ///
+    /// The node locations are always stored in memory,
+    /// because libosmium's bindings don't expose a choice of location index.
+    #[serde(default = "default_areas")]
+    pub areas: Option<AreaOptions>,
+
+    /// Indexes of the only tiles to generate, all others stay empty
+    ///
+    /// Used to regenerate the tiles affected by an [update](update::update).
+    #[serde(default)]
+    pub only_tiles: Option<Vec<usize>>,
+
+    /// Sort each tile's items by their oid, see [formats::Tile::canonicalize]
+    ///
+    /// Use this to get byte-identical output for the same input.
+    #[serde(default)]
+    pub canonical_order: bool,
+
+    /// Build each tile's spatial index for point queries, see [formats::Tile::build_index]
+    #[serde(default)]
+    pub spatial_index: bool,
+
+    /// Exclude objects by the metadata of their latest edit before parsing their features
+    ///
+    /// Excluded objects are reported to the [monitor](Self::monitor)'s observer.
+    #[serde(default)]
+    pub metadata_filter: Option<MetadataFilter>,
+
+    /// Progress reporting and cancellation
+    #[serde(skip)]
+    pub monitor: Monitor,
+}
+impl<Visual: FeatureParser, Prjctn: Projection> Config<Visual, Prjctn> {
+    /// Get the parameters to record in an [Envelope](formats::envelope::Envelope)
+    pub fn parameters(&self) -> formats::envelope::Parameters {
+        formats::envelope::Parameters {
+            file: self.file.clone(),
+            cols: self.cols,
+            rows: self.rows,
+            center_x: self.center_x,
+            center_y: self.center_y,
+            zoom: self.zoom,
+            projection: self.projection.name().to_string(),
+            config_hash: None,
+        }
+    }
+
+    /// Hash everything influencing the tiles' content, see [formats::archive::config_hash]
+    ///
+    /// The input file, the tiles to generate and the settings only affecting the speed are left out,
+    /// so generations of different files using the same settings get the same hash.
+    /// Requires the `serde_json` feature.
+    #[cfg(feature = "serde_json")]
+    pub fn config_hash(&self) -> Result<u64, Error>
+    where
+        Self: Serialize,
+    {
+        let mut settings = serde_json::to_value(self).map_err(Error::serialization)?;
+        if let Some(settings) = settings.as_object_mut() {
+            for key in [
+                "file",
+                "workers",
+                "buffer_size",
+                "channel_depth",
+                "only_tiles",
+            ] {
+                settings.remove(key);
+            }
+        }
+        Ok(formats::archive::config_hash(
+            settings.to_string().as_bytes(),
+        ))
+    }
+}
+
+fn default_buffer_size() -> usize {
+    CAPACITY
+}
+fn default_channel_depth() -> usize {
+    DEPTH
+}
+fn default_areas() -> Option<AreaOptions> {
+    Some(AreaOptions::default())
+}
+
+/// Serializable version of libosmium's [AreaAssemblerConfig]
+///
+/// Missing fields use libosmium's defaults except for `create_empty_areas`, which is disabled.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct AreaOptions {
+    pub debug_level: i32,
+    pub check_roles: bool,
+    pub create_empty_areas: bool,
+    pub create_new_style_polygons: bool,
+    pub create_old_style_polygons: bool,
+    pub create_way_polygons: bool,
+    pub keep_type_tag: bool,
+    pub ignore_invalid_locations: bool,
+}
+impl Default for AreaOptions {
+    fn default() -> Self {
+        AreaAssemblerConfig {
+            create_empty_areas: false,
+            ..Default::default()
+        }
+        .into()
+    }
+}
+impl From<AreaAssemblerConfig> for AreaOptions {
+    fn from(config: AreaAssemblerConfig) -> Self {
+        AreaOptions {
+            debug_level: config.debug_level,
+            check_roles: config.check_roles,
+            create_empty_areas: config.create_empty_areas,
+            create_new_style_polygons: config.create_new_style_polygons,
+            create_old_style_polygons: config.create_old_style_polygons,
+            create_way_polygons: config.create_way_polygons,
+            keep_type_tag: config.keep_type_tag,
+            ignore_invalid_locations: config.ignore_invalid_locations,
+        }
+    }
+}
+impl From<AreaOptions> for AreaAssemblerConfig {
+    fn from(options: AreaOptions) -> Self {
+        AreaAssemblerConfig {
+            debug_level: options.debug_level,
+            check_roles: options.check_roles,
+            create_empty_areas: options.create_empty_areas,
+            create_new_style_polygons: options.create_new_style_polygons,
+            create_old_style_polygons: options.create_old_style_polygons,
+            create_way_polygons: options.create_way_polygons,
+            keep_type_tag: options.keep_type_tag,
+            ignore_invalid_locations: options.ignore_invalid_locations,
+            ..Default::default()
+        }
+    }
 }
 
 pub fn parse<Visual: FeatureParser, Prjctn: Projection>(
     config: Config<Visual, Prjctn>,
-) -> Result<Vec<formats::Tile<Visual::Feature>>, String>
+) -> Result<world::World<Visual::Feature, Prjctn>, Error>
 where
     Visual: Send + Sync + 'static,
-    Visual::Feature: Default + Clone + Send + 'static,
+    Visual::Feature: Default + Clone + PartialEq + Send + 'static,
 {
     let Config {
         file,
@@ -47,30 +243,100 @@ where
         center_y,
         visual,
         projection,
+        workers,
+        buffer_size,
+        channel_depth,
+        area_rule,
+        simplification,
+        exclusions,
+        areas,
+        only_tiles,
+        canonical_order,
+        spatial_index,
+        metadata_filter,
+        monitor,
     } = config;
+    if cols == 0 || rows == 0 {
+        return Err(Error::InvalidConfig(format!(
+            "The grid has to contain at least one tile, got {cols}x{rows}"
+        )));
+    }
+    if zoom > 30 {
+        return Err(Error::InvalidConfig(format!(
+            "The zoom has to be at most 30, got {zoom}"
+        )));
+    }
+    // Report a missing or unreadable file with its actual cause
+    std::fs::File::open(&file)?;
+
     let step_num = (cols, rows);
     let center = Vector2::new(center_x, center_y);
 
     let visual = Arc::new(visual);
-    let handler = generator::WorldGenerator::new(center, step_num, zoom, visual, projection);
-    let mut handler = MultithreadedGenerator::new(handler);
-    handler.spawn_workers(4);
+    let mut handler = generator::WorldGenerator::new(center, step_num, zoom, visual, projection);
+    handler.area_rule = area_rule;
+    handler.monitor = monitor.clone();
+    handler.simplification = simplification.for_zoom(zoom);
+    handler.tile_mask = only_tiles.map(|indexes| {
+        let mut mask = vec![false; cols * rows];
+        for index in indexes {
+            if let Some(included) = mask.get_mut(index) {
+                *included = true;
+            }
+        }
+        mask
+    });
+    handler.metadata_filter = metadata_filter.map(MetadataFilter::with_now);
+    let origin = handler.grid.min();
+    let step_size = handler.grid.step_size();
+    // Tiles beyond the map's edge would share their `x`/`y` keys with others
+    let last =
+        origin + Vector2::new((cols - 1) as f64, (rows - 1) as f64).component_mul(&step_size);
+    if mvt::position(origin, zoom).is_none() || mvt::position(last, zoom).is_none() {
+        return Err(Error::InvalidConfig(format!(
+            "The {cols}x{rows} grid around ({center_x}, {center_y}) extends beyond the map at zoom {zoom}"
+        )));
+    }
+    let mut handler = MultithreadedGenerator::new(handler, buffer_size, channel_depth);
+    let workers = workers.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(1)
+    });
+    handler.spawn_workers(workers.max(1));
 
     //let mut timed_handler = measurements::TimedHandler::new(handler);
     //timed_handler
-    handler
-        .apply_with_areas(
-            &file,
-            AreaAssemblerConfig {
-                create_empty_areas: false,
-                ..Default::default()
-            },
-        )
-        .map_err(|error| error.into_string().unwrap())?;
+    match areas {
+        Some(options) => handler.apply_with_areas(&file, options.into()),
+        None => handler.apply_with_ways(&file),
+    }
+    .map_err(|error| Error::Pbf(error.to_string_lossy().into_owned()))?;
     //timed_handler.print();
     //let handler = timed_handler.into_handler();
 
-    Ok(handler.into_tiles())
+    let mut tiles = handler.into_tiles()?;
+    for tile in tiles.iter_mut() {
+        exclusion::suppress(tile, &exclusions);
+    }
+    if canonical_order {
+        tiles.iter_mut().for_each(formats::Tile::canonicalize);
+    }
+    if spatial_index {
+        tiles.iter_mut().for_each(formats::Tile::build_index);
+    }
+    if monitor.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+    monitor.finish(&tiles);
+    Ok(world::World {
+        origin,
+        step_size,
+        cols,
+        rows,
+        projection,
+        tiles,
+    })
 }
 
 pub fn convert_format<T, F>(tiles: Vec<formats::Tile<usize>>, convert: F) -> impl Serialize
@@ -118,3 +384,62 @@ where
         seq.end()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::buffered::{CAPACITY, DEPTH};
+    use crate::features::config::ConfigParser;
+    use crate::projection::Simple;
+    use crate::{parse, Config};
+
+    #[test]
+    fn ways_without_areas() {
+        let osm = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6">
+  <node id="1" version="1" lat="10" lon="10"/>
+  <node id="2" version="1" lat="20" lon="20"/>
+  <way id="10" version="1">
+    <nd ref="1"/><nd ref="2"/>
+    <tag k="highway" v="path"/>
+  </way>
+</osm>"#;
+        let path =
+            std::env::temp_dir().join(format!("rustymon_no_areas_{}.osm", std::process::id()));
+        std::fs::write(&path, osm).unwrap();
+
+        // A single tile from 0 to 0.5 radians
+        let config = Config {
+            file: path.to_str().unwrap().to_string(),
+            cols: 1,
+            rows: 1,
+            center_x: 15.0,
+            center_y: 15.0,
+            zoom: 1,
+            visual: ConfigParser::borrowing()
+                .parse_file("[Ways]\n3: \"highway\" exists")
+                .unwrap(),
+            projection: Simple,
+            workers: Some(1),
+            buffer_size: CAPACITY,
+            channel_depth: DEPTH,
+            area_rule: Default::default(),
+            simplification: Default::default(),
+            exclusions: Default::default(),
+            areas: None,
+            only_tiles: None,
+            canonical_order: false,
+            spatial_index: false,
+            metadata_filter: None,
+            monitor: Default::default(),
+        };
+        let world = parse(config);
+        std::fs::remove_file(&path).unwrap();
+        let world = world.unwrap();
+
+        // The way's nodes got their locations
+        let ways = &world.tiles[0].ways;
+        assert_eq!(ways.len(), 1);
+        assert_eq!(ways[0].oid, 10);
+        assert_eq!(ways[0].feature, 3);
+    }
+}
@@ -0,0 +1,291 @@
+//! Osm objects kept in memory as input for a [WorldGenerator] without a PBF file
+//!
+//! This allows other tools to feed in geometry from their own sources
+//! and tests to check the clipping and feature assignment precisely.
+
+use crate::features::FeatureParser;
+use crate::generator::WorldGenerator;
+use crate::geometry::Point;
+use crate::projection::Projection;
+
+/// Owned tags of an object
+pub type OwnedTags = Vec<(String, String)>;
+
+/// Outer rings paired with their inner rings
+pub type Polygons = Vec<(Vec<Point>, Vec<Vec<Point>>)>;
+
+/// Builder collecting nodes, ways and multipolygons
+///
+/// All locations are given in degrees as `(lon, lat)`.
+#[derive(Clone, Debug, Default)]
+pub struct MemorySource {
+    nodes: Vec<(i64, OwnedTags, Point)>,
+    ways: Vec<(i64, OwnedTags, Vec<Point>)>,
+    multipolygons: Vec<(i64, OwnedTags, Polygons)>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node
+    pub fn node(&mut self, id: i64, tags: &[(&str, &str)], location: Point) -> &mut Self {
+        self.nodes.push((id, owned(tags), location));
+        self
+    }
+
+    /// Add a way
+    ///
+    /// A way is closed if its first and last location are equal.
+    /// Like libosmium, closed ways are also added as areas when applied.
+    pub fn way(&mut self, id: i64, tags: &[(&str, &str)], locations: &[Point]) -> &mut Self {
+        self.ways.push((id, owned(tags), locations.to_vec()));
+        self
+    }
+
+    /// Add a multipolygon relation consisting of outer rings paired with their inner rings
+    ///
+    /// The rings are expected to be closed already.
+    pub fn multipolygon(&mut self, id: i64, tags: &[(&str, &str)], rings: Polygons) -> &mut Self {
+        self.multipolygons.push((id, owned(tags), rings));
+        self
+    }
+
+    /// Feed all objects into a generator
+    ///
+    /// The nodes are applied first, followed by the ways and then the areas.
+    pub fn apply<P, V>(&self, generator: &mut WorldGenerator<P, V>)
+    where
+        P: Projection,
+        V: FeatureParser,
+        V::Feature: Clone + PartialEq,
+    {
+        for (id, tags, location) in self.nodes.iter() {
+            generator.add_node(*id, borrowed(tags), *location);
+        }
+        for (id, tags, locations) in self.ways.iter() {
+            generator.add_way(*id, borrowed(tags), locations);
+        }
+        for (id, tags, locations) in self.ways.iter() {
+            if locations.len() > 3 && locations.first() == locations.last() {
+                let rings = [(locations.clone(), Vec::new())];
+                generator.add_area(2 * id, borrowed(tags), &rings);
+            }
+        }
+        for (id, tags, rings) in self.multipolygons.iter() {
+            generator.add_area(2 * id + 1, borrowed(tags), rings);
+        }
+    }
+}
+
+fn owned(tags: &[(&str, &str)]) -> OwnedTags {
+    tags.iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn borrowed(tags: &OwnedTags) -> impl Iterator<Item = (&str, &str)> + Clone {
+    tags.iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::Vector2;
+
+    use crate::exclusion::{suppress, ExclusionRule, Exclusions};
+    use crate::features::config::ConfigParser;
+    use crate::formats::{Kind, Tile};
+    use crate::generator::{Simplification, SimplificationRule, WorldGenerator};
+    use crate::geometry::Point;
+    use crate::memory::MemorySource;
+    use crate::projection::Simple;
+
+    const CONFIG: &str = r#"
+        [Areas]
+        1: "leisure" is "park"
+        [Nodes]
+        2: "amenity" exists
+        [Ways]
+        3: "highway" exists
+        [Exclusions]
+        5: "amenity" is "school"
+    "#;
+
+    /// Generate a 2x1 grid at zoom 0 using the simple projection
+    ///
+    /// The simple projection maps degrees to radians, so the tiles are `1` radian wide.
+    fn generate(source: &MemorySource) -> Vec<Tile<usize>> {
+        let ast = ConfigParser::borrowing().parse_file(CONFIG).unwrap();
+        let center = Vector2::new(0.5f64.to_degrees(), 0.5f64.to_degrees());
+        let mut generator = WorldGenerator::new(center, (2, 1), 0, ast, Simple);
+        source.apply(&mut generator);
+        generator.into_tiles()
+    }
+
+    /// A point in radians given in degrees
+    fn degrees(x: f64, y: f64) -> Point {
+        Point::new(x.to_degrees(), y.to_degrees())
+    }
+
+    /// A closed [square](crate::geometry::square) given in radians
+    fn square(min: f64, max: f64) -> Vec<Point> {
+        let square = crate::geometry::square(min, max);
+        square
+            .iter()
+            .chain(&square[..1])
+            .map(|point| degrees(point.x, point.y))
+            .collect()
+    }
+
+    #[test]
+    fn nodes() {
+        let mut source = MemorySource::new();
+        source
+            .node(1, &[("amenity", "bench")], degrees(-0.5, 0.5))
+            .node(2, &[("amenity", "bench")], degrees(0.5, 0.5))
+            .node(3, &[("shop", "bakery")], degrees(0.5, 0.5))
+            .node(4, &[("amenity", "bench")], degrees(5.0, 0.5));
+        let tiles = generate(&source);
+
+        assert_eq!(tiles.len(), 2);
+        assert_eq!(tiles[0].min, Point::new(-1.0, 0.0));
+        let oids = |tile: &Tile<usize>| tile.nodes.iter().map(|n| n.oid).collect::<Vec<_>>();
+        assert_eq!(oids(&tiles[0]), vec![1]);
+        assert_eq!(oids(&tiles[1]), vec![2]);
+        assert_eq!(tiles[1].nodes[0].feature, 2);
+    }
+
+    #[test]
+    fn negative_ids() {
+        let mut source = MemorySource::new();
+        source
+            .node(-1, &[("amenity", "bench")], degrees(0.5, 0.5))
+            .way(
+                -2,
+                &[("highway", "path")],
+                &[degrees(-0.5, 0.5), degrees(0.5, 0.5)],
+            )
+            .multipolygon(
+                -3,
+                &[("leisure", "park")],
+                vec![(square(0.25, 0.75), Vec::new())],
+            );
+        let tiles = generate(&source);
+        assert!(tiles
+            .iter()
+            .all(|tile| tile.areas.is_empty() && tile.nodes.is_empty() && tile.ways.is_empty()));
+    }
+
+    #[test]
+    fn ways_and_areas() {
+        let mut source = MemorySource::new();
+        source
+            .way(
+                10,
+                &[("highway", "path")],
+                &[degrees(-0.5, 0.5), degrees(0.5, 0.5)],
+            )
+            .way(11, &[("leisure", "park")], &square(0.25, 0.75))
+            .way(12, &[("highway", "service")], &square(-0.75, -0.25))
+            .multipolygon(
+                20,
+                &[("leisure", "park")],
+                vec![(square(-0.9, 0.9), vec![square(-0.1, 0.1)])],
+            );
+        let tiles = generate(&source);
+
+        // The path is cut at the tiles' border
+        for tile in tiles.iter() {
+            let way = tile.iter_ways().next().unwrap();
+            assert_eq!((way.oid, *way.feature), (10, 3));
+            assert_eq!(way.points.len(), 2);
+        }
+
+        // The closed way is an area, the multipolygon covers both tiles
+        let areas = |tile: &Tile<usize>| tile.areas.iter().map(|a| a.oid).collect::<Vec<_>>();
+        assert_eq!(areas(&tiles[0]), vec![41]);
+        assert_eq!(areas(&tiles[1]), vec![22, 41]);
+        assert_eq!(
+            tiles[1].iter_areas().nth(1).unwrap().points.inner().count(),
+            1
+        );
+
+        // The ring outside the tiles is skipped
+        assert!(tiles.iter().all(|tile| tile.ways.len() == 1));
+    }
+
+    #[test]
+    fn simplified_areas() {
+        let mut source = MemorySource::new();
+        source.multipolygon(
+            20,
+            &[("leisure", "park")],
+            vec![(
+                vec![
+                    degrees(-0.5, 0.2),
+                    degrees(0.5, 0.2),
+                    degrees(0.5, 0.8),
+                    degrees(-0.5, 0.8),
+                    degrees(-0.5, 0.2),
+                ],
+                Vec::new(),
+            )],
+        );
+        let ast = ConfigParser::borrowing().parse_file(CONFIG).unwrap();
+        let center = Vector2::new(0.5f64.to_degrees(), 0.5f64.to_degrees());
+        let mut generator = WorldGenerator::new(center, (2, 1), 0, ast, Simple);
+        generator.simplification = Simplification {
+            rules: vec![SimplificationRule {
+                zoom: None,
+                feature: None,
+                tolerance: 10.0,
+            }],
+        };
+        source.apply(&mut generator);
+        let tiles = generator.into_tiles();
+
+        // The pieces keep their vertices on the tiles' shared edge
+        for tile in tiles.iter() {
+            let area = tile.iter_areas().next().unwrap();
+            let outer = area.points.outer();
+            assert_eq!(outer.len(), 3);
+            let on_edge = outer.iter().filter(|point| point.x.abs() < 1e-9);
+            assert_eq!(on_edge.count(), 2);
+        }
+    }
+
+    #[test]
+    fn exclusion_zones() {
+        let mut source = MemorySource::new();
+        source
+            .node(1, &[("amenity", "bench")], degrees(0.25, 0.25))
+            .node(2, &[("amenity", "bench")], degrees(0.75, 0.75))
+            .multipolygon(
+                30,
+                &[("amenity", "school")],
+                vec![(square(-0.5, 0.5), Vec::new())],
+            );
+        let mut tiles = generate(&source);
+
+        // The zone is clipped into both tiles' masks but isn't an area
+        for tile in tiles.iter() {
+            assert!(tile.areas.is_empty());
+            let zone = tile.exclusions.zone(0);
+            assert_eq!((zone.oid, *zone.feature), (61, 5));
+        }
+
+        let exclusions = Exclusions {
+            rules: vec![ExclusionRule {
+                zone: Some(5),
+                kind: Some(Kind::Node),
+                feature: None,
+                overlapping: false,
+            }],
+        };
+        suppress(&mut tiles[1], &exclusions);
+        let nodes: Vec<_> = tiles[1].iter_nodes().map(|node| node.oid).collect();
+        assert_eq!(nodes, vec![2]);
+    }
+}
@@ -0,0 +1,188 @@
+//! Excluding objects based on the metadata of their latest edit
+//!
+//! Players might edit osm to create spawns close to them.
+//! A [MetadataFilter] excludes recently edited objects before their features are parsed.
+//!
+//! libosmium only provides the metadata of an object's latest edit.
+//! A way's metadata doesn't change when only its nodes are moved
+//! and an area built from a multipolygon relation uses the relation's metadata.
+//! Files without metadata report a timestamp of `0`, which is never excluded.
+//! libosmium's bindings (0.3) don't expose an object's changeset, so it can't be filtered by.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libosmium::OSMObject;
+use serde::{Deserialize, Serialize};
+
+use crate::formats::Kind;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// Metadata of an object's latest edit
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub version: u32,
+
+    /// Seconds since the unix epoch
+    pub timestamp: u32,
+}
+
+impl From<&OSMObject> for Metadata {
+    fn from(object: &OSMObject) -> Self {
+        Metadata {
+            version: object.version(),
+            timestamp: object.timestamp(),
+        }
+    }
+}
+
+/// Rules for excluding objects, an object is excluded if any rule applies
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct MetadataFilter {
+    /// Exclude objects created or modified after this time in seconds since the unix epoch
+    pub cutoff: Option<u32>,
+
+    /// Exclude objects whose latest edit is younger than this number of days
+    pub quarantine_days: Option<u32>,
+
+    /// Time the quarantine is measured from in seconds since the unix epoch
+    ///
+    /// Defaults to the time the generation starts, set it to get reproducible output.
+    pub now: Option<u32>,
+}
+
+/// The rule which excluded an object
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    Cutoff,
+    Quarantine,
+}
+
+/// An object excluded by a [MetadataFilter]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Excluded {
+    pub kind: Kind,
+
+    /// The object's id, libosmium's derived id for areas
+    pub id: i64,
+
+    pub metadata: Metadata,
+    pub reason: Reason,
+}
+
+impl MetadataFilter {
+    /// Set `now` to the current time unless it is set already
+    pub fn with_now(mut self) -> Self {
+        if self.now.is_none() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs());
+            self.now = Some(now.min(u32::MAX as u64) as u32);
+        }
+        self
+    }
+
+    /// Get the first rule excluding an object
+    ///
+    /// The quarantine is only checked if `now` is set.
+    pub fn check(&self, metadata: Metadata) -> Option<Reason> {
+        let timestamp = metadata.timestamp;
+        if timestamp > 0 && self.cutoff.is_some_and(|cutoff| timestamp > cutoff) {
+            return Some(Reason::Cutoff);
+        }
+        if let (Some(days), Some(now)) = (self.quarantine_days, self.now) {
+            let start = now.saturating_sub(days.saturating_mul(SECONDS_PER_DAY));
+            if timestamp > 0 && timestamp > start {
+                return Some(Reason::Quarantine);
+            }
+        }
+        None
+    }
+}
+
+/// Parse a date given as `YYYY-MM-DD` into the seconds since the unix epoch at its start in UTC
+pub fn parse_date(date: &str) -> Option<u32> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+
+    // Days since the epoch using Howard Hinnant's `days_from_civil`
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    u32::try_from(days * SECONDS_PER_DAY as i64).ok()
+}
+
+/// Parse a date given as `YYYY-MM-DD` into the seconds since the unix epoch at its last second in UTC
+///
+/// Use this for a [cutoff](MetadataFilter::cutoff) to keep the objects edited during the date.
+pub fn parse_date_end(date: &str) -> Option<u32> {
+    parse_date(date)?.checked_add(SECONDS_PER_DAY - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::metadata::{parse_date, parse_date_end, Metadata, MetadataFilter, Reason};
+
+    fn edited(timestamp: u32) -> Metadata {
+        Metadata {
+            version: 1,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-03-01"), Some(951868800));
+        assert_eq!(parse_date("2024-02-29"), Some(1709164800));
+        assert_eq!(parse_date("1969-12-31"), None);
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-02-30"), None);
+
+        assert_eq!(parse_date_end("1970-01-01"), Some(86399));
+        assert_eq!(parse_date_end("2024-02-30"), None);
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("2000-02-29"), Some(951782400));
+        assert_eq!(parse_date("2100-02-29"), None);
+        assert_eq!(parse_date("2024-04-31"), None);
+        assert_eq!(parse_date("2024-01"), None);
+    }
+
+    #[test]
+    fn rules() {
+        let day = 24 * 60 * 60;
+        let filter = MetadataFilter {
+            cutoff: Some(100 * day),
+            quarantine_days: Some(7),
+            now: Some(105 * day),
+        };
+        assert_eq!(filter.check(edited(90 * day)), None);
+        assert_eq!(filter.check(edited(0)), None);
+        assert_eq!(filter.check(edited(100 * day + 1)), Some(Reason::Cutoff));
+        assert_eq!(filter.check(edited(99 * day)), Some(Reason::Quarantine));
+
+        let filter = MetadataFilter {
+            quarantine_days: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(filter.check(edited(u32::MAX)), None);
+        assert!(filter.with_now().check(edited(u32::MAX)).is_some());
+    }
+}
@@ -0,0 +1,297 @@
+//! Progress reporting and cancellation for long running generations
+//!
+//! A [Monitor] is shared by all threads taking part in a generation.
+//! They update its counters, check its [CancellationToken] and let it notify an [Observer].
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::formats::Tile;
+use crate::metadata::Excluded;
+
+/// Receives progress updates during a generation
+///
+/// It is called from the reader and the worker threads.
+pub trait Observer: Send + Sync {
+    /// Called periodically with the current progress
+    fn progress(&self, progress: &Progress);
+
+    /// Called once for every tile after the generation finished
+    fn tile_finished(&self, _index: usize, _items: usize) {}
+
+    /// Called for every object excluded by the [MetadataFilter](crate::metadata::MetadataFilter)
+    fn excluded(&self, _excluded: &Excluded) {}
+}
+
+/// A snapshot of the counters
+#[derive(Default, Copy, Clone, Debug)]
+pub struct Progress {
+    /// Number of areas processed by the generators
+    pub areas: u64,
+
+    /// Number of nodes processed by the generators
+    pub nodes: u64,
+
+    /// Number of ways processed by the generators
+    pub ways: u64,
+
+    /// Number of bytes of osm items read from the input
+    pub bytes: u64,
+
+    /// Number of areas, nodes and ways rejected by the bounding box or their negative id before being processed
+    pub skipped: u64,
+
+    /// Number of areas, nodes and ways excluded by their metadata
+    pub excluded: u64,
+
+    /// Number of items in the generated tiles
+    ///
+    /// The items are counted once the workers finished and their bands' tiles are merged.
+    pub items: u64,
+
+    /// Time since the monitor was created
+    pub elapsed: Duration,
+}
+
+/// Cheaply clonable flag to request a generation to stop
+///
+/// libosmium can't be interrupted while reading a file.
+/// Once cancelled, the remaining input is only read but not processed anymore.
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken(Arc<AtomicBool>);
+impl CancellationToken {
+    /// Create a new token which is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request the generation to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Check whether the generation should stop
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counters, cancellation token and observer shared by all threads of a generation
+#[derive(Clone)]
+pub struct Monitor {
+    shared: Arc<Shared>,
+    observer: Option<Arc<dyn Observer>>,
+    token: CancellationToken,
+}
+
+struct Shared {
+    areas: AtomicU64,
+    nodes: AtomicU64,
+    ways: AtomicU64,
+    bytes: AtomicU64,
+    skipped: AtomicU64,
+    excluded: AtomicU64,
+    items: AtomicU64,
+
+    start: Instant,
+
+    /// Minimum time between two calls to the observer
+    interval: Duration,
+
+    /// Time of the last call to the observer in milliseconds since `start`
+    last_report: AtomicU64,
+}
+
+impl Monitor {
+    /// Create a monitor notifying an observer at most once per `interval`
+    pub fn new(
+        observer: Option<Arc<dyn Observer>>,
+        token: CancellationToken,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                areas: AtomicU64::new(0),
+                nodes: AtomicU64::new(0),
+                ways: AtomicU64::new(0),
+                bytes: AtomicU64::new(0),
+                skipped: AtomicU64::new(0),
+                excluded: AtomicU64::new(0),
+                items: AtomicU64::new(0),
+                start: Instant::now(),
+                interval,
+                last_report: AtomicU64::new(0),
+            }),
+            observer,
+            token,
+        }
+    }
+
+    /// Get the token used to cancel the generation
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Check whether the generation should stop
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Count a processed area
+    #[inline]
+    pub fn add_area(&self) {
+        self.shared.areas.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a processed node
+    #[inline]
+    pub fn add_node(&self) {
+        self.shared.nodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a processed way
+    #[inline]
+    pub fn add_way(&self) {
+        self.shared.ways.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count bytes read from the input
+    #[inline]
+    pub fn add_bytes(&self, bytes: u64) {
+        self.shared.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Count an item rejected by the bounding box
+    #[inline]
+    pub fn add_skipped(&self) {
+        self.shared.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an object excluded by its metadata and notify the observer
+    pub fn exclude(&self, excluded: &Excluded) {
+        self.shared.excluded.fetch_add(1, Ordering::Relaxed);
+        if let Some(observer) = self.observer.as_ref() {
+            observer.excluded(excluded);
+        }
+    }
+
+    /// Count the items of finished tiles
+    pub fn add_items<F>(&self, tiles: &[Tile<F>]) {
+        let items: usize = tiles
+            .iter()
+            .map(|tile| tile.areas.len() + tile.nodes.len() + tile.ways.len())
+            .sum();
+        self.shared.items.fetch_add(items as u64, Ordering::Relaxed);
+    }
+
+    /// Take a snapshot of the counters
+    pub fn progress(&self) -> Progress {
+        let shared = self.shared.as_ref();
+        Progress {
+            areas: shared.areas.load(Ordering::Relaxed),
+            nodes: shared.nodes.load(Ordering::Relaxed),
+            ways: shared.ways.load(Ordering::Relaxed),
+            bytes: shared.bytes.load(Ordering::Relaxed),
+            skipped: shared.skipped.load(Ordering::Relaxed),
+            excluded: shared.excluded.load(Ordering::Relaxed),
+            items: shared.items.load(Ordering::Relaxed),
+            elapsed: shared.start.elapsed(),
+        }
+    }
+
+    /// Notify the observer if the last notification is older than the interval
+    ///
+    /// When called from several threads at once, only one of them notifies the observer.
+    pub fn report(&self) {
+        let Some(observer) = self.observer.as_ref() else {
+            return;
+        };
+
+        let shared = self.shared.as_ref();
+        let now = shared.start.elapsed().as_millis() as u64;
+        let last = shared.last_report.load(Ordering::Relaxed);
+        if now < last + shared.interval.as_millis() as u64 {
+            return;
+        }
+        if shared
+            .last_report
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            observer.progress(&self.progress());
+        }
+    }
+
+    /// Notify the observer about the final progress and every tile
+    pub fn finish<F>(&self, tiles: &[Tile<F>]) {
+        let Some(observer) = self.observer.as_ref() else {
+            return;
+        };
+
+        observer.progress(&self.progress());
+        for (index, tile) in tiles.iter().enumerate() {
+            observer.tile_finished(index, tile.areas.len() + tile.nodes.len() + tile.ways.len());
+        }
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new(None, CancellationToken::new(), Duration::from_secs(1))
+    }
+}
+
+impl fmt::Debug for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Monitor")
+            .field("progress", &self.progress())
+            .field("observer", &self.observer.is_some())
+            .field("token", &self.token)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{CancellationToken, Monitor, Observer, Progress};
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<Progress>>);
+    impl Observer for Collect {
+        fn progress(&self, progress: &Progress) {
+            self.0.lock().unwrap().push(*progress);
+        }
+    }
+
+    #[test]
+    fn cancel_clone() {
+        let token = CancellationToken::new();
+        let monitor = Monitor::new(None, token.clone(), Duration::ZERO);
+        assert!(!monitor.clone().is_cancelled());
+        token.cancel();
+        assert!(monitor.clone().is_cancelled());
+    }
+
+    #[test]
+    fn report_interval() {
+        let observer = Arc::new(Collect::default());
+        let monitor = Monitor::new(
+            Some(observer.clone()),
+            CancellationToken::new(),
+            Duration::from_secs(3600),
+        );
+        monitor.clone().add_node();
+        monitor.add_way();
+        monitor.report(); // Too early
+        monitor.finish::<usize>(&[]);
+
+        let reports = observer.0.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!((reports[0].nodes, reports[0].ways), (1, 1));
+    }
+}
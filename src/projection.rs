@@ -2,6 +2,7 @@ use std::f64::consts::PI;
 
 use libosmium::{Location, Node, NodeRef};
 use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
 
 pub trait GetLocation {
     fn get_location(&self) -> Option<Location>;
@@ -35,27 +36,58 @@ pub trait Projection: Copy + Send + 'static {
         Vector2::new(x, y)
     }
 
+    /// Reverse `project_nalgebra` i.e. convert a point in the map's coordinates into `(lon, lat)` degrees
+    fn unproject_nalgebra(&self, point: Vector2<f64>) -> Vector2<f64> {
+        let (lambda, phi) = self._unproject(point.x, point.y);
+        Vector2::new(lambda.to_degrees(), phi.to_degrees())
+    }
+
+    /// Name identifying the projection in metadata
+    fn name(&self) -> &'static str;
+
     fn _project(&self, lambda: f64, phi: f64) -> (f64, f64);
+
+    fn _unproject(&self, x: f64, y: f64) -> (f64, f64);
 }
 
-#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
 pub struct Simple;
 impl Projection for Simple {
+    fn name(&self) -> &'static str {
+        "simple"
+    }
+
     #[inline]
     fn _project(&self, lambda: f64, phi: f64) -> (f64, f64) {
         (lambda, phi)
     }
+
+    #[inline]
+    fn _unproject(&self, x: f64, y: f64) -> (f64, f64) {
+        (x, y)
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
 pub struct WebMercator;
 impl Projection for WebMercator {
+    fn name(&self) -> &'static str {
+        "web_mercator"
+    }
+
     #[inline]
     fn _project(&self, lambda: f64, phi: f64) -> (f64, f64) {
         let x = (lambda + PI) / (2.0 * PI);
         let y = (PI - (PI / 4.0 + phi / 2.0).tan().ln()) / (2.0 * PI);
         (x, y.clamp(0.0, 1.0))
     }
+
+    #[inline]
+    fn _unproject(&self, x: f64, y: f64) -> (f64, f64) {
+        let lambda = x * 2.0 * PI - PI;
+        let phi = 2.0 * (PI - 2.0 * PI * y).exp().atan() - PI / 2.0;
+        (lambda, phi)
+    }
 }
 
 #[cfg(test)]
@@ -85,4 +117,17 @@ mod test {
             Vector2::new(0.5, 0.0)
         );
     }
+
+    #[test]
+    fn web_mercator_inverse() {
+        let p = WebMercator;
+        for point in [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(13.37, 52.52),
+            Vector2::new(-122.42, -37.77),
+        ] {
+            let inverse = p.unproject_nalgebra(p.project_nalgebra(point));
+            assert!((inverse - point).amax() < 1e-9);
+        }
+    }
 }
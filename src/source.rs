@@ -0,0 +1,246 @@
+//! Read the replication state from a PBF file's header
+//!
+//! libosmium doesn't expose the header block, so its first blob is decoded here.
+//! See the [PBF format](https://wiki.openstreetmap.org/wiki/PBF_Format) for details.
+
+use std::io::Read;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Maximum size of a blob's header allowed by the format
+const MAX_BLOB_HEADER_SIZE: usize = 64 << 10;
+
+/// Maximum size of a blob allowed by the format
+const MAX_BLOB_SIZE: usize = 32 << 20;
+
+/// Information about the input file from its header
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Source {
+    /// Seconds since the unix epoch of the replication state the file is based on
+    pub timestamp: Option<i64>,
+
+    /// Sequence number of the replication state
+    pub replication_sequence: Option<i64>,
+
+    /// Base url of the replication server
+    pub replication_url: Option<String>,
+}
+
+impl Source {
+    /// Read the header of a PBF file
+    ///
+    /// Missing replication fields are left as `None`.
+    /// Other formats like `.osm` or `.o5m` don't have such a header and produce an error,
+    /// callers should treat it as missing information rather than fail.
+    pub fn from_pbf(path: &str) -> Result<Self, Error> {
+        let mut file = std::fs::File::open(path)?;
+
+        let mut length = [0; 4];
+        file.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_BLOB_HEADER_SIZE {
+            return Err(Error::Pbf(format!(
+                "the first blob's header is {length} bytes long"
+            )));
+        }
+        let mut blob_header = vec![0; length];
+        file.read_exact(&mut blob_header)?;
+
+        let mut kind = None;
+        let mut data_size = None;
+        for field in Fields(&blob_header) {
+            match field? {
+                (1, Value::Bytes(bytes)) => kind = Some(bytes),
+                (3, Value::Varint(size)) => data_size = Some(size as usize),
+                _ => {}
+            }
+        }
+        if kind != Some(b"OSMHeader") {
+            return Err(Error::Pbf("the first blob isn't a header".to_string()));
+        }
+        let data_size = data_size.unwrap_or(0);
+        if data_size > MAX_BLOB_SIZE {
+            return Err(Error::Pbf(format!(
+                "the header blob is {data_size} bytes long"
+            )));
+        }
+        let mut blob = vec![0; data_size];
+        file.read_exact(&mut blob)?;
+
+        let Some(header) = decode_blob(&blob)? else {
+            return Ok(Source::default());
+        };
+        let mut source = Source::default();
+        for field in Fields(&header) {
+            match field? {
+                (32, Value::Varint(timestamp)) => source.timestamp = Some(timestamp as i64),
+                (33, Value::Varint(sequence)) => {
+                    source.replication_sequence = Some(sequence as i64)
+                }
+                (34, Value::Bytes(url)) => {
+                    source.replication_url = Some(String::from_utf8_lossy(url).into_owned())
+                }
+                _ => {}
+            }
+        }
+        Ok(source)
+    }
+}
+
+/// Get a blob's uncompressed data, `None` if its compression isn't supported
+fn decode_blob(blob: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let mut raw_size = 0;
+    for field in Fields(blob) {
+        match field? {
+            (1, Value::Bytes(raw)) => return Ok(Some(raw.to_vec())),
+            (2, Value::Varint(size)) => raw_size = size as usize,
+            #[cfg(feature = "compression")]
+            (3, Value::Bytes(zlib)) => {
+                // The sizes are read from the file, so they aren't trusted
+                let mut data = Vec::with_capacity(raw_size.min(MAX_BLOB_SIZE));
+                flate2::read::ZlibDecoder::new(zlib)
+                    .take(MAX_BLOB_SIZE as u64 + 1)
+                    .read_to_end(&mut data)?;
+                if data.len() > MAX_BLOB_SIZE {
+                    return Err(Error::Pbf(format!(
+                        "the header blob is more than {MAX_BLOB_SIZE} bytes long uncompressed"
+                    )));
+                }
+                return Ok(Some(data));
+            }
+            _ => {}
+        }
+    }
+    warn!("The PBF header's compression isn't supported, its replication state is ignored");
+    Ok(None)
+}
+
+/// A protobuf field's value
+enum Value<'b> {
+    Varint(u64),
+    Bytes(&'b [u8]),
+    Fixed,
+}
+
+/// Iterator over the fields of a protobuf message
+struct Fields<'b>(&'b [u8]);
+impl<'b> Fields<'b> {
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.0.split_first().ok_or_else(truncated)?;
+            self.0 = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(truncated())
+    }
+
+    fn skip(&mut self, length: usize) -> Result<&'b [u8], Error> {
+        if self.0.len() < length {
+            return Err(truncated());
+        }
+        let (skipped, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(skipped)
+    }
+}
+impl<'b> Iterator for Fields<'b> {
+    type Item = Result<(u64, Value<'b>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = (|| {
+            let key = self.varint()?;
+            let value = match key & 0x7 {
+                0 => Value::Varint(self.varint()?),
+                1 => {
+                    self.skip(8)?;
+                    Value::Fixed
+                }
+                2 => {
+                    let length = self.varint()? as usize;
+                    Value::Bytes(self.skip(length)?)
+                }
+                5 => {
+                    self.skip(4)?;
+                    Value::Fixed
+                }
+                wire_type => {
+                    return Err(Error::Pbf(format!("unknown wire type {wire_type}")));
+                }
+            };
+            Ok((key >> 3, value))
+        })();
+        if field.is_err() {
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+fn truncated() -> Error {
+    Error::Pbf("the header is truncated".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use crate::source::Source;
+
+    #[test]
+    fn read_header() {
+        // HeaderBlock with required feature, timestamp, sequence and url
+        let mut header = vec![0x22, 4];
+        header.extend_from_slice(b"Test");
+        header.extend_from_slice(&[0x80, 0x02, 0xe8, 0x07]); // 32: 1000
+        header.extend_from_slice(&[0x88, 0x02, 0x2a]); // 33: 42
+        header.extend_from_slice(&[0x92, 0x02, 3]); // 34: "url"
+        header.extend_from_slice(b"url");
+
+        // Uncompressed blob
+        let mut blob = vec![0x0a, header.len() as u8];
+        blob.extend_from_slice(&header);
+
+        let mut blob_header = vec![0x0a, 9];
+        blob_header.extend_from_slice(b"OSMHeader");
+        blob_header.extend_from_slice(&[0x18, blob.len() as u8]);
+
+        let path = std::env::temp_dir().join(format!("rustymon_header_{}.pbf", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(&(blob_header.len() as u32).to_be_bytes())
+            .unwrap();
+        file.write_all(&blob_header).unwrap();
+        file.write_all(&blob).unwrap();
+        drop(file);
+
+        let source = Source::from_pbf(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            source.unwrap(),
+            Source {
+                timestamp: Some(1000),
+                replication_sequence: Some(42),
+                replication_url: Some("url".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn reject_xml() {
+        let path = std::env::temp_dir().join(format!("rustymon_header_{}.osm", std::process::id()));
+        std::fs::write(&path, "<?xml version='1.0' encoding='UTF-8'?>\n<osm/>").unwrap();
+
+        let source = Source::from_pbf(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(source.is_err());
+    }
+}
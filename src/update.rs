@@ -0,0 +1,542 @@
+//! Incremental updates of a previous generation using an osm change file (`.osc`)
+//!
+//! A tile is affected by the changes if it contained a changed object before or contains one now.
+//! The objects' previous tiles are found by their oids in the previous [World],
+//! their current tiles by reading the updated PBF file.
+//! Only the affected tiles are regenerated while all others are kept as they are.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io::Read;
+
+use libosmium::handler::Handler;
+use libosmium::{Area, Way};
+use log::info;
+
+use crate::error::Error;
+use crate::features::FeatureParser;
+use crate::geometry::{BBox, Point};
+use crate::projection::Projection;
+use crate::world::World;
+use crate::{parse, AreaOptions, Config};
+
+/// The ids of the objects an osm change file creates, modifies or deletes
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Changes {
+    pub nodes: HashSet<i64>,
+    pub ways: HashSet<i64>,
+    pub relations: HashSet<i64>,
+
+    /// The new locations of created and modified nodes in degrees as `(lon, lat)`
+    pub locations: Vec<Point>,
+}
+
+impl Changes {
+    /// Read an osm change file, which may be gzipped if its name ends with `.gz`
+    pub fn from_osc(path: &str) -> Result<Self, Error> {
+        let file = std::fs::File::open(path)?;
+        let mut osc = String::new();
+        if path.ends_with(".gz") {
+            #[cfg(feature = "compression")]
+            flate2::read::GzDecoder::new(file).read_to_string(&mut osc)?;
+            #[cfg(not(feature = "compression"))]
+            return Err(Error::ChangeFile(
+                "reading gzipped files requires the 'compression' feature".to_string(),
+            ));
+        } else {
+            std::io::BufReader::new(file).read_to_string(&mut osc)?;
+        }
+        Self::parse(&osc)
+    }
+
+    /// Parse the xml of an osm change file
+    ///
+    /// Only the objects' ids and the nodes' locations are read, everything else is ignored.
+    /// Comments, CDATA sections and processing instructions are skipped.
+    pub fn parse(osc: &str) -> Result<Self, Error> {
+        let mut changes = Changes::default();
+        let mut rest = osc;
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start + 1..];
+
+            // Markup whose content may look like elements
+            let skipped = [("!--", "-->"), ("![CDATA[", "]]>"), ("?", "?>")]
+                .into_iter()
+                .find(|(open, _)| rest.starts_with(open));
+            if let Some((open, close)) = skipped {
+                let end = rest
+                    .find(close)
+                    .ok_or_else(|| Error::ChangeFile(format!("<{open} isn't closed by {close}")))?;
+                rest = &rest[end + close.len()..];
+                continue;
+            }
+
+            let (element, next) = rest.split_at(element_end(rest));
+            rest = next;
+            let name_end = element
+                .find(|c: char| c.is_whitespace() || c == '/')
+                .unwrap_or(element.len());
+            let (name, attributes) = element.split_at(name_end);
+
+            let ids = match name {
+                "node" => &mut changes.nodes,
+                "way" => &mut changes.ways,
+                "relation" => &mut changes.relations,
+                _ => continue,
+            };
+            let id = attribute(attributes, "id")
+                .and_then(|id| id.parse().ok())
+                .ok_or_else(|| Error::ChangeFile(format!("<{element}> has no valid id")))?;
+            ids.insert(id);
+
+            if name == "node" {
+                let lon = attribute(attributes, "lon").and_then(|lon| lon.parse().ok());
+                let lat = attribute(attributes, "lat").and_then(|lat| lat.parse().ok());
+                if let (Some(lon), Some(lat)) = (lon, lat) {
+                    changes.locations.push(Point::new(lon, lat));
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.ways.is_empty() && self.relations.is_empty()
+    }
+}
+
+/// Get the length of an element's tag up to its closing `>`, which may be quoted in an attribute
+fn element_end(element: &str) -> usize {
+    let mut quote = None;
+    for (index, c) in element.char_indices() {
+        match (quote, c) {
+            (None, '>') => return index,
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            _ => {}
+        }
+    }
+    element.len()
+}
+
+/// Get an xml attribute's value
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    while let Some(position) = rest.find(name) {
+        let preceded_by_space = rest[..position].ends_with(char::is_whitespace);
+        rest = &rest[position + name.len()..];
+        if !preceded_by_space {
+            continue;
+        }
+        let Some(value) = rest.trim_start().strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let quote = value.chars().next()?;
+        if quote == '"' || quote == '\'' {
+            let value = &value[1..];
+            return value.find(quote).map(|end| &value[..end]);
+        }
+    }
+    None
+}
+
+/// Objects found to be changed while reading the updated PBF file
+///
+/// These include ways and areas which aren't part of the change file themselves but whose nodes are.
+#[derive(Clone, Debug, Default)]
+pub struct Touched {
+    /// Ids of the changed ways
+    pub ways: HashSet<i64>,
+
+    /// libosmium's ids of the changed areas, i.e. the oids used in the tiles
+    pub areas: HashSet<i64>,
+
+    /// The changed objects' current bounding boxes in the map's coordinates
+    pub boxes: Vec<BBox>,
+}
+
+/// Get the indexes of the tiles affected by the changes
+pub fn affected_tiles<F, P: Projection>(
+    world: &World<F, P>,
+    changes: &Changes,
+    touched: &Touched,
+) -> Vec<usize> {
+    let mut affected = BTreeSet::new();
+
+    // Areas and exclusion zones both use libosmium's area ids
+    let area_changed = |oid: usize| {
+        let id = oid as i64;
+        let changed = if id % 2 == 0 {
+            changes.ways.contains(&(id / 2)) || touched.ways.contains(&(id / 2))
+        } else {
+            changes.relations.contains(&(id / 2))
+        };
+        changed || touched.areas.contains(&id)
+    };
+
+    // Tiles the changed objects were in before
+    for (index, tile) in world.tiles.iter().enumerate() {
+        let changed = tile
+            .nodes
+            .iter()
+            .any(|node| changes.nodes.contains(&(node.oid as i64)))
+            || tile.ways.iter().any(|way| {
+                let id = way.oid as i64;
+                changes.ways.contains(&id) || touched.ways.contains(&id)
+            })
+            || tile.areas.iter().any(|area| area_changed(area.oid))
+            || tile
+                .exclusions
+                .zones
+                .iter()
+                .any(|zone| area_changed(zone.oid));
+        if changed {
+            affected.insert(index);
+        }
+    }
+
+    // Tiles the changed objects are in now
+    for location in changes.locations.iter() {
+        let point = world.projection.project_nalgebra(*location);
+        if let Some((x, y)) = world.position_of(point) {
+            affected.insert(y * world.cols + x);
+        }
+    }
+    for bbox in touched.boxes.iter() {
+        affected.extend(
+            world
+                .range_of(bbox.min, bbox.max)
+                .map(|((x, y), _)| y * world.cols + x),
+        );
+    }
+
+    affected.into_iter().collect()
+}
+
+/// Read the updated PBF file to find the changed ways and areas and their current bounding boxes
+pub fn touched<P: Projection>(
+    file: &str,
+    projection: P,
+    changes: &Changes,
+    areas: Option<AreaOptions>,
+) -> Result<Touched, Error> {
+    let mut handler = TouchedHandler {
+        projection,
+        changes,
+        nodes: HashSet::new(),
+        touched: Touched::default(),
+    };
+    match areas {
+        Some(options) => handler.apply_with_areas(file, options.into()),
+        None => handler.apply_with_ways(file),
+    }
+    .map_err(|error| Error::Pbf(error.to_string_lossy().into_owned()))?;
+    Ok(handler.touched)
+}
+
+struct TouchedHandler<'c, P> {
+    projection: P,
+    changes: &'c Changes,
+
+    /// Nodes of the changed ways, to find the multipolygons using them
+    nodes: HashSet<i64>,
+
+    touched: Touched,
+}
+impl<P: Projection> TouchedHandler<'_, P> {
+    fn add_box(&mut self, points: impl Iterator<Item = Point>) {
+        let bbox = BBox::from_iter(points);
+        if bbox.min.x <= bbox.max.x {
+            self.touched.boxes.push(bbox);
+        }
+    }
+}
+impl<P: Projection> Handler for TouchedHandler<'_, P> {
+    fn area(&mut self, area: &Area) {
+        let id = area.id();
+        let mut changed = if id % 2 == 0 {
+            self.changes.ways.contains(&(id / 2)) || self.touched.ways.contains(&(id / 2))
+        } else {
+            self.changes.relations.contains(&(id / 2))
+        };
+        let rings = || {
+            area.outer_rings().flat_map(|outer| {
+                outer
+                    .iter()
+                    .chain(area.inner_rings(outer).flat_map(|inner| inner.iter()))
+            })
+        };
+        if !changed {
+            changed = rings()
+                .any(|node| self.changes.nodes.contains(&node.id) || self.nodes.contains(&node.id));
+        }
+        if changed {
+            self.touched.areas.insert(id);
+            let projection = self.projection;
+            self.add_box(rings().filter_map(|node| projection.project(node)));
+        }
+    }
+
+    fn way(&mut self, way: &Way) {
+        let nodes = way.nodes();
+        let changed = self.changes.ways.contains(&way.id())
+            || nodes
+                .iter()
+                .any(|node| self.changes.nodes.contains(&node.id));
+        if changed {
+            self.touched.ways.insert(way.id());
+            self.nodes.extend(nodes.iter().map(|node| node.id));
+            let projection = self.projection;
+            self.add_box(nodes.iter().filter_map(|node| projection.project(node)));
+        }
+    }
+}
+
+/// The result of an [update]
+pub struct Update<Feature, P> {
+    pub world: World<Feature, P>,
+
+    /// Indexes of the regenerated tiles
+    pub changed: Vec<usize>,
+}
+
+/// Regenerate the tiles of a previous generation which are affected by some changes
+///
+/// The config's file has to be the PBF file with the changes already applied
+/// and its grid has to match the previous generation's.
+///
+/// The file is read twice: first by [touched] to find the affected tiles,
+/// then by [parse] to regenerate them, which can only be restricted to them once they are known.
+/// So an update takes about as long to read as a full generation,
+/// but only the affected tiles are clipped and their features parsed.
+pub fn update<Visual, Prjctn: Projection>(
+    mut config: Config<Visual, Prjctn>,
+    mut previous: World<Visual::Feature, Prjctn>,
+    changes: &Changes,
+) -> Result<Update<Visual::Feature, Prjctn>, Error>
+where
+    Visual: FeatureParser + Send + Sync + 'static,
+    Visual::Feature: Default + Clone + PartialEq + Send + 'static,
+{
+    if (previous.cols, previous.rows) != (config.cols, config.rows) {
+        return Err(Error::InvalidConfig(format!(
+            "The previous generation has {}x{} tiles, but the config {}x{}",
+            previous.cols, previous.rows, config.cols, config.rows
+        )));
+    }
+    if changes.is_empty() {
+        return Ok(Update {
+            world: previous,
+            changed: Vec::new(),
+        });
+    }
+
+    let touched = touched(&config.file, config.projection, changes, config.areas)?;
+    let affected = affected_tiles(&previous, changes, &touched);
+    info!(
+        "{} of {} tiles are affected",
+        affected.len(),
+        previous.tiles.len()
+    );
+
+    config.only_tiles = Some(affected.clone());
+    let mut current = parse(config)?;
+    if (current.origin, current.step_size) != (previous.origin, previous.step_size) {
+        return Err(Error::InvalidConfig(format!(
+            "The previous generation's grid starts at ({}, {}) with tiles of size {}, but the config's at ({}, {}) with {}",
+            previous.origin.x,
+            previous.origin.y,
+            previous.step_size.x,
+            current.origin.x,
+            current.origin.y,
+            current.step_size.x
+        )));
+    }
+    for &index in affected.iter() {
+        std::mem::swap(&mut previous.tiles[index], &mut current.tiles[index]);
+    }
+    Ok(Update {
+        world: previous,
+        changed: affected,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::Vector2;
+
+    use crate::formats::Tile;
+    use crate::geometry::{BBox, Point};
+    use crate::projection::Simple;
+    use crate::update::{affected_tiles, touched, Changes, Touched};
+    use crate::world::World;
+    use crate::AreaOptions;
+
+    const OSC: &str = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osmChange version="0.6" generator="test">
+  <modify>
+    <node id="1" version="2" lat="0.5" lon="2.5"/>
+    <way id="10" version="3">
+      <nd ref="1"/>
+      <tag k="highway" v="path"/>
+    </way>
+  </modify>
+  <delete>
+    <node id="2" version="4"/>
+    <relation id='30' version='1'>
+      <member type="way" ref="10" role="outer"/>
+    </relation>
+  </delete>
+</osmChange>"#;
+
+    #[test]
+    fn parse_osc() {
+        let changes = Changes::parse(OSC).unwrap();
+        assert_eq!(changes.nodes, [1, 2].into());
+        assert_eq!(changes.ways, [10].into());
+        assert_eq!(changes.relations, [30].into());
+        assert_eq!(changes.locations, vec![Point::new(2.5, 0.5)]);
+
+        assert!(Changes::parse("<node lat='1'/>").is_err());
+    }
+
+    #[test]
+    fn parse_markup() {
+        let osc = r#"<?xml version='1.0'?>
+<osmChange>
+  <!-- <node id="1"/> -->
+  <modify>
+    <node id="2" lat="1" lon="2"><tag k="note" v="a > b"/></node>
+    <way id="3"/>
+  </modify>
+  <![CDATA[<relation id="5"/>]]>
+</osmChange>"#;
+        let changes = Changes::parse(osc).unwrap();
+        assert_eq!(changes.nodes, [2].into());
+        assert_eq!(changes.ways, [3].into());
+        assert!(changes.relations.is_empty());
+        assert_eq!(changes.locations, vec![Point::new(2.0, 1.0)]);
+
+        assert!(Changes::parse("<!-- <node id='1'/>").is_err());
+    }
+
+    /// A row of 4 tiles, each one radian wide
+    fn world() -> World<usize, Simple> {
+        let tiles = (0..4)
+            .map(|x| {
+                Tile::new(BBox {
+                    min: Point::new(x as f64, 0.0),
+                    max: Point::new(x as f64 + 1.0, 1.0),
+                })
+            })
+            .collect();
+        World {
+            origin: Point::new(0.0, 0.0),
+            step_size: Vector2::new(1.0, 1.0),
+            cols: 4,
+            rows: 1,
+            projection: Simple,
+            tiles,
+        }
+    }
+
+    #[test]
+    fn affected() {
+        let mut world = world();
+        world.tiles[0].add_node(Point::new(0.5, 0.5), 0, 2);
+        world.tiles[1].add_area(&[Point::new(1.5, 0.5)], 0, 61);
+        world.tiles[3].add_way(&[Point::new(3.5, 0.5)], 0, 11);
+
+        let changes = Changes::parse(OSC).unwrap();
+        let none = Touched::default();
+        // Deleted node, deleted relation's area and modified node's new location
+        let degrees = 2.5f64.to_degrees();
+        let changes = Changes {
+            locations: vec![Point::new(degrees, 0.5f64.to_degrees())],
+            ..changes
+        };
+        assert_eq!(affected_tiles(&world, &changes, &none), vec![0, 1, 2]);
+
+        // A way using a changed node and a box of a current geometry
+        let touched = Touched {
+            ways: [11].into(),
+            boxes: vec![BBox {
+                min: Point::new(2.2, 0.2),
+                max: Point::new(3.2, 0.4),
+            }],
+            ..Default::default()
+        };
+        let changes = Changes::default();
+        assert_eq!(affected_tiles(&world, &changes, &touched), vec![2, 3]);
+    }
+
+    #[test]
+    fn affected_by_zone() {
+        let mut world = world();
+        world.tiles[2]
+            .exclusions
+            .add_zone(&[Point::new(2.5, 0.5)], 5, 20);
+
+        // A zone's closed way was changed
+        let changes = Changes {
+            ways: [10].into(),
+            ..Default::default()
+        };
+        let none = Touched::default();
+        assert_eq!(affected_tiles(&world, &changes, &none), vec![2]);
+
+        // A zone whose nodes moved
+        let touched = Touched {
+            areas: [20].into(),
+            ..Default::default()
+        };
+        let changes = Changes::default();
+        assert_eq!(affected_tiles(&world, &changes, &touched), vec![2]);
+    }
+
+    #[test]
+    fn moved_hole() {
+        // A multipolygon whose hole's node 6 moved
+        let osm = r#"<?xml version='1.0' encoding='UTF-8'?>
+<osm version="0.6">
+  <node id="1" version="1" lat="0" lon="0"/>
+  <node id="2" version="1" lat="0" lon="4"/>
+  <node id="3" version="1" lat="4" lon="4"/>
+  <node id="4" version="1" lat="4" lon="0"/>
+  <node id="5" version="1" lat="1" lon="1"/>
+  <node id="6" version="2" lat="1" lon="3"/>
+  <node id="7" version="1" lat="3" lon="3"/>
+  <node id="8" version="1" lat="3" lon="1"/>
+  <way id="10" version="1">
+    <nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/><nd ref="1"/>
+  </way>
+  <way id="11" version="1">
+    <nd ref="5"/><nd ref="6"/><nd ref="7"/><nd ref="8"/><nd ref="5"/>
+  </way>
+  <relation id="30" version="1">
+    <member type="way" ref="10" role="outer"/>
+    <member type="way" ref="11" role="inner"/>
+    <tag k="type" v="multipolygon"/>
+    <tag k="leisure" v="park"/>
+  </relation>
+</osm>"#;
+        let path =
+            std::env::temp_dir().join(format!("rustymon_moved_hole_{}.osm", std::process::id()));
+        std::fs::write(&path, osm).unwrap();
+
+        let changes = Changes {
+            nodes: [6].into(),
+            ..Default::default()
+        };
+        let touched = touched(
+            path.to_str().unwrap(),
+            Simple,
+            &changes,
+            Some(AreaOptions::default()),
+        );
+        std::fs::remove_file(&path).unwrap();
+        let touched = touched.unwrap();
+        assert!(touched.ways.contains(&11));
+        assert!(touched.areas.contains(&61));
+    }
+}
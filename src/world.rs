@@ -0,0 +1,228 @@
+//! The generated tiles together with the grid they were clipped into
+
+use std::ops::Range;
+
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::formats::Tile;
+use crate::geometry::Point;
+use crate::projection::Projection;
+
+/// A grid of tiles covering a part of the map
+///
+/// The tiles are stored in row-major order,
+/// i.e. the tile in column `x` and row `y` is at `tiles[y * cols + x]`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct World<Feature, P> {
+    /// The "min" corner of the grid in the map's coordinates
+    pub origin: Point,
+
+    /// A tile's size in the map's coordinates
+    pub step_size: Vector2<f64>,
+
+    pub cols: usize,
+    pub rows: usize,
+
+    /// Projection from lon/lat into the map's coordinates
+    pub projection: P,
+
+    pub tiles: Vec<Tile<Feature>>,
+}
+
+impl<Feature, P> World<Feature, P> {
+    /// Check whether the grid matches its tiles
+    ///
+    /// The lookups index the tiles by their column and row,
+    /// so a deserialized world should be checked before using them.
+    pub fn check(&self) -> Result<(), Error> {
+        check_grid(self.cols, self.rows, self.step_size, self.tiles.len())
+    }
+}
+
+/// Check whether a grid of `cols` × `rows` tiles of `step_size` consists of `len` tiles
+pub(crate) fn check_grid(
+    cols: usize,
+    rows: usize,
+    step_size: Vector2<f64>,
+    len: usize,
+) -> Result<(), Error> {
+    if cols.checked_mul(rows) != Some(len) {
+        return Err(Error::Format(format!(
+            "a grid of {cols}x{rows} tiles contains {len} tiles"
+        )));
+    }
+    if !(step_size.x > 0.0 && step_size.y > 0.0 && step_size.iter().all(|v| v.is_finite())) {
+        return Err(Error::Format(format!(
+            "the grid's step size {} x {} isn't positive",
+            step_size.x, step_size.y
+        )));
+    }
+    Ok(())
+}
+
+impl<Feature, P: Projection> World<Feature, P> {
+    /// Get the column and row of the tile containing a point in the map's coordinates
+    pub fn position_of(&self, point: Point) -> Option<(usize, usize)> {
+        let relative = (point - self.origin).component_div(&self.step_size);
+        let (x, y) = (relative.x.floor(), relative.y.floor());
+        if x < 0.0 || y < 0.0 || x >= self.cols as f64 || y >= self.rows as f64 {
+            None
+        } else {
+            Some((x as usize, y as usize))
+        }
+    }
+
+    /// Get the column and row of a tile's index
+    pub fn position(&self, index: usize) -> (usize, usize) {
+        (index % self.cols, index / self.cols)
+    }
+
+    /// Get the tile containing a lon/lat coordinate
+    pub fn tile_at(&self, lon: f64, lat: f64) -> Option<&Tile<Feature>> {
+        let point = self.projection.project_nalgebra(Vector2::new(lon, lat));
+        let (x, y) = self.position_of(point)?;
+        self.tile_by_index(x, y)
+    }
+
+    /// Get the tile in column `x` and row `y`
+    pub fn tile_by_index(&self, x: usize, y: usize) -> Option<&Tile<Feature>> {
+        if x < self.cols && y < self.rows {
+            self.tiles.get(y * self.cols + x)
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over the up to 8 tiles surrounding the tile in column `x` and row `y`
+    pub fn neighbors(
+        &self,
+        x: usize,
+        y: usize,
+    ) -> impl Iterator<Item = ((usize, usize), &Tile<Feature>)> + '_ {
+        let columns = x.saturating_sub(1)..(x + 2).min(self.cols);
+        let rows = y.saturating_sub(1)..(y + 2).min(self.rows);
+        self.iter_range(columns, rows)
+            .filter(move |(position, _)| *position != (x, y))
+    }
+
+    /// Iterate over the tiles intersecting a rectangle given by two lon/lat corners
+    pub fn range(
+        &self,
+        (lon1, lat1): (f64, f64),
+        (lon2, lat2): (f64, f64),
+    ) -> impl Iterator<Item = ((usize, usize), &Tile<Feature>)> + '_ {
+        // The projection might flip an axis
+        let a = self.projection.project_nalgebra(Vector2::new(lon1, lat1));
+        let b = self.projection.project_nalgebra(Vector2::new(lon2, lat2));
+        self.range_of(a.inf(&b), a.sup(&b))
+    }
+
+    /// Iterate over the tiles intersecting a rectangle in the map's coordinates
+    pub fn range_of(
+        &self,
+        min: Point,
+        max: Point,
+    ) -> impl Iterator<Item = ((usize, usize), &Tile<Feature>)> + '_ {
+        let min = (min - self.origin).component_div(&self.step_size);
+        let max = (max - self.origin).component_div(&self.step_size);
+
+        let clamp = |value: f64, len: usize| value.clamp(0.0, len as f64) as usize;
+        let columns = clamp(min.x.floor(), self.cols)..clamp(max.x.floor() + 1.0, self.cols);
+        let rows = clamp(min.y.floor(), self.rows)..clamp(max.y.floor() + 1.0, self.rows);
+        self.iter_range(columns, rows)
+    }
+
+    fn iter_range(
+        &self,
+        columns: Range<usize>,
+        rows: Range<usize>,
+    ) -> impl Iterator<Item = ((usize, usize), &Tile<Feature>)> + '_ {
+        rows.flat_map(move |y| {
+            columns
+                .clone()
+                .map(move |x| ((x, y), &self.tiles[y * self.cols + x]))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::Vector2;
+
+    use crate::formats::Tile;
+    use crate::geometry::{BBox, Point};
+    use crate::projection::{Projection, WebMercator};
+    use crate::world::World;
+
+    /// A 3x2 grid of tiles at zoom 2 starting at the map's origin
+    fn world() -> World<usize, WebMercator> {
+        let step_size = Vector2::new(0.25, 0.25);
+        let mut tiles = Vec::new();
+        for y in 0..2 {
+            for x in 0..3 {
+                let min = Point::new(x as f64 * 0.25, y as f64 * 0.25);
+                tiles.push(Tile::new(BBox {
+                    min,
+                    max: min + step_size,
+                }));
+            }
+        }
+        World {
+            origin: Point::new(0.0, 0.0),
+            step_size,
+            cols: 3,
+            rows: 2,
+            projection: WebMercator,
+            tiles,
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let world = world();
+        // Top left corner of the map
+        let tile = world.tile_at(-179.0, 84.0).unwrap();
+        assert_eq!(tile.min, Point::new(0.0, 0.0));
+
+        let tile = world.tile_by_index(2, 1).unwrap();
+        assert_eq!(tile.min, Point::new(0.5, 0.25));
+        assert!(world.tile_by_index(3, 0).is_none());
+
+        assert_eq!(
+            world.position_of(WebMercator.project_nalgebra(Vector2::new(-1.0, 1.0))),
+            Some((1, 1))
+        );
+        // Outside the grid
+        assert!(world.tile_at(10.0, 0.0).is_none());
+        assert_eq!(world.position(4), (1, 1));
+    }
+
+    #[test]
+    fn neighbors_and_range() {
+        let world = world();
+        let neighbors: Vec<_> = world.neighbors(0, 0).map(|(p, _)| p).collect();
+        assert_eq!(neighbors, vec![(1, 0), (0, 1), (1, 1)]);
+        assert_eq!(world.neighbors(1, 1).count(), 5);
+
+        let range: Vec<_> = world
+            .range((-170.0, 80.0), (-80.0, 30.0))
+            .map(|(p, _)| p)
+            .collect();
+        assert_eq!(range, vec![(0, 0), (1, 0), (0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn check() {
+        let mut world = world();
+        assert!(world.check().is_ok());
+
+        world.cols = 4;
+        assert!(world.check().is_err());
+
+        world.cols = 3;
+        world.step_size.y = 0.0;
+        assert!(world.check().is_err());
+    }
+}